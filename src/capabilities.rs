@@ -0,0 +1,76 @@
+use crate::screen_controller::ScreenControllerType;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// The compiled-in controllers and their supported actions, as printed by `--capabilities`.
+#[derive(Debug, Serialize)]
+struct Capabilities {
+    version: &'static str,
+    controllers: Vec<ControllerCapabilities>,
+}
+
+#[derive(Debug, Serialize)]
+struct ControllerCapabilities {
+    name: String,
+    actions: &'static [&'static str],
+}
+
+/// Describes the compiled-in controllers and their supported actions as JSON.
+pub(super) fn to_json() -> String {
+    let capabilities = Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        controllers: ScreenControllerType::value_variants()
+            .iter()
+            .filter_map(|&controller_type| {
+                Some(ControllerCapabilities {
+                    name: controller_type.to_possible_value()?.get_name().to_string(),
+                    actions: controller_type.actions(),
+                })
+            })
+            .collect(),
+    };
+
+    serde_json::to_string(&capabilities).expect("capabilities should serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lists_exactly_compiled_controllers() {
+        // Arrange
+        let controllers: Vec<_> = ScreenControllerType::compiled_in()
+            .iter()
+            .map(|name| format!("\"name\":\"{name}\""))
+            .collect();
+
+        // Act
+        let json = to_json();
+
+        // Assert
+        assert!(json.starts_with(&format!("{{\"version\":\"{}\",", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(json.matches("\"name\":").count(), controllers.len());
+        for controller in controllers {
+            assert!(json.contains(&controller), "{controller} not in {json}");
+        }
+    }
+
+    #[cfg(all(feature = "xrandr", feature = "sway", feature = "randr"))]
+    #[test]
+    fn json_with_default_features() {
+        assert_eq!(
+            to_json(),
+            format!(
+                concat!(
+                    "{{\"version\":\"{}\",\"controllers\":[",
+                    "{{\"name\":\"xrandr\",\"actions\":[\"mirror\",\"layout\",\"rotate\",\"gamma\",\"brightness\",\"scale-mirrored\",\"scale-filter\",\"primary\",\"auto-scale-mirror\",\"provider-output-source\",\"modeline\"]}},",
+                    "{{\"name\":\"sway\",\"actions\":[\"mirror\",\"layout\",\"rotate\",\"disabled-background\",\"scale-filter\",\"dpms-on-disable\",\"tearing\",\"max-render-time\"]}},",
+                    "{{\"name\":\"randr\",\"actions\":[\"mirror\",\"layout\",\"rotate\",\"gamma\",\"brightness\",\"hardware-mirror\",\"primary\",\"no-resize\",\"no-reposition\",\"modeline\"]}}",
+                    "]}}"
+                ),
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use crate::layout::Layout;
+use crate::screen::{Output, Position, Resolution, Screen};
+use crate::switch::SwitchPlan;
+use serde::{Deserialize, Serialize};
+
+/// Output formats of `--diff`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum DiffFormat {
+    /// One line per output, for humans.
+    #[default]
+    Text,
+    /// A single JSON object, for front-ends.
+    Json,
+}
+
+/// State of an output as far as it is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct OutputState {
+    pub(super) enabled: bool,
+    /// Resolution of the mode, `None` if unknown or left to the controller.
+    #[serde(
+        rename = "mode",
+        default,
+        serialize_with = "serialize_resolution",
+        deserialize_with = "deserialize_resolution"
+    )]
+    pub(super) resolution: Option<Resolution>,
+    #[serde(default)]
+    pub(super) position: Option<Position>,
+}
+
+impl OutputState {
+    const DISABLED: Self = Self {
+        enabled: false,
+        resolution: None,
+        position: None,
+    };
+
+    pub(super) fn current(output: &Output) -> Self {
+        Self {
+            enabled: output.enabled,
+            resolution: output.current_resolution,
+            position: output.current_position,
+        }
+    }
+}
+
+/// Serializes the resolution like `xrandr` names modes, e.g. `"1920x1080"`.
+fn serialize_resolution<S: serde::Serializer>(
+    resolution: &Option<Resolution>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match resolution {
+        Some(resolution) => serializer.collect_str(resolution),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_resolution<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Resolution>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|resolution| resolution.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+impl std::fmt::Display for OutputState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.enabled {
+            return write!(f, "disabled");
+        }
+        write!(f, "enabled")?;
+        if let Some(resolution) = self.resolution {
+            write!(f, " {resolution}")?;
+        }
+        if let Some(position) = self.position {
+            write!(f, " at {},{}", position.x, position.y)?;
+        }
+        Ok(())
+    }
+}
+
+/// Current and target state of an output.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(super) struct OutputDiff<'a> {
+    pub(super) name: &'a str,
+    pub(super) current: OutputState,
+    pub(super) target: OutputState,
+}
+
+/// Compares the current state of every output with the state carrying out the plan would leave it in.
+pub(super) fn build<'a>(
+    screen: &'a Screen,
+    switch_plan: &SwitchPlan,
+    resolution: Option<Resolution>,
+    layout: &Layout,
+) -> Vec<OutputDiff<'a>> {
+    screen
+        .outputs
+        .iter()
+        .map(|output| {
+            let current = OutputState::current(output);
+            let target = if switch_plan
+                .outputs_to_enable
+                .iter()
+                .any(|output_to_enable| std::ptr::eq(*output_to_enable, output))
+            {
+                OutputState {
+                    enabled: true,
+                    resolution,
+                    position: Some(layout.position(&output.name)),
+                }
+            } else if switch_plan
+                .outputs_to_disable
+                .iter()
+                .any(|output_to_disable| std::ptr::eq(*output_to_disable, output))
+            {
+                OutputState::DISABLED
+            } else {
+                current
+            };
+
+            OutputDiff {
+                name: &output.name,
+                current,
+                target,
+            }
+        })
+        .collect()
+}
+
+pub(super) fn format(diffs: &[OutputDiff], format: DiffFormat) -> String {
+    match format {
+        DiffFormat::Text => diffs
+            .iter()
+            .map(|diff| format!("{}: {} -> {}", diff.name, diff.current, diff.target))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        DiffFormat::Json => {
+            #[derive(Serialize)]
+            struct JsonDiff<'a> {
+                outputs: &'a [OutputDiff<'a>],
+            }
+
+            serde_json::to_string(&JsonDiff { outputs: diffs })
+                .expect("output diffs should serialize to JSON")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::Mode;
+
+    const TEST_RESOLUTION: Resolution = Resolution {
+        width: 1920,
+        height: 1080,
+    };
+
+    fn test_screen() -> Screen {
+        let output = |name: &str, enabled: bool| Output {
+            modes: vec![Mode {
+                resolution: TEST_RESOLUTION,
+                refresh_rate_millihz: 60000,
+                preferred: false,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
+            }],
+            current_resolution: enabled.then_some(TEST_RESOLUTION),
+            current_position: enabled.then(Position::default),
+            ..Output::test(name, true, enabled)
+        };
+
+        Screen {
+            outputs: vec![
+                output("eDP-1", true),
+                output("HDMI-1", false),
+                output("DP-1", false),
+            ],
+        }
+    }
+
+    #[test]
+    fn diff_marks_switched_outputs() {
+        // Arrange
+        let screen = test_screen();
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&screen.outputs[0]],
+            outputs_to_enable: vec![&screen.outputs[1]],
+        };
+
+        // Act
+        let diffs = build(
+            &screen,
+            &switch_plan,
+            Some(TEST_RESOLUTION),
+            &Layout::default(),
+        );
+
+        // Assert
+        let enabled = OutputState {
+            enabled: true,
+            resolution: Some(TEST_RESOLUTION),
+            position: Some(Position::default()),
+        };
+        assert_eq!(
+            diffs,
+            [
+                OutputDiff {
+                    name: "eDP-1",
+                    current: enabled,
+                    target: OutputState::DISABLED,
+                },
+                OutputDiff {
+                    name: "HDMI-1",
+                    current: OutputState::DISABLED,
+                    target: enabled,
+                },
+                OutputDiff {
+                    name: "DP-1",
+                    current: OutputState::DISABLED,
+                    target: OutputState::DISABLED,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_formats_as_text_and_json() {
+        // Arrange
+        let screen = test_screen();
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&screen.outputs[0]],
+            outputs_to_enable: vec![&screen.outputs[1]],
+        };
+        let diffs = build(&screen, &switch_plan, None, &Layout::default());
+
+        // Act
+        let text = format(&diffs, DiffFormat::Text);
+        let json = format(&diffs[..2], DiffFormat::Json);
+
+        // Assert
+        assert_eq!(
+            text,
+            "eDP-1: enabled 1920x1080 at 0,0 -> disabled\n\
+             HDMI-1: disabled -> enabled at 0,0\n\
+             DP-1: disabled -> disabled"
+        );
+        assert_eq!(
+            json,
+            concat!(
+                "{\"outputs\":[",
+                "{\"name\":\"eDP-1\",",
+                "\"current\":{\"enabled\":true,\"mode\":\"1920x1080\",\"position\":{\"x\":0,\"y\":0}},",
+                "\"target\":{\"enabled\":false,\"mode\":null,\"position\":null}},",
+                "{\"name\":\"HDMI-1\",",
+                "\"current\":{\"enabled\":false,\"mode\":null,\"position\":null},",
+                "\"target\":{\"enabled\":true,\"mode\":null,\"position\":{\"x\":0,\"y\":0}}}",
+                "]}"
+            )
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::screen_controller::SwitchError;
+
+/// How a run ended, reported to scripts as the exit code of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// The outputs were switched, or the requested information was printed.
+    Applied,
+    /// The outputs were already in the target state, or switching was skipped.
+    NoChange,
+    /// The arguments are invalid or the outputs and modes they select are unusable.
+    InvalidSelection,
+    /// The controller failed to switch the outputs.
+    ControllerFailed,
+    /// The controller cannot query the outputs in this environment, e.g. there is no X server.
+    NoController,
+}
+
+impl Outcome {
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            Outcome::Applied => 0,
+            Outcome::NoChange => 2,
+            Outcome::InvalidSelection => 3,
+            Outcome::ControllerFailed => 4,
+            Outcome::NoController => 5,
+        }
+    }
+}
+
+impl From<Outcome> for std::process::ExitCode {
+    fn from(outcome: Outcome) -> Self {
+        std::process::ExitCode::from(outcome.code())
+    }
+}
+
+/// Description of the exit codes for `--help`.
+pub(crate) const HELP: &str = "\
+Exit codes:
+  0  Outputs switched, or the requested information printed
+  2  Nothing changed: outputs already in the target state, or switching skipped
+  3  Invalid arguments, outputs or modes
+  4  The controller failed to switch the outputs
+  5  The controller is unavailable, e.g. no X server or sway running";
+
+/// Logs an error the controller returned, returning the outcome it maps to. Failures of the
+/// controller itself, e.g. its command exiting unsuccessfully, are reported as `failure`.
+pub(crate) fn report_switch_error(err: SwitchError, failure: Outcome) -> Outcome {
+    log::error!("{err}");
+    match err {
+        #[cfg(any(feature = "xrandr", feature = "sway"))]
+        SwitchError::BackendNotFound { .. } => Outcome::NoController,
+        #[cfg(any(feature = "xrandr", feature = "sway"))]
+        SwitchError::BackendNotStarted { .. } => Outcome::NoController,
+        #[cfg(any(feature = "xrandr", feature = "sway"))]
+        SwitchError::CommandFailed { .. } => failure,
+        #[cfg(feature = "sway")]
+        SwitchError::ParseOutputs { .. } => failure,
+        #[cfg(feature = "randr")]
+        SwitchError::X11 { .. } => failure,
+        #[cfg(feature = "xrandr")]
+        SwitchError::UnknownProvider { .. } => Outcome::InvalidSelection,
+        #[cfg(feature = "randr")]
+        SwitchError::ScreenTooLarge { .. } => Outcome::InvalidSelection,
+        #[cfg(feature = "randr")]
+        SwitchError::NoFreeCrtc { .. } => Outcome::InvalidSelection,
+        #[cfg(test)]
+        SwitchError::Fake => failure,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(any(feature = "xrandr", feature = "sway"))]
+    fn failed_command_is_reported_as_given_failure() {
+        // Arrange
+        use std::os::unix::process::ExitStatusExt;
+        let err = SwitchError::CommandFailed {
+            command: "\"xrandr\"".to_string(),
+            status: std::process::ExitStatus::from_raw(1 << 8),
+            stderr: "Can't open display".to_string(),
+        };
+
+        // Act
+        let outcome = report_switch_error(err, Outcome::ControllerFailed);
+
+        // Assert
+        assert_eq!(outcome, Outcome::ControllerFailed);
+    }
+
+    #[test]
+    #[cfg(any(feature = "xrandr", feature = "sway"))]
+    fn missing_backend_is_reported_as_no_controller() {
+        // Arrange
+        let err = SwitchError::BackendNotFound {
+            binary: "xrandr".to_string(),
+        };
+
+        // Act
+        let outcome = report_switch_error(err, Outcome::ControllerFailed);
+
+        // Assert
+        assert_eq!(outcome, Outcome::NoController);
+    }
+
+    #[test]
+    #[cfg(any(feature = "xrandr", feature = "sway"))]
+    fn unstartable_backend_is_reported_as_no_controller() {
+        // Arrange
+        let err = SwitchError::BackendNotStarted {
+            binary: "xrandr".to_string(),
+            reason: "Permission denied (os error 13)".to_string(),
+        };
+
+        // Act
+        let outcome = report_switch_error(err, Outcome::ControllerFailed);
+
+        // Assert
+        assert_eq!(outcome, Outcome::NoController);
+    }
+
+    #[test]
+    #[cfg(feature = "sway")]
+    fn unparsable_outputs_are_reported_as_given_failure() {
+        // Arrange
+        let err = SwitchError::ParseOutputs {
+            reason: "expected value at line 1 column 1".to_string(),
+        };
+
+        // Act
+        let outcome = report_switch_error(err, Outcome::NoController);
+
+        // Assert
+        assert_eq!(outcome, Outcome::NoController);
+    }
+
+    #[test]
+    #[cfg(feature = "xrandr")]
+    fn unknown_provider_is_reported_as_invalid_selection() {
+        // Arrange
+        let err = SwitchError::UnknownProvider {
+            name: "NVIDIA-1".to_string(),
+            known: vec!["NVIDIA-0".to_string(), "modesetting".to_string()],
+        };
+
+        // Act
+        let outcome = report_switch_error(err, Outcome::ControllerFailed);
+
+        // Assert
+        assert_eq!(outcome, Outcome::InvalidSelection);
+    }
+
+    #[test]
+    #[cfg(feature = "randr")]
+    fn too_large_screen_is_reported_as_invalid_selection() {
+        // Arrange
+        let err = SwitchError::ScreenTooLarge {
+            reason: "screen size 40000x1080 exceeds the maximum of 32767x32767".to_string(),
+        };
+
+        // Act
+        let outcome = report_switch_error(err, Outcome::ControllerFailed);
+
+        // Assert
+        assert_eq!(outcome, Outcome::InvalidSelection);
+    }
+
+    #[test]
+    #[cfg(feature = "randr")]
+    fn missing_crtc_is_reported_as_invalid_selection() {
+        // Arrange
+        let err = SwitchError::NoFreeCrtc {
+            output: "HDMI-1".to_string(),
+        };
+
+        // Act
+        let outcome = report_switch_error(err, Outcome::ControllerFailed);
+
+        // Assert
+        assert_eq!(outcome, Outcome::InvalidSelection);
+    }
+}
@@ -0,0 +1,655 @@
+use crate::screen::{Location, Output, Position, Resolution};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Largest coordinate an output can be placed at, as X11 positions outputs with 16-bit integers.
+const MAX_COORDINATE: i32 = i16::MAX as i32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    LeftOf,
+    RightOf,
+    Above,
+    Below,
+}
+
+/// Rotation of an output, counterclockwise like in xrandr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Rotation {
+    #[default]
+    Normal,
+    Left,
+}
+
+impl Rotation {
+    /// Whether the output is in portrait orientation, i.e. its width and height are swapped.
+    pub(crate) fn swaps_dimensions(self) -> bool {
+        matches!(self, Rotation::Left)
+    }
+}
+
+/// Common arrangements of outputs, expanded into relations and rotations by [`preset`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum LayoutPreset {
+    /// Rotate the external outputs to portrait and stack them top to bottom.
+    PortraitStack,
+}
+
+/// Requests `output` to be placed in `direction` of `anchor`, e.g. DP-2 right of HDMI-1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Relation {
+    pub(crate) output: String,
+    pub(crate) direction: Direction,
+    pub(crate) anchor: String,
+}
+
+/// Parses an `OUTPUT=ANCHOR` pair of output names.
+pub(crate) fn parse_output_pair(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((output, anchor)) if !output.is_empty() && !anchor.is_empty() => {
+            Ok((output.to_string(), anchor.to_string()))
+        }
+        _ => Err(format!("expected OUTPUT=ANCHOR, got {s:?}")),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Placement {
+    name: String,
+    position: Position,
+    relation: Option<(Direction, String)>,
+}
+
+/// Positions of outputs, ordered so that every output comes after its anchor.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Layout {
+    placements: Vec<Placement>,
+    rotations: HashMap<String, Rotation>,
+    mirror_anchor: Option<String>,
+    /// Whether the positions were given rather than resolved from relations, so that outputs
+    /// without a relation are placed at their position instead of mirroring each other.
+    fixed: bool,
+}
+
+impl Layout {
+    /// Layout placing each output at the given position, e.g. as saved by `--print-state`.
+    pub(crate) fn fixed(positions: impl IntoIterator<Item = (String, Position)>) -> Self {
+        Self {
+            placements: positions
+                .into_iter()
+                .map(|(name, position)| Placement {
+                    name,
+                    position,
+                    relation: None,
+                })
+                .collect(),
+            fixed: true,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the layout was built by [`Layout::fixed`].
+    #[cfg(feature = "xrandr")]
+    pub(crate) fn is_fixed(&self) -> bool {
+        self.fixed
+    }
+
+    pub(crate) fn with_rotations(mut self, rotations: HashMap<String, Rotation>) -> Self {
+        self.rotations = rotations;
+        self
+    }
+
+    /// Rotation of the output, outputs without one are not rotated.
+    pub(crate) fn rotation(&self, name: &str) -> Rotation {
+        self.rotations.get(name).copied().unwrap_or_default()
+    }
+
+    /// Sets the output the outputs not in the layout mirror, so that they are placed with it.
+    pub(crate) fn with_mirror_anchor(mut self, name: Option<&str>) -> Self {
+        self.mirror_anchor = name.map(str::to_string);
+        self
+    }
+
+    /// Position of the output. Outputs not in the layout are placed with the mirror anchor,
+    /// or at the origin if it is not in the layout either.
+    pub(crate) fn position(&self, name: &str) -> Position {
+        self.placement(name)
+            .or_else(|| self.placement(self.mirror_anchor.as_deref()?))
+            .map(|placement| placement.position)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn relation(&self, name: &str) -> Option<(Direction, &str)> {
+        self.placement(name)?
+            .relation
+            .as_ref()
+            .map(|(direction, anchor)| (*direction, anchor.as_str()))
+    }
+
+    /// Index of the output in the layout order, if the output is in the layout.
+    #[cfg(any(feature = "xrandr", test))]
+    pub(crate) fn order(&self, name: &str) -> Option<usize> {
+        self.placements
+            .iter()
+            .position(|placement| placement.name == name)
+    }
+
+    fn placement(&self, name: &str) -> Option<&Placement> {
+        self.placements
+            .iter()
+            .find(|placement| placement.name == name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LayoutError {
+    Cycle(Vec<String>),
+    MultipleAnchors(String),
+    OutOfRange(String, Position),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Cycle(names) => {
+                write!(
+                    f,
+                    "outputs are positioned in a cycle: {}",
+                    names.join(" -> ")
+                )
+            }
+            LayoutError::MultipleAnchors(name) => {
+                write!(
+                    f,
+                    "output {name} is positioned relative to more than one output"
+                )
+            }
+            LayoutError::OutOfRange(name, position) => {
+                write!(
+                    f,
+                    "output {name} would be placed at ({}, {}), beyond the largest coordinate {MAX_COORDINATE}",
+                    position.x, position.y
+                )
+            }
+        }
+    }
+}
+
+/// Relations and rotations making up the preset for the given outputs being enabled.
+pub(crate) fn preset(
+    preset: LayoutPreset,
+    outputs: &[&Output],
+) -> (Vec<Relation>, HashMap<String, Rotation>) {
+    match preset {
+        LayoutPreset::PortraitStack => {
+            let externals: Vec<_> = outputs
+                .iter()
+                .filter(|output| output.location == Location::External)
+                .map(|output| output.name.as_str())
+                .collect();
+
+            let relations = externals
+                .windows(2)
+                .map(|pair| Relation {
+                    output: pair[1].to_string(),
+                    direction: Direction::Below,
+                    anchor: pair[0].to_string(),
+                })
+                .collect();
+            let rotations = externals
+                .iter()
+                .map(|name| (name.to_string(), Rotation::Left))
+                .collect();
+
+            (relations, rotations)
+        }
+    }
+}
+
+/// Swaps the width and height of the outputs rotated to portrait.
+pub(crate) fn rotate_sizes(
+    sizes: &mut HashMap<&str, Resolution>,
+    rotations: &HashMap<String, Rotation>,
+) {
+    for (name, size) in sizes.iter_mut() {
+        if rotations
+            .get(*name)
+            .is_some_and(|rotation| rotation.swaps_dimensions())
+        {
+            *size = Resolution {
+                width: size.height,
+                height: size.width,
+            };
+        }
+    }
+}
+
+/// Sizes of the outputs once enabled: the given common resolution, if any,
+/// otherwise the largest mode of each output, which is what controllers usually pick.
+pub(crate) fn estimate_sizes<'a>(
+    outputs: &[&'a Output],
+    resolution: Option<Resolution>,
+) -> HashMap<&'a str, Resolution> {
+    outputs
+        .iter()
+        .filter_map(|output| {
+            let size = resolution.or_else(|| output.largest_resolution())?;
+            Some((output.name.as_str(), size))
+        })
+        .collect()
+}
+
+/// Computes pixel positions of the outputs from the relations between them,
+/// given the size of every output being enabled. Relations involving outputs
+/// of unknown size, i.e. not being enabled, are ignored.
+pub(crate) fn resolve(
+    relations: &[Relation],
+    sizes: &HashMap<&str, Resolution>,
+) -> Result<Layout, LayoutError> {
+    let relations: Vec<_> = relations
+        .iter()
+        .filter(|relation| {
+            let known = sizes.contains_key(relation.output.as_str())
+                && sizes.contains_key(relation.anchor.as_str());
+            if !known {
+                log::debug!("Ignoring {relation:?}, it involves an output not being enabled");
+            }
+            known
+        })
+        .collect();
+
+    let mut anchors: HashMap<&str, &Relation> = HashMap::new();
+    for relation in &relations {
+        if anchors.insert(&relation.output, relation).is_some() {
+            return Err(LayoutError::MultipleAnchors(relation.output.clone()));
+        }
+    }
+
+    let mut resolver = Resolver {
+        anchors: &anchors,
+        sizes,
+        in_progress: Vec::new(),
+        placements: Vec::new(),
+    };
+    for relation in &relations {
+        resolver.resolve(&relation.anchor)?;
+        resolver.resolve(&relation.output)?;
+    }
+    let mut placements = resolver.placements;
+
+    let min_x = placements.iter().map(|p| p.position.x).min().unwrap_or(0);
+    let min_y = placements.iter().map(|p| p.position.y).min().unwrap_or(0);
+    for placement in &mut placements {
+        placement.position.x -= min_x;
+        placement.position.y -= min_y;
+        if placement.position.x > MAX_COORDINATE || placement.position.y > MAX_COORDINATE {
+            return Err(LayoutError::OutOfRange(
+                placement.name.clone(),
+                placement.position,
+            ));
+        }
+    }
+
+    Ok(Layout {
+        placements,
+        ..Default::default()
+    })
+}
+
+struct Resolver<'a> {
+    anchors: &'a HashMap<&'a str, &'a Relation>,
+    sizes: &'a HashMap<&'a str, Resolution>,
+    in_progress: Vec<&'a str>,
+    placements: Vec<Placement>,
+}
+
+impl<'a> Resolver<'a> {
+    fn resolve(&mut self, name: &'a str) -> Result<Position, LayoutError> {
+        if let Some(placement) = self.placements.iter().find(|p| p.name == name) {
+            return Ok(placement.position);
+        }
+
+        if let Some(i) = self.in_progress.iter().position(|n| *n == name) {
+            let mut cycle: Vec<_> = self.in_progress[i..]
+                .iter()
+                .map(|n| n.to_string())
+                .collect();
+            cycle.push(name.to_string());
+            return Err(LayoutError::Cycle(cycle));
+        }
+
+        self.in_progress.push(name);
+
+        let (position, relation) = if let Some(relation) = self.anchors.get(name) {
+            let anchor_position = self.resolve(&relation.anchor)?;
+            let size = self.size(name);
+            let anchor_size = self.size(&relation.anchor);
+            let position = match relation.direction {
+                Direction::LeftOf => Position {
+                    x: anchor_position.x - size.width as i32,
+                    y: anchor_position.y,
+                },
+                Direction::RightOf => Position {
+                    x: anchor_position.x + anchor_size.width as i32,
+                    y: anchor_position.y,
+                },
+                Direction::Above => Position {
+                    x: anchor_position.x,
+                    y: anchor_position.y - size.height as i32,
+                },
+                Direction::Below => Position {
+                    x: anchor_position.x,
+                    y: anchor_position.y + anchor_size.height as i32,
+                },
+            };
+            (
+                position,
+                Some((relation.direction, relation.anchor.clone())),
+            )
+        } else {
+            (Position::default(), None)
+        };
+
+        self.in_progress.pop();
+        self.placements.push(Placement {
+            name: name.to_string(),
+            position,
+            relation,
+        });
+
+        Ok(position)
+    }
+
+    fn size(&self, name: &str) -> Resolution {
+        *self
+            .sizes
+            .get(name)
+            .expect("relations with unknown sizes are filtered out")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::Mode;
+
+    #[test]
+    fn portrait_stack_places_rotated_externals_top_to_bottom() {
+        // Arrange
+        let output = |name: &str| Output {
+            modes: vec![Mode {
+                resolution: Resolution {
+                    width: 1920,
+                    height: 1080,
+                },
+                refresh_rate_millihz: 60000,
+                preferred: false,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
+            }],
+            ..Output::test(name, true, false)
+        };
+        let hdmi = output("HDMI-1");
+        let dp = output("DP-1");
+        let outputs = [&hdmi, &dp];
+
+        // Act
+        let (relations, rotations) = preset(LayoutPreset::PortraitStack, &outputs);
+        let mut sizes = estimate_sizes(&outputs, None);
+        rotate_sizes(&mut sizes, &rotations);
+        let layout = resolve(&relations, &sizes)
+            .expect("layout should resolve")
+            .with_rotations(rotations);
+
+        // Assert
+        assert_eq!(layout.rotation("HDMI-1"), Rotation::Left);
+        assert_eq!(layout.rotation("DP-1"), Rotation::Left);
+        assert_eq!(layout.position("HDMI-1"), Position { x: 0, y: 0 });
+        assert_eq!(layout.position("DP-1"), Position { x: 0, y: 1920 });
+        assert_eq!(layout.relation("DP-1"), Some((Direction::Below, "HDMI-1")));
+    }
+
+    use maplit::hashmap;
+
+    fn relation(output: &str, direction: Direction, anchor: &str) -> Relation {
+        Relation {
+            output: output.to_string(),
+            direction,
+            anchor: anchor.to_string(),
+        }
+    }
+
+    fn three_output_sizes() -> HashMap<&'static str, Resolution> {
+        hashmap! {
+            "eDP-1" => Resolution { width: 1920, height: 1080 },
+            "HDMI-1" => Resolution { width: 2560, height: 1440 },
+            "DP-2" => Resolution { width: 1280, height: 1024 },
+        }
+    }
+
+    #[test]
+    fn test_estimate_sizes() {
+        // Arrange
+        let outputs = [
+            Output {
+                modes: vec![
+                    Mode {
+                        resolution: Resolution {
+                            width: 1280,
+                            height: 720,
+                        },
+                        refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
+                    },
+                    Mode {
+                        resolution: Resolution {
+                            width: 1920,
+                            height: 1080,
+                        },
+                        refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
+                    },
+                ],
+                ..Output::test("eDP-1", true, true)
+            },
+            Output::test("HDMI-1", false, false),
+        ];
+        let outputs: Vec<_> = outputs.iter().collect();
+        let resolution = Resolution {
+            width: 800,
+            height: 600,
+        };
+
+        // Act
+        let estimated_sizes = estimate_sizes(&outputs, None);
+        let common_sizes = estimate_sizes(&outputs, Some(resolution));
+
+        // Assert
+        assert_eq!(
+            estimated_sizes,
+            hashmap! { "eDP-1" => Resolution { width: 1920, height: 1080 } }
+        );
+        assert_eq!(
+            common_sizes,
+            hashmap! { "eDP-1" => resolution, "HDMI-1" => resolution }
+        );
+    }
+
+    #[test]
+    fn test_parse_output_pair() {
+        assert_eq!(
+            parse_output_pair("DP-2=HDMI-1"),
+            Ok(("DP-2".to_string(), "HDMI-1".to_string()))
+        );
+        assert!(parse_output_pair("DP-2").is_err());
+        assert!(parse_output_pair("=HDMI-1").is_err());
+        assert!(parse_output_pair("DP-2=").is_err());
+    }
+
+    #[test]
+    fn when_no_relations_resolve_returns_empty_layout() {
+        // Arrange
+        let sizes = three_output_sizes();
+
+        // Act
+        let layout = resolve(&[], &sizes);
+
+        // Assert
+        assert_eq!(layout, Ok(Layout::default()));
+        assert_eq!(layout.unwrap().position("eDP-1"), Position { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn resolve_places_outputs_right_of_and_below_anchor() {
+        // Arrange
+        let relations = [
+            relation("DP-2", Direction::RightOf, "HDMI-1"),
+            relation("eDP-1", Direction::Below, "HDMI-1"),
+        ];
+        let sizes = three_output_sizes();
+
+        // Act
+        let layout = resolve(&relations, &sizes).expect("layout should resolve");
+
+        // Assert
+        assert_eq!(layout.position("HDMI-1"), Position { x: 0, y: 0 });
+        assert_eq!(layout.position("DP-2"), Position { x: 2560, y: 0 });
+        assert_eq!(layout.position("eDP-1"), Position { x: 0, y: 1440 });
+        assert_eq!(layout.relation("HDMI-1"), None);
+        assert_eq!(
+            layout.relation("DP-2"),
+            Some((Direction::RightOf, "HDMI-1"))
+        );
+        assert!(layout.order("HDMI-1") < layout.order("DP-2"));
+        assert!(layout.order("HDMI-1") < layout.order("eDP-1"));
+    }
+
+    #[test]
+    fn resolve_normalizes_left_of_and_above_to_non_negative_positions() {
+        // Arrange
+        let relations = [
+            relation("eDP-1", Direction::Above, "DP-2"),
+            relation("DP-2", Direction::LeftOf, "HDMI-1"),
+        ];
+        let sizes = three_output_sizes();
+
+        // Act
+        let layout = resolve(&relations, &sizes).expect("layout should resolve");
+
+        // Assert
+        assert_eq!(layout.position("eDP-1"), Position { x: 0, y: 0 });
+        assert_eq!(layout.position("DP-2"), Position { x: 0, y: 1080 });
+        assert_eq!(layout.position("HDMI-1"), Position { x: 1280, y: 1080 });
+        assert!(layout.order("HDMI-1") < layout.order("DP-2"));
+        assert!(layout.order("DP-2") < layout.order("eDP-1"));
+    }
+
+    #[test]
+    fn resolve_detects_cycles() {
+        // Arrange
+        let relations = [
+            relation("DP-2", Direction::RightOf, "HDMI-1"),
+            relation("eDP-1", Direction::RightOf, "DP-2"),
+            relation("HDMI-1", Direction::RightOf, "eDP-1"),
+        ];
+        let sizes = three_output_sizes();
+
+        // Act
+        let layout = resolve(&relations, &sizes);
+
+        // Assert
+        assert_eq!(
+            layout,
+            Err(LayoutError::Cycle(vec![
+                "HDMI-1".to_string(),
+                "eDP-1".to_string(),
+                "DP-2".to_string(),
+                "HDMI-1".to_string(),
+            ]))
+        );
+    }
+
+    #[test]
+    fn resolve_rejects_multiple_anchors() {
+        // Arrange
+        let relations = [
+            relation("DP-2", Direction::RightOf, "HDMI-1"),
+            relation("DP-2", Direction::Below, "eDP-1"),
+        ];
+        let sizes = three_output_sizes();
+
+        // Act
+        let layout = resolve(&relations, &sizes);
+
+        // Assert
+        assert_eq!(
+            layout,
+            Err(LayoutError::MultipleAnchors("DP-2".to_string()))
+        );
+    }
+
+    #[test]
+    fn resolve_rejects_positions_out_of_range() {
+        // Arrange
+        let relations = [
+            relation("DP-1", Direction::RightOf, "HDMI-1"),
+            relation("DP-2", Direction::RightOf, "DP-1"),
+        ];
+        let sizes = hashmap! {
+            "HDMI-1" => Resolution { width: 20000, height: 1080 },
+            "DP-1" => Resolution { width: 20000, height: 1080 },
+            "DP-2" => Resolution { width: 1920, height: 1080 },
+        };
+
+        // Act
+        let layout = resolve(&relations, &sizes);
+
+        // Assert
+        assert_eq!(
+            layout,
+            Err(LayoutError::OutOfRange(
+                "DP-2".to_string(),
+                Position { x: 40000, y: 0 }
+            ))
+        );
+    }
+
+    #[test]
+    fn outputs_not_in_layout_are_placed_with_mirror_anchor() {
+        // Arrange
+        let relations = [relation("HDMI-1", Direction::LeftOf, "eDP-1")];
+        let sizes = three_output_sizes();
+
+        // Act
+        let layout = resolve(&relations, &sizes)
+            .expect("layout should resolve")
+            .with_mirror_anchor(Some("eDP-1"));
+        let without_anchor = resolve(&relations, &sizes).expect("layout should resolve");
+
+        // Assert
+        assert_eq!(layout.position("eDP-1"), Position { x: 2560, y: 0 });
+        assert_eq!(layout.position("DP-2"), Position { x: 2560, y: 0 });
+        assert_eq!(without_anchor.position("DP-2"), Position { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn resolve_ignores_relations_with_outputs_not_being_enabled() {
+        // Arrange
+        let relations = [
+            relation("DP-2", Direction::RightOf, "HDMI-1"),
+            relation("DP-3", Direction::RightOf, "DP-2"),
+        ];
+        let sizes = three_output_sizes();
+
+        // Act
+        let layout = resolve(&relations, &sizes).expect("layout should resolve");
+
+        // Assert
+        assert_eq!(layout.order("DP-3"), None);
+        assert_eq!(layout.position("DP-2"), Position { x: 2560, y: 0 });
+    }
+}
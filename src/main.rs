@@ -1,33 +1,694 @@
 #![forbid(unsafe_code)]
+// Without a controller nothing can run, so only the `compile_error!` in `screen_controller`
+// is worth reporting.
+#![cfg_attr(
+    not(any(feature = "xrandr", feature = "sway", feature = "randr")),
+    allow(unused, unreachable_code)
+)]
+mod capabilities;
+mod diff;
+mod exit_code;
+mod layout;
+mod pid_file;
+mod rate_limit;
 mod screen;
 mod screen_controller;
+mod state;
 mod switch;
+mod watch;
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use exit_code::Outcome;
 
-#[derive(Parser)]
-#[command(author, version, about, arg_required_else_help(true))]
+/// How often --wait-for checks whether the output got connected.
+const WAIT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+#[derive(Clone, Parser)]
+#[command(
+    author,
+    version,
+    about,
+    arg_required_else_help(true),
+    after_help = exit_code::HELP
+)]
 struct Args {
     /// Method to use for querying and setting output modes.
-    #[arg(long, env = "SWITCH_DISPLAY_CONTROLLER")]
-    controller: screen_controller::ScreenControllerType,
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_CONTROLLER",
+        required_unless_present_any = ["capabilities", "print_config"]
+    )]
+    controller: Option<screen_controller::ScreenControllerType>,
     /// When choosing a mode, choose one with at least this refresh rate.
-    /// The value is specified in millihertz, i.e. 60000 is 60 Hz.
-    #[arg(long, env = "SWITCH_DISPLAY_MIN_REFRESH_RATE")]
+    /// The value is given in hertz or with an explicit unit, e.g. 60, 59.94Hz or 60000mHz.
+    /// Bare numbers of at least 1000 are deprecated and taken as millihertz.
+    /// Refresh rates are compared in whole hertz, so e.g. 59.94Hz modes meet a minimum of 60.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_MIN_REFRESH_RATE",
+        value_name = "RATE",
+        value_parser = screen::parse_refresh_rate
+    )]
     min_refresh_rate: Option<u32>,
+    /// When choosing a mode, choose one with at least the area of this resolution, given as WxH.
+    #[arg(long, env = "SWITCH_DISPLAY_MIN_RESOLUTION")]
+    min_resolution: Option<screen::Resolution>,
+    /// When choosing a mode, choose one giving at least this pixel density on every output
+    /// of known physical size.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_MIN_DPI",
+        value_name = "DPI",
+        value_parser = screen::parse_dpi
+    )]
+    min_dpi: Option<f32>,
+    /// When choosing a mode, prefer one with the aspect ratio of the internal output's largest
+    /// resolution, e.g. to avoid black bars on a projector.
+    #[arg(long, env = "SWITCH_DISPLAY_MATCH_INTERNAL_ASPECT")]
+    match_internal_aspect: bool,
+    /// What to maximize when choosing the common mode of mirrored outputs.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_MIRROR_OPTIMIZE",
+        value_enum,
+        default_value_t = switch::MirrorOptimize::Area
+    )]
+    mirror_optimize: switch::MirrorOptimize,
+    /// When choosing a mode, choose the one closest in area to this resolution, given as WxH,
+    /// preferring ones not larger than it. Must be given together with --closest.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_TARGET_RESOLUTION",
+        requires = "closest",
+        conflicts_with = "resolution"
+    )]
+    target_resolution: Option<screen::Resolution>,
+    /// Choose the mode closest to --target-resolution instead of the largest one.
+    #[arg(long, env = "SWITCH_DISPLAY_CLOSEST", requires = "target_resolution")]
+    closest: bool,
+    /// Gamma correction to apply to enabled outputs, as R:G:B.
+    /// Each component must be in the range from 0.1 to 10.0.
+    #[arg(long, env = "SWITCH_DISPLAY_GAMMA")]
+    gamma: Option<screen::Gamma>,
+    /// Brightness to apply to enabled outputs, in the range from 0.1 to 1.0.
+    #[arg(long, env = "SWITCH_DISPLAY_BRIGHTNESS", value_parser = screen::parse_brightness)]
+    brightness: Option<f32>,
+    /// Only enable outputs, never disable any.
+    #[arg(long, env = "SWITCH_DISPLAY_NO_DISABLE")]
+    no_disable: bool,
+    /// Only disable outputs, never enable any.
+    #[arg(long, env = "SWITCH_DISPLAY_NO_ENABLE")]
+    no_enable: bool,
+    /// Disable the output with this name, in addition to the outputs disabled by the switch.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "NAME")]
+    disable: Vec<String>,
+    /// Enable the output with this name even if it is reported as disconnected,
+    /// e.g. because a KVM switch does not pass EDID through. Such outputs usually
+    /// have no known modes, so `--resolution` or `--modeline` must be given too; the xrandr
+    /// and randr controllers then create a CVT mode of that resolution, or the mode of the
+    /// modeline, for them.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "NAME")]
+    force_enable: Vec<String>,
+    /// Treat the output with this name as internal, e.g. a built-in panel connected over DP-1.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "NAME")]
+    internal_connector: Vec<String>,
+    /// Use this resolution, given as WxH, instead of choosing the best common one.
+    #[arg(long, env = "SWITCH_DISPLAY_RESOLUTION")]
+    resolution: Option<screen::Resolution>,
+    /// Create the mode for outputs without modes with these timings instead of CVT ones, given as
+    /// an xrandr modeline like `Modeline "1920x1080_60.00" 148.50 1920 2008 2052 2200 1080 1084
+    /// 1089 1125 +hsync +vsync`, e.g. a known-good one of the display. Uses its resolution,
+    /// as if given with --resolution.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_MODELINE",
+        value_name = "MODELINE",
+        conflicts_with_all = ["resolution", "target_resolution", "scale_mirrored", "auto"]
+    )]
+    modeline: Option<screen::Modeline>,
+    /// Keep running and switch outputs whenever a display is connected or disconnected.
+    #[arg(long, env = "SWITCH_DISPLAY_WATCH")]
+    watch: bool,
+    /// In watch mode, check for connected or disconnected displays this often.
+    /// The value is specified in milliseconds and must be at least 100.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_POLL_INTERVAL_MS",
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(100..)
+    )]
+    poll_interval_ms: u64,
+    /// In watch mode, wait this long before acting on a disconnected display,
+    /// and do nothing if it gets connected again meanwhile. Connected displays are acted on immediately.
+    /// The value is specified in milliseconds.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_DISCONNECT_DELAY_MS",
+        default_value_t = 0,
+        requires = "watch"
+    )]
+    disconnect_delay_ms: u64,
+    /// In watch mode, write the process ID to this file and remove it on exit.
+    /// Refuses to start if the file belongs to another running instance.
+    #[arg(long, env = "SWITCH_DISPLAY_PID_FILE", requires = "watch")]
+    pid_file: Option<std::path::PathBuf>,
+    /// Before disabling an output, fill it with this color, given as #RRGGBB.
+    /// Useful for panels that stay powered when disabled. Only supported by the sway controller.
+    #[arg(long, env = "SWITCH_DISPLAY_DISABLED_BACKGROUND", value_name = "COLOR")]
+    disabled_background: Option<screen::Color>,
+    /// Also switch virtual outputs, like HEADLESS-1, VIRTUAL-1 or WL-1, which are left as they are by default.
+    #[arg(long, env = "SWITCH_DISPLAY_INCLUDE_VIRTUAL")]
+    include_virtual: bool,
+    /// How to choose the outputs to enable: toggle between the internal and external ones,
+    /// or enable every connected output.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_MODE",
+        value_enum,
+        default_value_t = switch::SwitchMode::Toggle,
+        conflicts_with = "only"
+    )]
+    mode: switch::SwitchMode,
+    /// Make the given output the only enabled one, instead of choosing outputs automatically.
+    /// Fails if there is no output of that name.
+    #[arg(long, env = "SWITCH_DISPLAY_ONLY", value_name = "NAME")]
+    only: Option<String>,
+    /// Enable every connected output at its preferred mode, without disabling the internal one.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_AUTO",
+        conflicts_with_all = [
+            "only",
+            "resolution",
+            "target_resolution",
+            "min_refresh_rate",
+            "min_resolution",
+            "min_dpi",
+            "match_internal_aspect"
+        ]
+    )]
+    auto: bool,
+    /// Refuse to switch more than this many times within --max-switches-window-secs,
+    /// to avoid a switch loop when something else keeps changing the outputs.
+    #[arg(long, env = "SWITCH_DISPLAY_MAX_SWITCHES", value_name = "N")]
+    max_switches: Option<usize>,
+    /// Length of the window for --max-switches, in seconds.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_MAX_SWITCHES_WINDOW_SECS",
+        default_value_t = 60,
+        requires = "max_switches"
+    )]
+    max_switches_window_secs: u64,
+    /// File to keep the times of recent switches in for --max-switches,
+    /// shared between invocations. Defaults to a file in $XDG_RUNTIME_DIR.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_SWITCH_HISTORY_FILE",
+        requires = "max_switches"
+    )]
+    switch_history_file: Option<std::path::PathBuf>,
+    /// When mirroring, run every output at its largest resolution and scale the picture
+    /// of the largest one to fit the others, instead of using a common resolution.
+    /// Only supported by the xrandr controller.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_SCALE_MIRRORED",
+        conflicts_with = "resolution"
+    )]
+    scale_mirrored: bool,
+    /// When mirroring, run every output at its largest resolution and scale the larger ones down
+    /// to show the area of the smallest one, instead of using a common resolution.
+    /// Only supported by the xrandr controller.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_AUTO_SCALE_MIRROR",
+        conflicts_with = "scale_mirrored"
+    )]
+    auto_scale_mirror: bool,
+    /// Filter for sampling the picture of scaled outputs. Nearest keeps pixel art and terminals sharp.
+    /// Only supported by the xrandr and sway controllers.
+    #[arg(long, env = "SWITCH_DISPLAY_SCALE_FILTER", value_enum)]
+    scale_filter: Option<screen::ScaleFilter>,
+    /// Power off outputs after disabling them, for panels that stay powered when disabled,
+    /// and power them on again when enabling them. Only supported by the sway controller.
+    #[arg(long, env = "SWITCH_DISPLAY_DPMS_ON_DISABLE")]
+    dpms_on_disable: bool,
+    /// Allow the enabled outputs to tear, lowering latency e.g. for games, given as on or off.
+    /// Only supported by the sway controller.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_TEARING",
+        value_name = "on|off",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    tearing: Option<bool>,
+    /// How many milliseconds before the next frame sway starts rendering on the enabled outputs,
+    /// or off. Only supported by the sway controller.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_MAX_RENDER_TIME",
+        value_name = "MS|off",
+        value_parser = screen::parse_max_render_time
+    )]
+    max_render_time: Option<u32>,
+    /// How mirrored outputs share their picture. Hardware mirroring drives them from
+    /// a single CRTC where possible. Only supported by the randr controller.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_MIRROR_MODE",
+        value_enum,
+        default_value_t = switch::MirrorMode::Software
+    )]
+    mirror_mode: switch::MirrorMode,
+    /// Make the first enabled internal output the primary one.
+    /// Not supported by the sway controller.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_PRIMARY_INTERNAL",
+        conflicts_with = "primary_external"
+    )]
+    primary_internal: bool,
+    /// Make the first enabled external output the primary one.
+    /// Not supported by the sway controller.
+    #[arg(long, env = "SWITCH_DISPLAY_PRIMARY_EXTERNAL")]
+    primary_external: bool,
+    /// With --watch, prefer the most recently connected output: it becomes the mirror anchor
+    /// and primary candidate, and other external outputs that are not enabled yet stay off.
+    #[arg(long, env = "SWITCH_DISPLAY_PREFER_NEWEST", requires = "watch")]
+    prefer_newest: bool,
+    /// When the outputs have no common resolution, keep this output at its native mode
+    /// and scale the others to show its picture, instead of letting each output use its own mode.
+    /// Only supported by the xrandr controller.
+    #[arg(long, env = "SWITCH_DISPLAY_PREFER_OUTPUT", value_name = "NAME")]
+    prefer_output: Option<String>,
+    /// Wait until this output is connected before switching, e.g. when run by udev at boot
+    /// before the monitor is ready. Gives up after --wait-timeout-ms and switches anyway.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_WAIT_FOR",
+        value_name = "OUTPUT",
+        conflicts_with = "watch"
+    )]
+    wait_for: Option<String>,
+    /// How long to wait for the output given with --wait-for, in milliseconds.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_WAIT_TIMEOUT_MS",
+        default_value_t = 10000,
+        requires = "wait_for"
+    )]
+    wait_timeout_ms: u64,
+    /// Show the picture rendered by this provider, i.e. GPU, on the outputs of the one given
+    /// by --provider-offload, like `xrandr --setprovideroutputsource`. Only supported
+    /// by the xrandr controller.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_PROVIDER_SOURCE",
+        value_name = "NAME",
+        requires = "provider_offload"
+    )]
+    provider_source: Option<String>,
+    /// Provider whose outputs show the picture rendered by --provider-source, e.g. the discrete
+    /// GPU of a hybrid laptop the external outputs are wired to.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_PROVIDER_OFFLOAD",
+        value_name = "NAME",
+        requires = "provider_source"
+    )]
+    provider_offload: Option<String>,
+    /// Keep the size of the X screen instead of fitting it to the enabled outputs, for setups
+    /// like a fixed single-monitor kiosk where resizing is unnecessary or fails on the driver.
+    /// Only supported by the randr controller.
+    #[arg(long, env = "SWITCH_DISPLAY_NO_RESIZE")]
+    no_resize: bool,
+    /// Keep the position of outputs that are already enabled instead of placing them by
+    /// the layout, e.g. after arranging them with another tool. Newly enabled outputs are
+    /// still placed by the layout. Only supported by the randr controller.
+    #[arg(long, env = "SWITCH_DISPLAY_NO_REPOSITION")]
+    no_reposition: bool,
+    /// Reconfigure the outputs even if they already appear to be in the target state.
+    #[arg(long, env = "SWITCH_DISPLAY_FORCE")]
+    force: bool,
+    /// Use an X11 controller from within a Wayland session, where it likely only affects
+    /// Xwayland or a nested X server.
+    #[arg(long, env = "SWITCH_DISPLAY_ALLOW_NESTED_X")]
+    allow_nested_x: bool,
+    /// Run this shell command after switching outputs. The enabled and disabled outputs
+    /// are passed as comma-separated names in SWITCH_DISPLAY_ENABLED and SWITCH_DISPLAY_DISABLED,
+    /// the chosen resolution in SWITCH_DISPLAY_CHOSEN_RESOLUTION (empty if chosen by the controller).
+    #[arg(long, env = "SWITCH_DISPLAY_ON_SWITCH", value_name = "COMMAND")]
+    on_switch: Option<String>,
+    /// With --watch, run this shell command when outputs get connected, passing their
+    /// comma-separated names in SWITCH_DISPLAY_CONNECTED.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_ON_CONNECT",
+        value_name = "COMMAND",
+        requires = "watch"
+    )]
+    on_connect: Option<String>,
+    /// With --watch, run this shell command when outputs get disconnected, passing their
+    /// comma-separated names in SWITCH_DISPLAY_DISCONNECTED.
+    #[arg(
+        long,
+        env = "SWITCH_DISPLAY_ON_DISCONNECT",
+        value_name = "COMMAND",
+        requires = "watch"
+    )]
+    on_disconnect: Option<String>,
+    /// Do not print a summary of the switch to stderr.
+    #[arg(long, short, env = "SWITCH_DISPLAY_QUIET")]
+    quiet: bool,
+    /// Place an output to the right of another one, given as OUTPUT=ANCHOR.
+    /// Outputs not placed relative to others mirror each other.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "OUTPUT=ANCHOR", value_parser = layout::parse_output_pair)]
+    right_of: Vec<(String, String)>,
+    /// Arrange the enabled outputs according to a preset, in addition to explicit placements.
+    #[arg(long, env = "SWITCH_DISPLAY_LAYOUT", value_enum)]
+    layout: Option<layout::LayoutPreset>,
+    /// Place an output to the left of another one, given as OUTPUT=ANCHOR.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "OUTPUT=ANCHOR", value_parser = layout::parse_output_pair)]
+    left_of: Vec<(String, String)>,
+    /// Place an output above another one, given as OUTPUT=ANCHOR.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "OUTPUT=ANCHOR", value_parser = layout::parse_output_pair)]
+    above: Vec<(String, String)>,
+    /// Place an output below another one, given as OUTPUT=ANCHOR.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "OUTPUT=ANCHOR", value_parser = layout::parse_output_pair)]
+    below: Vec<(String, String)>,
+    /// Never use a mode of an output, given as OUTPUT=WxH@RATE, or OUTPUT=WxH@* for any refresh rate.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "OUTPUT=WxH@RATE")]
+    blacklist_mode: Vec<screen::ModeFilter>,
+    /// Restrict an output to a single mode, given as OUTPUT=WxH@RATE, or OUTPUT=WxH@* for any refresh rate.
+    /// Can be given multiple times to allow several modes.
+    #[arg(long, value_name = "OUTPUT=WxH@RATE")]
+    only_mode: Vec<screen::ModeFilter>,
+    /// Print the current and target state of every output instead of switching.
+    #[arg(long, conflicts_with = "watch")]
+    diff: bool,
+    /// Output format of --diff.
+    #[arg(long, value_enum, default_value_t = diff::DiffFormat::Text, requires = "diff")]
+    format: diff::DiffFormat,
+    /// Print the outputs reported by the controller, with their modes, and exit.
+    #[arg(long, conflicts_with = "watch")]
+    list: bool,
+    /// Print which outputs are enabled, with their modes and positions, as JSON for
+    /// --apply-state to restore, and exit.
+    #[arg(long, conflicts_with = "watch")]
+    print_state: bool,
+    /// Switch the outputs to a state printed by --print-state, read from the file, or from the
+    /// standard input if `-`, instead of choosing outputs, modes and positions automatically.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["watch", "only", "auto", "diff", "print_state"]
+    )]
+    apply_state: Option<std::path::PathBuf>,
+    /// Print the controllers and actions supported by this binary as JSON and exit.
+    #[arg(long)]
+    capabilities: bool,
+    /// Print the effective value of every option, merged from the command line, the environment
+    /// and the defaults, with where it came from, and exit.
+    #[arg(long)]
+    print_config: bool,
 }
 
-fn main() {
+fn main() -> std::process::ExitCode {
     env_logger::init();
 
-    let args = Args::parse();
-    let mut screen_controller = screen_controller::ScreenController::new(args.controller);
+    log::debug!(
+        "compiled_in_controllers = {:?}",
+        screen_controller::ScreenControllerType::compiled_in()
+    );
+
+    let usage_error = |err: clap::Error| {
+        err.print().expect("unable to print usage");
+        if err.use_stderr() {
+            Outcome::InvalidSelection
+        } else {
+            // --help or --version
+            Outcome::Applied
+        }
+    };
+    let (matches, args) = match Args::command()
+        .try_get_matches()
+        .and_then(|matches| Args::from_arg_matches(&matches).map(|args| (matches, args)))
+    {
+        Ok(parsed) => parsed,
+        Err(err) => return usage_error(err).into(),
+    };
+
+    run(&args, &matches).into()
+}
+
+/// Carries out the parsed command line.
+fn run(args: &Args, matches: &clap::ArgMatches) -> Outcome {
+    if args.print_config {
+        println!("{}", format_config(&Args::command(), matches));
+        return Outcome::Applied;
+    }
+
+    if args.capabilities {
+        println!("{}", capabilities::to_json());
+        return Outcome::Applied;
+    }
+
+    let controller_type = args
+        .controller
+        .expect("--controller is required unless --capabilities is given");
 
-    let screen = screen_controller.get_outputs();
+    if args.list {
+        let screen = screen_controller::ScreenController::new(controller_type)
+            .and_then(|screen_controller| screen_controller.get_outputs());
+        return match screen {
+            Ok(mut screen) => {
+                screen.dedup_modes();
+                println!("{}", format_output_list(&screen));
+                Outcome::Applied
+            }
+            Err(err) => exit_code::report_switch_error(err, Outcome::NoController),
+        };
+    }
+
+    if args.print_state {
+        let screen = screen_controller::ScreenController::new(controller_type)
+            .and_then(|screen_controller| screen_controller.get_outputs());
+        return match screen {
+            Ok(screen) => {
+                println!("{}", state::State::current(&screen).to_json());
+                Outcome::Applied
+            }
+            Err(err) => exit_code::report_switch_error(err, Outcome::NoController),
+        };
+    }
+
+    if controller_type.is_x11()
+        && is_nested_x_session(
+            std::env::var_os("DISPLAY").as_deref(),
+            std::env::var_os("WAYLAND_DISPLAY").as_deref(),
+        )
+    {
+        if args.allow_nested_x {
+            log::warn!(
+                "Running under Wayland, the {controller_type:?} controller likely only affects Xwayland or a nested X server"
+            );
+        } else {
+            log::error!(
+                "Running under Wayland, the {controller_type:?} controller would only affect Xwayland or a nested X server; use a Wayland controller, or --allow-nested-x to switch anyway"
+            );
+            return Outcome::NoController;
+        }
+    }
+
+    if args.watch {
+        let _pid_file = match args.pid_file.as_deref() {
+            Some(path) => match pid_file::PidFile::create(path) {
+                Ok(pid_file) => Some(pid_file),
+                Err(err) => {
+                    log::error!("Unable to create PID file {}: {err}", path.display());
+                    return Outcome::InvalidSelection;
+                }
+            },
+            None => None,
+        };
+
+        let mut last_switch_plan = None;
+        let watch_args = args.clone();
+        watch::watch(
+            controller_type,
+            std::time::Duration::from_millis(args.poll_interval_ms),
+            std::time::Duration::from_millis(args.disconnect_delay_ms),
+            move |screen_controller, hotplug, reconcile| {
+                let args = &watch_args;
+                switch(
+                    args,
+                    screen_controller,
+                    hotplug.trigger(),
+                    reconcile,
+                    &mut last_switch_plan,
+                );
+                run_hotplug_hooks(args, hotplug);
+            },
+        )
+    } else {
+        let target_state = match args.apply_state.as_deref().map(state::State::read) {
+            Some(Ok(target_state)) => Some(target_state),
+            Some(Err(err)) => {
+                log::error!("Unable to read the state to apply: {err}");
+                return Outcome::InvalidSelection;
+            }
+            None => None,
+        };
+
+        if let Some(name) = &args.wait_for {
+            let connected = watch::wait_for_output(
+                // The randr controller caches the screen resources, so a fresh one is needed every time.
+                || screen_controller::ScreenController::new(controller_type)?.get_outputs(),
+                name,
+                std::time::Duration::from_millis(args.wait_timeout_ms),
+                WAIT_POLL_INTERVAL,
+            );
+            match connected {
+                Ok(true) => {}
+                Ok(false) => log::warn!(
+                    "Output {name} is still not connected after {}ms, switching anyway",
+                    args.wait_timeout_ms
+                ),
+                Err(err) => return exit_code::report_switch_error(err, Outcome::NoController),
+            }
+        }
+
+        match screen_controller::ScreenController::new(controller_type) {
+            Ok(mut screen_controller) => match &target_state {
+                Some(target_state) => apply_state(args, &mut screen_controller, target_state),
+                None => switch(args, &mut screen_controller, None, false, &mut None),
+            },
+            Err(err) => exit_code::report_switch_error(err, Outcome::NoController),
+        }
+    }
+}
+
+/// Switches the outputs to the given state, e.g. printed by --print-state before,
+/// instead of planning the switch.
+fn apply_state(
+    args: &Args,
+    screen_controller: &mut screen_controller::ScreenController,
+    target_state: &state::State,
+) -> Outcome {
+    let screen = match screen_controller.get_outputs() {
+        Ok(screen) => screen,
+        Err(err) => return exit_code::report_switch_error(err, Outcome::NoController),
+    };
     log::trace!("screen = {screen:?}");
 
-    let switch_plan = switch::build_switch_plan(&screen);
+    let switch_plan = match target_state.build_switch_plan(&screen) {
+        Ok(switch_plan) => switch_plan,
+        Err(err) => {
+            log::error!("Unable to apply the state: {err}");
+            return Outcome::InvalidSelection;
+        }
+    };
     log::trace!("switch_plan = {switch_plan:?}");
+    if target_state.is_applied(&screen) {
+        log::info!("Outputs are already in the target state, nothing to do");
+        return Outcome::NoChange;
+    }
+
+    let resolution = target_state.common_resolution();
+    let switch_options = switch::SwitchOptions {
+        layout: target_state.layout(),
+        force: args.force,
+        mode_restrictions: target_state.mode_restrictions(),
+        ..Default::default()
+    };
+    let switched = match screen_controller.switch_outputs(&switch_plan, resolution, &switch_options)
+    {
+        Ok(switched) => switched,
+        Err(err) => return exit_code::report_switch_error(err, Outcome::ControllerFailed),
+    };
+
+    if !args.quiet {
+        eprintln!("{}", format_summary(&switch_plan, resolution));
+    }
+
+    if switched {
+        Outcome::Applied
+    } else {
+        Outcome::NoChange
+    }
+}
+
+/// Switches the outputs, biased towards the `trigger` output that just got connected, if any.
+/// `last_switch_plan` keeps the plan carried out last, across calls in watch mode.
+/// With `reconcile`, the outputs of the last plan are restored instead of switching further.
+fn switch(
+    args: &Args,
+    screen_controller: &mut screen_controller::ScreenController,
+    trigger: Option<&str>,
+    reconcile: bool,
+    last_switch_plan: &mut Option<switch::OwnedSwitchPlan>,
+) -> Outcome {
+    let mut screen = match screen_controller.get_outputs() {
+        Ok(screen) => screen,
+        Err(err) => return exit_code::report_switch_error(err, Outcome::NoController),
+    };
+    screen.mark_internal(&args.internal_connector);
+    let mode_restrictions = screen::ModeRestrictions {
+        blacklist: args.blacklist_mode.clone(),
+        whitelist: args.only_mode.clone(),
+    };
+    if let Err(err) = screen.restrict_modes(&mode_restrictions) {
+        log::error!("Invalid mode restrictions: {err}");
+        return Outcome::InvalidSelection;
+    }
+    log::trace!("screen = {screen:?}");
+
+    let mut switch_plan = match &args.only {
+        Some(name) => match switch::build_only_switch_plan(&screen, name) {
+            Some(switch_plan) => switch_plan,
+            None => {
+                log::error!("Output {name:?} requested to be the only one does not exist");
+                return Outcome::InvalidSelection;
+            }
+        },
+        None if args.auto || args.mode == switch::SwitchMode::AllConnected => {
+            switch::build_switch_plan_all_connected(&screen, args.include_virtual)
+        }
+        None if reconcile => switch::build_reconcile_switch_plan(
+            &screen,
+            last_switch_plan.as_ref(),
+            args.include_virtual,
+        ),
+        None if args.force => switch::build_target_switch_plan(&screen, args.include_virtual),
+        None => switch::build_switch_plan(&screen, args.include_virtual),
+    };
+    log::trace!("switch_plan = {switch_plan:?}");
+
+    let overrides = switch::SwitchPlanOverrides {
+        no_disable: args.no_disable,
+        no_enable: args.no_enable,
+        disable: args.disable.clone(),
+        force_enable: args.force_enable.clone(),
+    };
+    log::debug!("overrides = {overrides:?}");
+    if args.prefer_newest
+        && let Some(trigger) = trigger
+    {
+        switch::prefer_newest_output(&mut switch_plan, trigger);
+    }
+    switch::apply_overrides(&mut switch_plan, &screen, &overrides);
+    if !args.force {
+        switch::keep_last_enabled_output(&mut switch_plan, &screen);
+    }
 
     log::debug!(
         "outputs_to_disable = {:?}",
@@ -46,9 +707,1039 @@ fn main() {
             .collect::<Vec<_>>()
     );
 
-    let best_resolution =
-        switch::choose_best_resolution(&switch_plan.outputs_to_enable, args.min_refresh_rate);
+    let resolution = args
+        .resolution
+        .or_else(|| args.modeline.as_ref().map(screen::Modeline::resolution));
+    if resolution.is_none() {
+        // The controllers create a mode of the given resolution for outputs without modes.
+        if let Some(output) = switch_plan
+            .outputs_to_enable
+            .iter()
+            .find(|output| output.modes.is_empty())
+        {
+            log::error!(
+                "Output {} has no known modes, e.g. because it sends no EDID; give --resolution or --modeline to create a mode for it",
+                output.name
+            );
+            return Outcome::InvalidSelection;
+        }
+        for output in switch_plan
+            .outputs_to_enable
+            .iter()
+            .filter(|output| !output.is_usable())
+        {
+            log::warn!(
+                "Output {} is disconnected, consider giving --resolution",
+                output.name
+            );
+        }
+    }
+
+    if switch_plan.is_noop() && !args.diff {
+        log::info!("Outputs are already in the target state, nothing to do");
+        if !args.quiet {
+            eprintln!("{}", format_summary(&switch_plan, None));
+        }
+        return Outcome::NoChange;
+    }
+
+    let rate_limit = args.max_switches.map(|max_switches| rate_limit::RateLimit {
+        max_switches,
+        window: std::time::Duration::from_secs(args.max_switches_window_secs),
+    });
+    // --diff does not switch, so neither the rate limit nor the history apply to it.
+    let mut switch_history = rate_limit.as_ref().filter(|_| !args.diff).map(|_| {
+        let path = args
+            .switch_history_file
+            .clone()
+            .unwrap_or_else(rate_limit::default_history_path);
+        rate_limit::SwitchHistory::load_or_empty(&path)
+    });
+    if let (Some(rate_limit), Some(switch_history)) = (&rate_limit, &switch_history)
+        && !rate_limit.allows(switch_history.timestamps(), rate_limit::now())
+    {
+        log::warn!(
+            "Already switched {} times in the last {:?}, skipping",
+            rate_limit.max_switches,
+            rate_limit.window
+        );
+        return Outcome::NoChange;
+    }
+
+    let mut scale_from_preferred = false;
+    let best_resolution = resolution.or_else(|| {
+        // With --auto, the controller picks the preferred mode of each output.
+        if args.auto {
+            return None;
+        }
+
+        let aspect_ratio = args
+            .match_internal_aspect
+            .then(|| {
+                let internal_resolution = screen
+                    .internal_outputs()
+                    .filter(|output| output.connected)
+                    .find_map(screen::Output::largest_resolution);
+                if internal_resolution.is_none() {
+                    log::warn!("No internal output to match the aspect ratio of, ignoring it");
+                }
+                internal_resolution
+            })
+            .flatten();
+
+        let constraints = switch::ResolutionConstraints {
+            min_refresh_rate: args.min_refresh_rate,
+            min_resolution: args.min_resolution,
+            closest_to: args.target_resolution,
+            min_dpi: args.min_dpi,
+            aspect_ratio,
+            optimize: args.mirror_optimize,
+            // Explicitly asked for modes may differ from the current one.
+            prefer_current: args.min_refresh_rate.is_none() && args.target_resolution.is_none(),
+        };
+        match switch::choose_best_resolution(&switch_plan.outputs_to_enable, &constraints) {
+            Ok(resolution) => Some(resolution),
+            // Only disabling outputs, there is nothing to choose.
+            Err(switch::ChooseResolutionError::NoOutputs) => None,
+            Err(err @ switch::ChooseResolutionError::NoModesOnOutput(_)) => {
+                log::warn!("{err}, letting the controller choose the modes");
+                None
+            }
+            Err(switch::ChooseResolutionError::NoCommonResolution { .. })
+                if args.prefer_output.as_ref().is_some_and(|name| {
+                    switch_plan
+                        .outputs_to_enable
+                        .iter()
+                        .any(|output| output.name == *name)
+                }) =>
+            {
+                log::info!(
+                    "No common resolution, keeping the preferred output at its native mode and scaling the others"
+                );
+                scale_from_preferred = true;
+                None
+            }
+            Err(err @ switch::ChooseResolutionError::NoCommonResolution { .. }) => {
+                log::warn!(
+                    "Unable to mirror outputs, {err}; letting the controller choose a mode for each output"
+                );
+                None
+            }
+            Err(err @ switch::ChooseResolutionError::NoResolutionMeetsConstraints) => {
+                log::warn!(
+                    "{err}, letting the controller choose the modes; consider relaxing --min-refresh-rate, --min-resolution or --min-dpi"
+                );
+                None
+            }
+        }
+    });
     log::debug!("best_resolution = {best_resolution:?}");
 
-    screen_controller.switch_outputs(&switch_plan, best_resolution)
+    let color_adjustment = screen::ColorAdjustment {
+        gamma: args.gamma,
+        brightness: args.brightness,
+    };
+    log::debug!("color_adjustment = {color_adjustment:?}");
+
+    let mut relations = relations(args);
+    let mut rotations = std::collections::HashMap::new();
+    if let Some(preset) = args.layout {
+        let (preset_relations, preset_rotations) =
+            layout::preset(preset, &switch_plan.outputs_to_enable);
+        relations.extend(preset_relations);
+        rotations = preset_rotations;
+    }
+    let mut sizes = layout::estimate_sizes(&switch_plan.outputs_to_enable, best_resolution);
+    layout::rotate_sizes(&mut sizes, &rotations);
+    let layout = match layout::resolve(&relations, &sizes) {
+        Ok(layout) => {
+            // Outputs not positioned relative to others mirror the first of them.
+            let mirror_anchor = switch_plan
+                .outputs_to_enable
+                .iter()
+                .map(|output| output.name.as_str())
+                .find(|name| layout.relation(name).is_none());
+            layout
+                .with_mirror_anchor(mirror_anchor)
+                .with_rotations(rotations)
+        }
+        Err(err) => {
+            log::error!("Invalid layout: {err}");
+            return Outcome::InvalidSelection;
+        }
+    };
+    log::debug!("layout = {layout:?}");
+
+    let primary_location = if args.primary_internal {
+        Some(screen::Location::Internal)
+    } else if args.primary_external {
+        Some(screen::Location::External)
+    } else {
+        None
+    };
+    let primary = match primary_location {
+        Some(location) => match switch::choose_primary(&switch_plan, location) {
+            Some(output) => Some(output.name.clone()),
+            None => {
+                log::error!("No {location:?} output is enabled to make the primary one");
+                return Outcome::InvalidSelection;
+            }
+        },
+        None => None,
+    };
+    log::debug!("primary = {primary:?}");
+
+    let switch_options = switch::SwitchOptions {
+        color_adjustment,
+        disabled_background: args.disabled_background,
+        layout,
+        force: args.force,
+        scale_mirrored: args.scale_mirrored || scale_from_preferred,
+        scale_from: args.prefer_output.clone().filter(|_| scale_from_preferred),
+        auto_scale_mirror: args.auto_scale_mirror,
+        scale_filter: args.scale_filter,
+        dpms_on_disable: args.dpms_on_disable,
+        allow_tearing: args.tearing,
+        max_render_time_ms: args.max_render_time,
+        mirror_mode: args.mirror_mode,
+        primary,
+        mode_restrictions,
+        provider_output_source: args
+            .provider_offload
+            .clone()
+            .zip(args.provider_source.clone())
+            .map(|(sink, source)| switch::ProviderOutputSource { sink, source }),
+        no_resize: args.no_resize,
+        no_reposition: args.no_reposition,
+        modeline: args.modeline.clone(),
+    };
+
+    if args.diff {
+        let diffs = diff::build(
+            &screen,
+            &switch_plan,
+            best_resolution,
+            &switch_options.layout,
+        );
+        println!("{}", diff::format(&diffs, args.format));
+        return Outcome::Applied;
+    }
+
+    let owned_switch_plan = switch::OwnedSwitchPlan::from(&switch_plan);
+    // The plan holds the state of the outputs, so it only repeats if the outputs look
+    // the same as before the last switch.
+    if last_switch_plan.as_ref() == Some(&owned_switch_plan) {
+        log::warn!("The last switch apparently did not take effect, switching the same way again");
+    }
+
+    let switched =
+        match screen_controller.switch_outputs(&switch_plan, best_resolution, &switch_options) {
+            Ok(switched) => switched,
+            Err(err) => return exit_code::report_switch_error(err, Outcome::ControllerFailed),
+        };
+    *last_switch_plan = Some(owned_switch_plan);
+
+    if let (Some(rate_limit), Some(switch_history)) = (&rate_limit, &mut switch_history)
+        && let Err(err) = switch_history.record(rate_limit, rate_limit::now())
+    {
+        log::warn!("Unable to save switch history: {err}");
+    }
+
+    if !args.quiet {
+        eprintln!("{}", format_summary(&switch_plan, best_resolution));
+    }
+
+    if let Some(command) = &args.on_switch {
+        run_on_switch(command, &switch_plan, best_resolution);
+    }
+
+    if switched {
+        Outcome::Applied
+    } else {
+        Outcome::NoChange
+    }
+}
+
+fn run_on_switch(
+    command: &str,
+    switch_plan: &switch::SwitchPlan,
+    resolution: Option<screen::Resolution>,
+) {
+    run_hook("on-switch", command, on_switch_env(switch_plan, resolution));
+}
+
+/// Runs the --on-connect and --on-disconnect commands, after switching for the hotplug.
+fn run_hotplug_hooks(args: &Args, hotplug: &watch::Hotplug) {
+    if let Some(command) = &args.on_connect
+        && !hotplug.connected.is_empty()
+    {
+        run_hook(
+            "on-connect",
+            command,
+            [("SWITCH_DISPLAY_CONNECTED", hotplug.connected.join(","))],
+        );
+    }
+    if let Some(command) = &args.on_disconnect
+        && !hotplug.disconnected.is_empty()
+    {
+        run_hook(
+            "on-disconnect",
+            command,
+            [(
+                "SWITCH_DISPLAY_DISCONNECTED",
+                hotplug.disconnected.join(","),
+            )],
+        );
+    }
+}
+
+fn run_hook<const N: usize>(hook: &str, command: &str, env: [(&'static str, String); N]) {
+    log::debug!("Running {hook} command {command:?}");
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::warn!("The {hook} command {command:?} failed: {status}"),
+        Err(err) => log::warn!("Unable to run the {hook} command {command:?}: {err}"),
+    }
+}
+
+/// Whether an X server is reachable from within a Wayland session, so it is Xwayland or
+/// a nested X server rather than the one driving the outputs.
+fn is_nested_x_session(
+    display: Option<&std::ffi::OsStr>,
+    wayland_display: Option<&std::ffi::OsStr>,
+) -> bool {
+    let is_set = |value: Option<&std::ffi::OsStr>| value.is_some_and(|value| !value.is_empty());
+    is_set(display) && is_set(wayland_display)
+}
+
+fn on_switch_env(
+    switch_plan: &switch::SwitchPlan,
+    resolution: Option<screen::Resolution>,
+) -> [(&'static str, String); 3] {
+    [
+        (
+            "SWITCH_DISPLAY_ENABLED",
+            join_names(&switch_plan.outputs_to_enable),
+        ),
+        (
+            "SWITCH_DISPLAY_DISABLED",
+            join_names(&switch_plan.outputs_to_disable),
+        ),
+        (
+            "SWITCH_DISPLAY_CHOSEN_RESOLUTION",
+            resolution
+                .map(|resolution| resolution.to_string())
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+fn relations(args: &Args) -> Vec<layout::Relation> {
+    [
+        (&args.right_of, layout::Direction::RightOf),
+        (&args.left_of, layout::Direction::LeftOf),
+        (&args.above, layout::Direction::Above),
+        (&args.below, layout::Direction::Below),
+    ]
+    .into_iter()
+    .flat_map(|(pairs, direction)| {
+        pairs.iter().map(move |(output, anchor)| layout::Relation {
+            output: output.clone(),
+            direction,
+            anchor: anchor.clone(),
+        })
+    })
+    .collect()
+}
+
+fn format_summary(
+    switch_plan: &switch::SwitchPlan,
+    resolution: Option<screen::Resolution>,
+) -> String {
+    let mut parts = Vec::new();
+    if !switch_plan.outputs_to_enable.is_empty() {
+        let mode =
+            resolution.map_or_else(|| "auto".to_string(), |resolution| resolution.to_string());
+        parts.push(format!(
+            "enabled {} @ {mode}",
+            join_names(&switch_plan.outputs_to_enable)
+        ));
+    }
+    if !switch_plan.outputs_to_disable.is_empty() {
+        parts.push(format!(
+            "disabled {}",
+            join_names(&switch_plan.outputs_to_disable)
+        ));
+    }
+    if parts.is_empty() {
+        parts.push("nothing to switch".to_string());
+    }
+
+    format!("switch-display: {}", parts.join(", "))
+}
+
+fn format_output_list(screen: &screen::Screen) -> String {
+    let mut lines = Vec::new();
+    for output in &screen.outputs {
+        let location = match output.location {
+            screen::Location::Internal => "internal",
+            screen::Location::External => "external",
+            screen::Location::Virtual => "virtual",
+        };
+        let connected = if output.connected {
+            "connected"
+        } else {
+            "disconnected"
+        };
+        let mut state = if output.enabled {
+            "enabled".to_string()
+        } else {
+            "disabled".to_string()
+        };
+        if let Some(resolution) = output.current_resolution {
+            state = format!("{state} {resolution}");
+        }
+        lines.push(format!("{}: {location}, {connected}, {state}", output.name));
+
+        if let Some((width, height)) = output.physical_size_mm {
+            lines.push(format!("  Physical: {width}mm × {height}mm"));
+        }
+        if let Some(dpi) = output.dpi() {
+            lines.push(format!("  DPI: {dpi:.1}"));
+        }
+        if !output.modes.is_empty() {
+            let modes: Vec<_> = output
+                .modes
+                .iter()
+                .map(|mode| {
+                    let rates: Vec<_> = if mode.refresh_rates_millihz.is_empty() {
+                        std::slice::from_ref(&mode.refresh_rate_millihz)
+                    } else {
+                        &mode.refresh_rates_millihz
+                    }
+                    .iter()
+                    .map(|rate| format!("{}.{:02}", rate / 1000, rate % 1000 / 10))
+                    .collect();
+                    let flags: Vec<_> = [(mode.active, "current"), (mode.preferred, "preferred")]
+                        .into_iter()
+                        .filter_map(|(set, flag)| set.then_some(flag))
+                        .collect();
+                    let flags = if flags.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" ({})", flags.join(", "))
+                    };
+                    format!("{} @ {} Hz{flags}", mode.resolution, rates.join(", "))
+                })
+                .collect();
+            lines.push(format!("  Modes: {}", modes.join("; ")));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Formats every option as `name=value`, followed by the source of the value.
+fn format_config(command: &clap::Command, matches: &clap::ArgMatches) -> String {
+    command
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_id().as_str(), "help" | "version" | "print_config"))
+        .map(|arg| {
+            let id = arg.get_id().as_str();
+            let value = matches
+                .get_raw(id)
+                .map(|values| {
+                    values
+                        .map(|value| value.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .unwrap_or_default();
+            let source = match matches.value_source(id) {
+                Some(clap::parser::ValueSource::CommandLine) => "command line",
+                Some(clap::parser::ValueSource::EnvVariable) => "environment",
+                Some(clap::parser::ValueSource::DefaultValue) => "default",
+                _ => "unset",
+            };
+            format!("{}={value}  # {source}", arg.get_long().unwrap_or(id))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn join_names(outputs: &[&screen::Output]) -> String {
+    outputs
+        .iter()
+        .map(|output| output.name.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use screen::{Output, Resolution};
+
+    /// Laptop with its internal panel enabled, an external monitor just connected and
+    /// a disconnected output without modes.
+    fn fake_controller(fail: bool) -> screen_controller::ScreenController {
+        let output = |name: &str, connected: bool, enabled: bool| Output {
+            modes: [screen::Mode {
+                resolution: Resolution {
+                    width: 1920,
+                    height: 1080,
+                },
+                refresh_rate_millihz: 60000,
+                preferred: false,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
+            }]
+            .into_iter()
+            .filter(|_| connected)
+            .collect(),
+            ..Output::test(name, connected, enabled)
+        };
+        let screen = screen::Screen {
+            outputs: vec![
+                output("eDP-1", true, true),
+                output("HDMI-1", true, false),
+                output("DP-1", false, false),
+            ],
+        };
+        screen_controller::ScreenController::fake(screen, fail)
+    }
+
+    fn parse_args(args: &[&str]) -> Args {
+        let controller = &screen_controller::ScreenControllerType::compiled_in()[0];
+        Args::try_parse_from(
+            ["switch-display", "--controller", controller, "--quiet"]
+                .iter()
+                .chain(args),
+        )
+        .expect("arguments should parse")
+    }
+
+    #[test]
+    fn switch_mirrors_internal_to_connected_external() {
+        // Arrange
+        let args = parse_args(&[]);
+        let mut screen_controller = fake_controller(false);
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::Applied);
+        let [(switch_plan, resolution)] = screen_controller.fake_switches() else {
+            panic!("expected a single switch");
+        };
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert_eq!(
+            switch_plan
+                .outputs_to_enable
+                .iter()
+                .map(|output| output.name.as_str())
+                .collect::<Vec<_>>(),
+            ["eDP-1", "HDMI-1"]
+        );
+        assert_eq!(
+            *resolution,
+            Some(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+    }
+
+    #[test]
+    fn switch_scales_from_preferred_output_without_common_resolution() {
+        // Arrange
+        let args = parse_args(&["--prefer-output", "HDMI-1"]);
+        let mut screen = fake_controller(false).get_outputs().unwrap();
+        screen.outputs[1].modes[0].resolution = Resolution {
+            width: 2560,
+            height: 1440,
+        };
+        let mut screen_controller = screen_controller::ScreenController::fake(screen, false);
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::Applied);
+        let [(_, resolution)] = screen_controller.fake_switches() else {
+            panic!("expected a single switch");
+        };
+        assert_eq!(*resolution, None);
+        assert_eq!(
+            screen_controller.fake_mirror_scaling(),
+            [(true, Some("HDMI-1".to_string()))]
+        );
+    }
+
+    #[test]
+    fn reconciling_switch_restores_last_plan_instead_of_switching_further() {
+        // Arrange
+        let args = parse_args(&[]);
+        let mut screen_controller = fake_controller(false);
+        let internal = screen_controller.get_outputs().unwrap().outputs[0].clone();
+        let mut last_switch_plan = Some(switch::OwnedSwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![internal],
+        });
+
+        // Act
+        let outcome = switch(
+            &args,
+            &mut screen_controller,
+            None,
+            true,
+            &mut last_switch_plan,
+        );
+
+        // Assert
+        assert_eq!(outcome, Outcome::Applied);
+        let [(switch_plan, _)] = screen_controller.fake_switches() else {
+            panic!("expected a single switch");
+        };
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert_eq!(
+            switch_plan
+                .outputs_to_enable
+                .iter()
+                .map(|output| output.name.as_str())
+                .collect::<Vec<_>>(),
+            ["eDP-1"]
+        );
+    }
+
+    #[test]
+    fn switch_reports_controller_failure() {
+        // Arrange
+        let args = parse_args(&[]);
+        let mut screen_controller = fake_controller(true);
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::ControllerFailed);
+        assert!(screen_controller.fake_switches().is_empty());
+    }
+
+    #[test]
+    fn switch_rejects_mode_whitelist_matching_no_modes() {
+        // Arrange
+        let args = parse_args(&["--only-mode", "HDMI-1=1280x720@*"]);
+        let mut screen_controller = fake_controller(false);
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::InvalidSelection);
+        assert!(screen_controller.fake_switches().is_empty());
+    }
+
+    #[test]
+    fn switch_rejects_force_enabling_output_without_modes_without_resolution() {
+        // Arrange
+        let args = parse_args(&["--force-enable", "DP-1"]);
+        let mut screen_controller = fake_controller(false);
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::InvalidSelection);
+        assert!(screen_controller.fake_switches().is_empty());
+    }
+
+    #[test]
+    fn switch_force_enables_output_without_modes_at_given_resolution() {
+        // Arrange
+        let args = parse_args(&["--force-enable", "DP-1", "--resolution", "1920x1080"]);
+        let mut screen_controller = fake_controller(false);
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::Applied);
+        let [(switch_plan, resolution)] = screen_controller.fake_switches() else {
+            panic!("expected a single switch");
+        };
+        assert!(
+            switch_plan
+                .outputs_to_enable
+                .iter()
+                .any(|output| output.name == "DP-1")
+        );
+        assert_eq!(
+            *resolution,
+            Some(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+    }
+
+    #[test]
+    fn switch_rejects_primary_location_without_enabled_output() {
+        // Arrange
+        let args = parse_args(&["--primary-external", "--disable", "HDMI-1"]);
+        let mut screen_controller = fake_controller(false);
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::InvalidSelection);
+        assert!(screen_controller.fake_switches().is_empty());
+    }
+
+    /// Fake controller with both the internal and the external output enabled, i.e. mirroring.
+    fn mirroring_fake_controller() -> screen_controller::ScreenController {
+        let mut screen = fake_controller(false).get_outputs().unwrap();
+        screen.outputs[1].enabled = true;
+        screen_controller::ScreenController::fake(screen, false)
+    }
+
+    /// Names of the outputs the only switch of the fake controller disabled and enabled.
+    fn only_switched_names(
+        screen_controller: &screen_controller::ScreenController,
+    ) -> (Vec<&str>, Vec<&str>) {
+        let [(switch_plan, _)] = screen_controller.fake_switches() else {
+            panic!("expected a single switch");
+        };
+        fn names(outputs: &[screen::Output]) -> Vec<&str> {
+            outputs.iter().map(|output| output.name.as_str()).collect()
+        }
+        (
+            names(&switch_plan.outputs_to_disable),
+            names(&switch_plan.outputs_to_enable),
+        )
+    }
+
+    #[test]
+    fn switch_with_unknown_only_output_is_invalid_selection() {
+        // Arrange
+        let args = parse_args(&["--only", "HMDI-1"]);
+        let mut screen_controller = mirroring_fake_controller();
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::InvalidSelection);
+        assert!(screen_controller.fake_switches().is_empty());
+    }
+
+    #[test]
+    fn applying_printed_state_restores_it() {
+        // Arrange
+        let mut screen = fake_controller(false).get_outputs().unwrap();
+        screen.outputs[1].modes.push(screen::Mode {
+            resolution: Resolution {
+                width: 2560,
+                height: 1440,
+            },
+            refresh_rate_millihz: 60000,
+            preferred: true,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        });
+        let mut printed_screen = screen.clone();
+        for (output, active_mode, position) in [(0, 0, (0, 1440)), (1, 1, (0, 0))] {
+            let output = &mut printed_screen.outputs[output];
+            output.enabled = true;
+            output.modes[active_mode].active = true;
+            output.current_resolution = Some(output.modes[active_mode].resolution);
+            output.current_position = Some(screen::Position {
+                x: position.0,
+                y: position.1,
+            });
+        }
+        let printed = state::State::current(&printed_screen).to_json();
+        let args = parse_args(&[]);
+        let mut screen_controller = screen_controller::ScreenController::fake(screen, false);
+
+        // Act
+        let outcome = apply_state(&args, &mut screen_controller, &printed.parse().unwrap());
+        let reapplied_outcome =
+            apply_state(&args, &mut screen_controller, &printed.parse().unwrap());
+
+        // Assert
+        assert_eq!(outcome, Outcome::Applied);
+        assert_eq!(reapplied_outcome, Outcome::NoChange);
+        assert_eq!(
+            state::State::current(&screen_controller.get_outputs().unwrap()).to_json(),
+            printed
+        );
+    }
+
+    #[test]
+    fn applying_state_with_unknown_output_is_invalid_selection() {
+        // Arrange
+        let args = parse_args(&[]);
+        let mut screen_controller = fake_controller(false);
+        let target_state = r#"{"outputs":[{"name":"HMDI-1","enabled":true}]}"#.parse().unwrap();
+
+        // Act
+        let outcome = apply_state(&args, &mut screen_controller, &target_state);
+
+        // Assert
+        assert_eq!(outcome, Outcome::InvalidSelection);
+        assert!(screen_controller.fake_switches().is_empty());
+    }
+
+    #[test]
+    fn switch_with_no_enable_only_disables() {
+        // Arrange
+        let args = parse_args(&["--no-enable"]);
+        let mut screen_controller = mirroring_fake_controller();
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::Applied);
+        assert_eq!(
+            only_switched_names(&screen_controller),
+            (vec!["eDP-1"], vec![])
+        );
+    }
+
+    #[test]
+    fn switch_with_no_enable_and_disable_blacks_out_named_output() {
+        // Arrange
+        let args = parse_args(&["--no-enable", "--no-disable", "--disable", "HDMI-1"]);
+        let mut screen_controller = mirroring_fake_controller();
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::Applied);
+        assert_eq!(
+            only_switched_names(&screen_controller),
+            (vec!["HDMI-1"], vec![])
+        );
+    }
+
+    #[test]
+    fn switch_with_no_enable_and_disable_keeps_last_enabled_output() {
+        // Arrange
+        let args = parse_args(&["--no-enable", "--disable", "HDMI-1"]);
+        let mut screen_controller = mirroring_fake_controller();
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::Applied);
+        assert_eq!(
+            only_switched_names(&screen_controller),
+            (vec!["HDMI-1"], vec![])
+        );
+    }
+
+    #[test]
+    fn switch_with_no_enable_and_no_disable_changes_nothing() {
+        // Arrange
+        let args = parse_args(&["--no-enable", "--no-disable"]);
+        let mut screen_controller = mirroring_fake_controller();
+
+        // Act
+        let outcome = switch(&args, &mut screen_controller, None, false, &mut None);
+
+        // Assert
+        assert_eq!(outcome, Outcome::NoChange);
+        assert!(screen_controller.fake_switches().is_empty());
+    }
+
+    #[test]
+    fn nested_x_session_needs_both_displays() {
+        // Arrange
+        let value = |value: &'static str| Some(std::ffi::OsStr::new(value));
+
+        // Act & Assert
+        assert!(is_nested_x_session(value(":0"), value("wayland-1")));
+        assert!(!is_nested_x_session(value(":0"), None));
+        assert!(!is_nested_x_session(value(":0"), value("")));
+        assert!(!is_nested_x_session(None, value("wayland-1")));
+        assert!(!is_nested_x_session(value(""), value("wayland-1")));
+        assert!(!is_nested_x_session(None, None));
+    }
+
+    #[test]
+    fn config_shows_values_and_their_sources() {
+        // Arrange
+        let controller = &screen_controller::ScreenControllerType::compiled_in()[0];
+        let matches = Args::command()
+            .try_get_matches_from(["switch-display", "--controller", controller, "--quiet"])
+            .expect("arguments should parse");
+
+        // Act
+        let config = format_config(&Args::command(), &matches);
+
+        // Assert
+        let lines: Vec<_> = config.lines().collect();
+        assert!(lines.contains(&format!("controller={controller}  # command line").as_str()));
+        assert!(lines.contains(&"quiet=true  # command line"));
+        assert!(lines.contains(&"poll-interval-ms=1000  # default"));
+        assert!(lines.contains(&"only=  # unset"));
+        assert!(!config.contains("print-config"));
+    }
+
+    #[test]
+    fn test_format_summary() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-1", true, false),
+            Output::test("DP-2", true, false),
+        ];
+        let switch_plan = switch::SwitchPlan {
+            outputs_to_disable: vec![&outputs[0]],
+            outputs_to_enable: vec![&outputs[1], &outputs[2]],
+        };
+        let resolution = Some(Resolution {
+            width: 1920,
+            height: 1080,
+        });
+
+        // Act
+        let summary = format_summary(&switch_plan, resolution);
+
+        // Assert
+        assert_eq!(
+            summary,
+            "switch-display: enabled HDMI-1,DP-2 @ 1920x1080, disabled eDP-1"
+        );
+    }
+
+    #[test]
+    fn test_format_output_list() {
+        // Arrange
+        let screen = screen::Screen {
+            outputs: vec![
+                Output {
+                    modes: [
+                        (1920, 1080, 60020, true),
+                        (1920, 1080, 59970, false),
+                        (1280, 720, 60000, false),
+                    ]
+                    .into_iter()
+                    .map(
+                        |(width, height, refresh_rate_millihz, preferred)| screen::Mode {
+                            resolution: Resolution { width, height },
+                            refresh_rate_millihz,
+                            preferred,
+                            active: preferred,
+                            refresh_rates_millihz: Vec::new(),
+                        },
+                    )
+                    .collect(),
+                    current_resolution: Some(Resolution {
+                        width: 1920,
+                        height: 1080,
+                    }),
+                    physical_size_mm: Some((344, 194)),
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output::test("HDMI-1", false, false),
+            ],
+        };
+
+        let mut deduped_screen = screen.clone();
+        deduped_screen.dedup_modes();
+
+        // Act
+        let list = format_output_list(&screen);
+        let deduped_list = format_output_list(&deduped_screen);
+
+        // Assert
+        assert_eq!(
+            deduped_list.lines().nth(3),
+            Some("  Modes: 1920x1080 @ 60.02, 59.97 Hz (current, preferred); 1280x720 @ 60.00 Hz")
+        );
+        assert_eq!(
+            list,
+            "eDP-1: internal, connected, enabled 1920x1080\n  \
+             Physical: 344mm × 194mm\n  \
+             DPI: 141.7\n  \
+             Modes: 1920x1080 @ 60.02 Hz (current, preferred); 1920x1080 @ 59.97 Hz; 1280x720 @ 60.00 Hz\n\
+             HDMI-1: external, disconnected, disabled"
+        );
+    }
+
+    #[test]
+    fn test_format_summary_without_resolution() {
+        // Arrange
+        let outputs = [Output::test("eDP-1", true, false)];
+        let switch_plan = switch::SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0]],
+        };
+
+        // Act
+        let summary = format_summary(&switch_plan, None);
+
+        // Assert
+        assert_eq!(summary, "switch-display: enabled eDP-1 @ auto");
+    }
+
+    #[test]
+    fn test_format_summary_of_noop() {
+        // Arrange
+        let switch_plan = switch::SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: Vec::new(),
+        };
+
+        // Act
+        let summary = format_summary(&switch_plan, None);
+
+        // Assert
+        assert_eq!(summary, "switch-display: nothing to switch");
+    }
+
+    #[test]
+    fn on_switch_command_receives_env() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-1", true, false),
+        ];
+        let switch_plan = switch::SwitchPlan {
+            outputs_to_disable: vec![&outputs[0]],
+            outputs_to_enable: vec![&outputs[1]],
+        };
+        let resolution = Some(Resolution {
+            width: 1920,
+            height: 1080,
+        });
+        let record_path = std::env::temp_dir().join(format!(
+            "switch-display-{}-on-switch.txt",
+            std::process::id()
+        ));
+        let command = format!(
+            "echo \"$SWITCH_DISPLAY_ENABLED;$SWITCH_DISPLAY_DISABLED;$SWITCH_DISPLAY_CHOSEN_RESOLUTION\" > '{}'",
+            record_path.display()
+        );
+
+        // Act
+        run_on_switch(&command, &switch_plan, resolution);
+
+        // Assert
+        let recorded = std::fs::read_to_string(&record_path).unwrap();
+        std::fs::remove_file(&record_path).unwrap();
+        assert_eq!(recorded, "HDMI-1;eDP-1;1920x1080\n");
+    }
 }
@@ -0,0 +1,119 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A file containing the PID of this process, removed when dropped.
+#[derive(Debug)]
+pub(super) struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Creates the PID file, failing if it exists and belongs to a running process.
+    /// A PID file left behind by a process that is no longer running is replaced.
+    pub(super) fn create(path: &Path) -> io::Result<Self> {
+        match Self::create_new(path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                let contents = fs::read_to_string(path)?;
+                if let Ok(pid) = contents.trim().parse::<u32>()
+                    && is_running(pid)
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} belongs to running process {pid}", path.display()),
+                    ));
+                }
+
+                log::warn!("Removing stale PID file {}", path.display());
+                fs::remove_file(path)?;
+                Self::create_new(path)
+            }
+            result => result,
+        }
+    }
+
+    fn create_new(path: &Path) -> io::Result<Self> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let pid_file = Self {
+            path: path.to_path_buf(),
+        };
+        writeln!(file, "{}", std::process::id())?;
+        Ok(pid_file)
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            log::warn!("Unable to remove PID file {}: {err}", self.path.display());
+        }
+    }
+}
+
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("switch-display-{}-{name}.pid", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn create_writes_pid_and_drop_removes_file() {
+        // Arrange
+        let path = test_path("create");
+
+        // Act
+        let pid_file = PidFile::create(&path).expect("failed to create PID file");
+        let contents = fs::read_to_string(&path).expect("failed to read PID file");
+        drop(pid_file);
+
+        // Assert
+        assert_eq!(contents, format!("{}\n", std::process::id()));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn create_fails_when_process_is_running() {
+        // Arrange
+        let path = test_path("running");
+        let _pid_file = PidFile::create(&path).expect("failed to create PID file");
+
+        // Act
+        let result = PidFile::create(&path);
+
+        // Assert
+        assert_eq!(
+            result.expect_err("expected an error").kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn create_replaces_stale_pid_file() {
+        // Arrange
+        let path = test_path("stale");
+        fs::write(&path, format!("{}\n", u32::MAX)).expect("failed to write PID file");
+
+        // Act
+        let pid_file = PidFile::create(&path).expect("failed to create PID file");
+
+        // Assert
+        assert_eq!(
+            fs::read_to_string(&path).expect("failed to read PID file"),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+    }
+}
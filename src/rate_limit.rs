@@ -0,0 +1,191 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// At most `max_switches` switches are allowed within any `window`.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct RateLimit {
+    pub(super) max_switches: usize,
+    pub(super) window: Duration,
+}
+
+impl RateLimit {
+    /// Whether one more switch at `now` is allowed, given the times of the previous switches.
+    /// Times are in seconds since the Unix epoch.
+    pub(super) fn allows(&self, history: &[u64], now: u64) -> bool {
+        self.recent(history, now).count() < self.max_switches
+    }
+
+    /// Previous switches that still count against the limit at `now`.
+    fn recent<'a>(&self, history: &'a [u64], now: u64) -> impl Iterator<Item = u64> + 'a {
+        let window_start = now.saturating_sub(self.window.as_secs());
+        history
+            .iter()
+            .copied()
+            .filter(move |&timestamp| timestamp > window_start && timestamp <= now)
+    }
+}
+
+/// Times of recent switches, persisted so that the rate limit also applies
+/// across separate invocations, e.g. from udev rules.
+#[derive(Debug)]
+pub(super) struct SwitchHistory {
+    path: PathBuf,
+    timestamps: Vec<u64>,
+}
+
+impl SwitchHistory {
+    /// Loads the history from `path`, which may not exist yet. Malformed lines are skipped.
+    pub(super) fn load(path: &Path) -> io::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        let timestamps = contents
+            .lines()
+            .filter_map(|line| match line.trim().parse() {
+                Ok(timestamp) => Some(timestamp),
+                Err(_) => {
+                    log::warn!("Skipping bad line in {}: {line:?}", path.display());
+                    None
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            timestamps,
+        })
+    }
+
+    /// Like [`SwitchHistory::load`], but starts with an empty history if it cannot be read,
+    /// so that e.g. a history file with wrong permissions does not stop switching.
+    pub(super) fn load_or_empty(path: &Path) -> Self {
+        Self::load(path).unwrap_or_else(|err| {
+            log::warn!(
+                "Unable to load switch history from {}, starting with an empty one: {err}",
+                path.display()
+            );
+            Self {
+                path: path.to_path_buf(),
+                timestamps: Vec::new(),
+            }
+        })
+    }
+
+    pub(super) fn timestamps(&self) -> &[u64] {
+        &self.timestamps
+    }
+
+    /// Records a switch at `now` and saves the history, forgetting switches outside the window.
+    pub(super) fn record(&mut self, rate_limit: &RateLimit, now: u64) -> io::Result<()> {
+        let mut timestamps: Vec<_> = rate_limit.recent(&self.timestamps, now).collect();
+        timestamps.push(now);
+        self.timestamps = timestamps;
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents: String = self
+            .timestamps
+            .iter()
+            .map(|timestamp| format!("{timestamp}\n"))
+            .collect();
+        fs::write(&self.path, contents)
+    }
+}
+
+/// Where the switch history is kept by default: the user's runtime directory, if any.
+pub(super) fn default_history_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("switch-display")
+        .join("recent-switches")
+}
+
+pub(super) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the Unix epoch")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE_LIMIT: RateLimit = RateLimit {
+        max_switches: 3,
+        window: Duration::from_secs(60),
+    };
+
+    #[test]
+    fn allows_switches_below_limit() {
+        assert!(RATE_LIMIT.allows(&[], 1000));
+        assert!(RATE_LIMIT.allows(&[990, 995], 1000));
+    }
+
+    #[test]
+    fn refuses_switches_at_limit() {
+        assert!(!RATE_LIMIT.allows(&[950, 990, 995], 1000));
+    }
+
+    #[test]
+    fn ignores_switches_outside_window() {
+        assert!(RATE_LIMIT.allows(&[900, 940, 990, 995], 1000));
+        // Timestamps from the future, e.g. after the clock was set back, do not count either.
+        assert!(RATE_LIMIT.allows(&[990, 995, 2000], 1000));
+    }
+
+    #[test]
+    fn record_saves_recent_switches_only() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!(
+            "switch-display-{}-recent-switches",
+            std::process::id()
+        ));
+        fs::write(&path, "900\nbad\n990\n").unwrap();
+
+        // Act
+        let mut history = SwitchHistory::load(&path).unwrap();
+        let loaded = history.timestamps().to_vec();
+        history.record(&RATE_LIMIT, 1000).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+
+        // Assert
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, [900, 990]);
+        assert_eq!(saved, "990\n1000\n");
+    }
+
+    #[test]
+    fn load_of_missing_file_is_empty() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!(
+            "switch-display-{}-missing-recent-switches",
+            std::process::id()
+        ));
+
+        // Act
+        let history = SwitchHistory::load(&path).unwrap();
+
+        // Assert
+        assert!(history.timestamps().is_empty());
+    }
+
+    #[test]
+    fn load_or_empty_of_unreadable_file_is_empty() {
+        // Arrange
+        let path = std::env::temp_dir();
+
+        // Act
+        let history = SwitchHistory::load_or_empty(&path);
+
+        // Assert
+        assert!(history.timestamps().is_empty());
+    }
+}
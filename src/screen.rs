@@ -1,21 +1,214 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Screen {
     pub(crate) outputs: Vec<Output>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Screen {
+    /// Whether both screens have the same outputs, connected in the same way, ignoring their
+    /// modes and the order they are listed in. Whether outputs are enabled is ignored too:
+    /// switching changes it, as does enabling or disabling outputs by hand, and neither is
+    /// a display getting plugged in or out.
+    pub(crate) fn connectivity_eq(&self, other: &Screen) -> bool {
+        self.connectivity() == other.connectivity()
+    }
+
+    fn connectivity(&self) -> Vec<(&str, bool)> {
+        let mut connectivity: Vec<_> = self
+            .outputs
+            .iter()
+            .map(|output| (output.name.as_str(), output.connected))
+            .collect();
+        connectivity.sort();
+        connectivity
+    }
+
+    pub(crate) fn connected_outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.iter().filter(|output| output.connected)
+    }
+
+    pub(crate) fn enabled_outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.iter().filter(|output| output.enabled)
+    }
+
+    pub(crate) fn internal_outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs
+            .iter()
+            .filter(|output| output.location == Location::Internal)
+    }
+
+    pub(crate) fn external_outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs
+            .iter()
+            .filter(|output| output.location == Location::External)
+    }
+
+    /// Collapses the modes of each output to one per resolution, with the highest refresh rate,
+    /// recording all of its refresh rates. Keeps the order of the resolutions.
+    pub(crate) fn dedup_modes(&mut self) {
+        for output in &mut self.outputs {
+            let mut modes: Vec<Mode> = Vec::new();
+            for mode in output.modes.drain(..) {
+                match modes
+                    .iter_mut()
+                    .find(|collapsed| collapsed.resolution == mode.resolution)
+                {
+                    Some(collapsed) => {
+                        collapsed
+                            .refresh_rates_millihz
+                            .push(mode.refresh_rate_millihz);
+                        collapsed.refresh_rate_millihz = collapsed
+                            .refresh_rate_millihz
+                            .max(mode.refresh_rate_millihz);
+                        collapsed.preferred |= mode.preferred;
+                        collapsed.active |= mode.active;
+                    }
+                    None => modes.push(Mode {
+                        refresh_rates_millihz: vec![mode.refresh_rate_millihz],
+                        ..mode
+                    }),
+                }
+            }
+            output.modes = modes;
+        }
+    }
+
+    pub(crate) fn find_output_by_name<'a>(&'a self, name: &str) -> Option<&'a Output> {
+        self.outputs.iter().find(|output| output.name == name)
+    }
+
+    fn find_output_by_name_mut(&mut self, name: &str) -> Option<&mut Output> {
+        self.outputs.iter_mut().find(|output| output.name == name)
+    }
+
+    /// Treats the named outputs as internal, for built-in panels with connector names
+    /// that look external, like `DP-1`.
+    pub(crate) fn mark_internal(&mut self, names: &[String]) {
+        for name in names {
+            match self.find_output_by_name_mut(name) {
+                Some(output) => output.location = Location::Internal,
+                None => log::warn!("Output {name:?} requested to be internal does not exist"),
+            }
+        }
+    }
+
+    /// Removes the modes of every output that are not allowed, so that they are never chosen.
+    /// Fails if an output has modes but none of them is allowed.
+    pub(crate) fn restrict_modes(&mut self, restrictions: &ModeRestrictions) -> Result<(), String> {
+        for output in &mut self.outputs {
+            let had_modes = !output.modes.is_empty();
+            output
+                .modes
+                .retain(|mode| restrictions.allows(&output.name, mode));
+            if had_modes && output.modes.is_empty() {
+                return Err(format!(
+                    "none of the modes of output {} is allowed by --blacklist-mode and --only-mode",
+                    output.name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Output {
     pub(crate) name: String,
     pub(crate) connected: bool,
     pub(crate) enabled: bool,
     pub(crate) modes: Vec<Mode>,
     pub(crate) location: Location,
+    /// Whether the output is powered on. An enabled output may be powered off,
+    /// e.g. by DPMS, and then needs to be powered on again rather than enabled.
+    pub(crate) powered: bool,
+    /// Resolution of the current mode, if the output is enabled and it is known.
+    pub(crate) current_resolution: Option<Resolution>,
+    /// Position of the top left corner, if the output is enabled and it is known.
+    pub(crate) current_position: Option<Position>,
+    /// Filter used for scaling the output, if it is known.
+    pub(crate) scale_filter: Option<ScaleFilter>,
+    /// Physical width and height in millimeters, if reported by the display.
+    pub(crate) physical_size_mm: Option<(u32, u32)>,
+    /// Whether the output may tear to lower latency, if it is known.
+    pub(crate) allow_tearing: Option<bool>,
+    /// How long before the next frame sway starts rendering, 0 if off, if it is known.
+    pub(crate) max_render_time_ms: Option<u32>,
+}
+
+impl Output {
+    /// A powered output without modes and with nothing else known about it, located by its name,
+    /// for tests to override the fields they care about with `..Output::test(..)`.
+    #[cfg(test)]
+    pub(crate) fn test(name: &str, connected: bool, enabled: bool) -> Output {
+        Output {
+            name: name.to_string(),
+            connected,
+            enabled,
+            modes: Vec::new(),
+            location: Location::from_output_name(name),
+            powered: true,
+            current_resolution: None,
+            current_position: None,
+            scale_filter: None,
+            physical_size_mm: None,
+            allow_tearing: None,
+            max_render_time_ms: None,
+        }
+    }
+
+    /// Resolution of the largest mode, which is usually the native one of the panel.
+    pub(crate) fn largest_resolution(&self) -> Option<Resolution> {
+        self.modes
+            .iter()
+            .map(|mode| mode.resolution)
+            .max_by_key(Resolution::area)
+    }
+
+    /// Whether the output can be enabled at a mode of its own, i.e. it is connected and
+    /// reports modes. Disconnected outputs may still be enabled on a saved CRTC configuration.
+    pub(crate) fn is_usable(&self) -> bool {
+        self.connected && !self.modes.is_empty()
+    }
+
+    /// Pixel density along the diagonal at the current resolution, if the physical size is known.
+    pub(crate) fn dpi(&self) -> Option<f32> {
+        self.dpi_at(self.current_resolution?)
+    }
+
+    /// Pixel density along the diagonal at the given resolution, if the physical size is known.
+    pub(crate) fn dpi_at(&self, resolution: Resolution) -> Option<f32> {
+        const MM_PER_INCH: f32 = 25.4;
+
+        let (mm_width, mm_height) = self.physical_size_mm?;
+        let diagonal_px = (resolution.width as f32).hypot(resolution.height as f32);
+        let diagonal_mm = (mm_width as f32).hypot(mm_height as f32);
+        Some(diagonal_px / diagonal_mm * MM_PER_INCH)
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Mode {
     pub(crate) resolution: Resolution,
     pub(crate) refresh_rate_millihz: u32,
+    /// Whether the output reports the mode as preferred, e.g. its native one.
+    pub(crate) preferred: bool,
+    /// Whether the output currently runs in this mode.
+    pub(crate) active: bool,
+    /// Every refresh rate of the resolution, once collapsed by [`Screen::dedup_modes`],
+    /// empty otherwise.
+    pub(crate) refresh_rates_millihz: Vec<u32>,
+}
+
+impl Mode {
+    /// Refresh rate rounded to whole hertz. Controllers compute refresh rates with different
+    /// precision, so this is what refresh rates should be compared by, e.g. 59.94Hz and 59.97Hz
+    /// both count as 60Hz.
+    pub(crate) fn refresh_rate_hz_rounded(&self) -> u32 {
+        round_millihz_to_hz(self.refresh_rate_millihz)
+    }
+}
+
+pub(crate) fn round_millihz_to_hz(millihz: u32) -> u32 {
+    millihz.saturating_add(500) / 1000
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -28,26 +221,474 @@ impl Resolution {
     pub(crate) fn area(&self) -> u64 {
         self.width as u64 * self.height as u64
     }
+
+    pub(crate) fn has_aspect_ratio_of(&self, other: Resolution) -> bool {
+        self.width as u64 * other.height as u64 == self.height as u64 * other.width as u64
+    }
+}
+
+impl std::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl std::str::FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or_else(|| format!("expected resolution in WxH format, got {s:?}"))?;
+        let width = width
+            .parse()
+            .map_err(|_| format!("invalid resolution width: {width:?}"))?;
+        let height = height
+            .parse()
+            .map_err(|_| format!("invalid resolution height: {height:?}"))?;
+        Ok(Resolution { width, height })
+    }
+}
+
+/// Polarity of the horizontal or vertical sync pulses of a mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SyncPolarity {
+    Positive,
+    Negative,
+}
+
+/// Timings of a mode to create for outputs without modes, given as an xrandr modeline like
+/// `Modeline "1920x1080_60.00" 148.50 1920 2008 2052 2200 1080 1084 1089 1125 +hsync +vsync`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Modeline {
+    pub(crate) name: String,
+    pub(crate) clock_khz: u32,
+    pub(crate) hdisplay: u32,
+    pub(crate) hsync_start: u32,
+    pub(crate) hsync_end: u32,
+    pub(crate) htotal: u32,
+    pub(crate) vdisplay: u32,
+    pub(crate) vsync_start: u32,
+    pub(crate) vsync_end: u32,
+    pub(crate) vtotal: u32,
+    /// `None` if the modeline leaves the polarity to the driver.
+    pub(crate) hsync: Option<SyncPolarity>,
+    pub(crate) vsync: Option<SyncPolarity>,
+}
+
+impl Modeline {
+    pub(crate) fn resolution(&self) -> Resolution {
+        Resolution {
+            width: self.hdisplay,
+            height: self.vdisplay,
+        }
+    }
+}
+
+impl std::str::FromStr for Modeline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace().peekable();
+        // The keyword is optional, so that both `cvt`'s output and xrandr's --newmode arguments parse.
+        words.next_if(|word| word.eq_ignore_ascii_case("modeline"));
+        let name = words
+            .next()
+            .ok_or_else(|| format!("expected modeline with a name, got {s:?}"))?
+            .trim_matches('"');
+        // RandR sends the length of the name and the timings as 16-bit and the clock in Hz
+        // as 32-bit integers, so larger ones cannot be created.
+        if name.len() > usize::from(u16::MAX) {
+            return Err(format!(
+                "modeline name must not be longer than {} bytes",
+                u16::MAX
+            ));
+        }
+
+        let clock_mhz = words
+            .next()
+            .ok_or_else(|| format!("expected modeline with a pixel clock, got {s:?}"))?;
+        let (integer_part, fractional_part) = clock_mhz.split_once('.').unwrap_or((clock_mhz, ""));
+        let clock_khz = (fractional_part.len() <= 3)
+            .then(|| {
+                let integer_part: u32 = integer_part.parse().ok()?;
+                let fractional_part: u32 = format!("{fractional_part:0<3}").parse().ok()?;
+                integer_part.checked_mul(1000)?.checked_add(fractional_part)
+            })
+            .flatten()
+            .filter(|&clock_khz| clock_khz <= u32::MAX / 1000)
+            .ok_or_else(|| format!("invalid modeline pixel clock: {clock_mhz:?}"))?;
+
+        let mut timings = [0; 8];
+        for timing in &mut timings {
+            let word = words
+                .next()
+                .ok_or_else(|| format!("expected modeline with 8 timings, got {s:?}"))?;
+            *timing = word
+                .parse::<u16>()
+                .map_err(|_| format!("invalid modeline timing: {word:?}"))?
+                .into();
+        }
+        let [
+            hdisplay,
+            hsync_start,
+            hsync_end,
+            htotal,
+            vdisplay,
+            vsync_start,
+            vsync_end,
+            vtotal,
+        ] = timings;
+        if !(hdisplay <= hsync_start && hsync_start <= hsync_end && hsync_end <= htotal) {
+            return Err(format!(
+                "expected horizontal modeline timings in increasing order, got {s:?}"
+            ));
+        }
+        if !(vdisplay <= vsync_start && vsync_start <= vsync_end && vsync_end <= vtotal) {
+            return Err(format!(
+                "expected vertical modeline timings in increasing order, got {s:?}"
+            ));
+        }
+
+        let mut hsync = None;
+        let mut vsync = None;
+        for flag in words {
+            match flag.to_ascii_lowercase().as_str() {
+                "+hsync" => hsync = Some(SyncPolarity::Positive),
+                "-hsync" => hsync = Some(SyncPolarity::Negative),
+                "+vsync" => vsync = Some(SyncPolarity::Positive),
+                "-vsync" => vsync = Some(SyncPolarity::Negative),
+                _ => return Err(format!("unsupported modeline flag: {flag:?}")),
+            }
+        }
+
+        Ok(Modeline {
+            name: name.to_string(),
+            clock_khz,
+            hdisplay,
+            hsync_start,
+            hsync_end,
+            htotal,
+            vdisplay,
+            vsync_start,
+            vsync_end,
+            vtotal,
+            hsync,
+            vsync,
+        })
+    }
+}
+
+/// Modes of an output, given as `OUTPUT=WxH@RATE`, or `OUTPUT=WxH@*` for any refresh rate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ModeFilter {
+    pub(crate) output: String,
+    pub(crate) resolution: Resolution,
+    /// Refresh rate in whole hertz, `None` for any.
+    pub(crate) refresh_rate_hz: Option<u32>,
+}
+
+impl ModeFilter {
+    pub(crate) fn matches(&self, output_name: &str, mode: &Mode) -> bool {
+        self.output == output_name
+            && self.resolution == mode.resolution
+            && self
+                .refresh_rate_hz
+                .is_none_or(|refresh_rate_hz| refresh_rate_hz == mode.refresh_rate_hz_rounded())
+    }
+}
+
+impl std::str::FromStr for ModeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (output, mode) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected mode in OUTPUT=WxH@RATE format, got {s:?}"))?;
+        let (resolution, refresh_rate) = mode
+            .split_once('@')
+            .ok_or_else(|| format!("expected mode in WxH@RATE format, got {mode:?}"))?;
+        let refresh_rate_hz = match refresh_rate {
+            "*" => None,
+            refresh_rate => Some(round_millihz_to_hz(parse_refresh_rate(refresh_rate)?)),
+        };
+        Ok(ModeFilter {
+            output: output.to_string(),
+            resolution: resolution.parse()?,
+            refresh_rate_hz,
+        })
+    }
+}
+
+/// Modes allowed to be chosen, as restricted by `--blacklist-mode` and `--only-mode`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ModeRestrictions {
+    /// Modes that must never be chosen.
+    pub(crate) blacklist: Vec<ModeFilter>,
+    /// Modes the outputs they name are restricted to.
+    pub(crate) whitelist: Vec<ModeFilter>,
+}
+
+impl ModeRestrictions {
+    pub(crate) fn allows(&self, output_name: &str, mode: &Mode) -> bool {
+        let matches = |filter: &ModeFilter| filter.matches(output_name, mode);
+        !self.blacklist.iter().any(matches)
+            && (!self.whitelists(output_name) || self.whitelist.iter().any(matches))
+    }
+
+    /// Whether any blacklisted or whitelisted mode names the output.
+    pub(crate) fn restricts(&self, output_name: &str) -> bool {
+        self.blacklist
+            .iter()
+            .chain(&self.whitelist)
+            .any(|filter| filter.output == output_name)
+    }
+
+    fn whitelists(&self, output_name: &str) -> bool {
+        self.whitelist
+            .iter()
+            .any(|filter| filter.output == output_name)
+    }
+
+    /// Chooses the mode to set explicitly on an output the restrictions name, among its allowed
+    /// modes, so that the controller cannot fall back to a disallowed refresh rate or mode.
+    /// Picks the highest refresh rate at `resolution`, or at the preferred or largest resolution
+    /// without one. Returns `None` when the output is unrestricted or has no such mode.
+    #[cfg(any(feature = "xrandr", feature = "sway", test))]
+    pub(crate) fn choose_mode<'a>(
+        &self,
+        output: &'a Output,
+        resolution: Option<Resolution>,
+    ) -> Option<&'a Mode> {
+        if !self.restricts(&output.name) {
+            return None;
+        }
+
+        let allowed = output
+            .modes
+            .iter()
+            .filter(|mode| self.allows(&output.name, mode));
+        match resolution {
+            Some(resolution) => allowed
+                .filter(|mode| mode.resolution == resolution)
+                .max_by_key(|mode| (mode.preferred, mode.refresh_rate_millihz)),
+            None => allowed.max_by_key(|mode| {
+                (
+                    mode.preferred,
+                    mode.resolution.area(),
+                    mode.refresh_rate_millihz,
+                )
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Position {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+}
+
+/// Filter used when sampling the picture of a scaled output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum ScaleFilter {
+    Bilinear,
+    /// Keeps pixels sharp, e.g. for pixel art and terminals.
+    Nearest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Color {
+    pub(crate) red: u8,
+    pub(crate) green: u8,
+    pub(crate) blue: u8,
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_color = || format!("expected color in #RRGGBB format, got {s:?}");
+
+        let hex = s.strip_prefix('#').ok_or_else(bad_color)?;
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(bad_color());
+        }
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| bad_color());
+
+        Ok(Color {
+            red: component(0)?,
+            green: component(2)?,
+            blue: component(4)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Gamma {
+    pub(crate) red: f32,
+    pub(crate) green: f32,
+    pub(crate) blue: f32,
+}
+
+impl Gamma {
+    #[cfg(feature = "randr")]
+    pub(crate) const IDENTITY: Gamma = Gamma {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+}
+
+impl std::str::FromStr for Gamma {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let components = s
+            .split(':')
+            .map(|component| {
+                let value: f32 = component
+                    .parse()
+                    .map_err(|_| format!("invalid gamma component: {component:?}"))?;
+                if (0.1..=10.0).contains(&value) {
+                    Ok(value)
+                } else {
+                    Err(format!(
+                        "gamma component {value} is not in range 0.1..=10.0"
+                    ))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match components[..] {
+            [red, green, blue] => Ok(Gamma { red, green, blue }),
+            _ => Err(format!("expected gamma in R:G:B format, got {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct ColorAdjustment {
+    pub(crate) gamma: Option<Gamma>,
+    pub(crate) brightness: Option<f32>,
+}
+
+impl ColorAdjustment {
+    pub(crate) fn is_requested(&self) -> bool {
+        self.gamma.is_some() || self.brightness.is_some()
+    }
+}
+
+pub(crate) fn parse_brightness(s: &str) -> Result<f32, String> {
+    let value: f32 = s
+        .parse()
+        .map_err(|_| format!("invalid brightness: {s:?}"))?;
+    if (0.1..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("brightness {value} is not in range 0.1..=1.0"))
+    }
+}
+
+/// Parses a pixel density, which must be a positive number of dots per inch.
+pub(crate) fn parse_dpi(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|_| format!("invalid DPI: {s:?}"))?;
+    if value.is_finite() && value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("DPI {value} is not a positive number"))
+    }
+}
+
+/// Parses a maximum render time for sway, either in milliseconds or `off`, which is returned as 0.
+pub(crate) fn parse_max_render_time(s: &str) -> Result<u32, String> {
+    if s == "off" {
+        return Ok(0);
+    }
+    match s.parse() {
+        Ok(0) | Err(_) => Err(format!(
+            "invalid max render time: {s:?}, expected milliseconds or off"
+        )),
+        Ok(milliseconds) => Ok(milliseconds),
+    }
+}
+
+/// Parses a refresh rate into millihertz, either in Hz (`60`, `59.94Hz`) or in mHz (`60000mHz`).
+/// Bare numbers of at least 1000 are taken as millihertz for backward compatibility.
+pub(crate) fn parse_refresh_rate(s: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid refresh rate: {s:?}, expected e.g. 60Hz or 60000mHz");
+
+    if let Some(millihertz) = s.strip_suffix("mHz") {
+        return millihertz.parse().map_err(|_| invalid());
+    }
+
+    let (hertz, has_unit) = match s.strip_suffix("Hz") {
+        Some(hertz) => (hertz, true),
+        None => (s, false),
+    };
+    let hertz: f64 = hertz.parse().map_err(|_| invalid())?;
+    if !hertz.is_finite() || hertz < 0.0 {
+        return Err(invalid());
+    }
+
+    if !has_unit && hertz >= 1000.0 {
+        log::warn!("Refresh rate {s} without a unit is taken as millihertz, use {s}mHz instead");
+        return u32::try_from(hertz as u64).map_err(|_| invalid());
+    }
+
+    let millihertz = (hertz * 1000.0).round();
+    if millihertz > u32::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(millihertz as u32)
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub(crate) enum Location {
     Internal,
     External,
+    /// Not backed by a physical display, e.g. an output of a nested wlroots compositor.
+    Virtual,
 }
 
 impl Location {
+    #[cfg(any(feature = "sway", feature = "randr", test))]
     pub(crate) fn from_output_name(name: &str) -> Location {
+        Self::try_from_output_name(name)
+            .unwrap_or_else(|| unreachable!("FIXME: output with unknown location: {}", name))
+    }
+
+    pub(crate) fn try_from_output_name(name: &str) -> Option<Location> {
         if name.starts_with("eDP-") || name.starts_with("LVDS-") {
-            Location::Internal
+            Some(Location::Internal)
         } else if name.starts_with("DP-")
+            // AMD drivers may also name DisplayPort outputs DP-A-0-0, DP-B-0-0,
+            // which are covered by the prefix above, or DisplayPort-0.
+            || name.starts_with("DisplayPort-")
+            || name.starts_with("DISPLAYPORT-")
+            // Also covers the DVI-D-, DVI-A- and DVI-I- variants used by some drivers.
             || name.starts_with("DVI-")
             || name.starts_with("HDMI-")
             || name.starts_with("VGA-")
+            // Outputs of Thunderbolt and USB-C docking stations.
+            || name.starts_with("THUNDERBOLT-")
+            || name.starts_with("USB-C-")
+            || name.starts_with("USBC-")
         {
-            Location::External
+            Some(Location::External)
+        } else if name.starts_with("HEADLESS-")
+            || name.starts_with("VIRTUAL-")
+            || name.starts_with("WL-")
+        {
+            Some(Location::Virtual)
         } else {
-            unreachable!("FIXME: output with unknown location: {}", name);
+            None
         }
     }
 }
@@ -66,6 +707,218 @@ mod tests {
         assert_eq!(Location::from_output_name("VGA-1"), Location::External);
     }
 
+    #[test]
+    fn test_location_from_docking_station_output_name() {
+        assert_eq!(
+            Location::from_output_name("THUNDERBOLT-1"),
+            Location::External
+        );
+        assert_eq!(Location::from_output_name("USB-C-1"), Location::External);
+        assert_eq!(Location::from_output_name("USBC-2"), Location::External);
+    }
+
+    #[test]
+    fn test_location_from_virtual_output_name() {
+        assert_eq!(Location::from_output_name("HEADLESS-1"), Location::Virtual);
+        assert_eq!(Location::from_output_name("VIRTUAL-1"), Location::Virtual);
+        assert_eq!(Location::from_output_name("WL-1"), Location::Virtual);
+    }
+
+    #[test]
+    fn test_location_from_dvi_variant_output_name() {
+        assert_eq!(Location::from_output_name("DVI-D-1"), Location::External);
+        assert_eq!(Location::from_output_name("DVI-A-1"), Location::External);
+        assert_eq!(Location::from_output_name("DVI-I-1"), Location::External);
+    }
+
+    #[test]
+    fn test_location_from_amd_displayport_output_name() {
+        assert_eq!(Location::from_output_name("DP-A-0-0"), Location::External);
+        assert_eq!(Location::from_output_name("DP-B-1-0"), Location::External);
+        assert_eq!(
+            Location::from_output_name("DisplayPort-0"),
+            Location::External
+        );
+        assert_eq!(
+            Location::from_output_name("DISPLAYPORT-1"),
+            Location::External
+        );
+    }
+
+    #[test]
+    fn test_location_try_from_output_name() {
+        assert_eq!(
+            Location::try_from_output_name("eDP-1"),
+            Some(Location::Internal)
+        );
+        assert_eq!(
+            Location::try_from_output_name("HDMI-1"),
+            Some(Location::External)
+        );
+        assert_eq!(Location::try_from_output_name("?DMI-1"), None);
+    }
+
+    #[test]
+    fn connected_outputs_skips_disconnected() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output::test("eDP-1", true, false),
+                Output::test("HDMI-1", false, true),
+                Output::test("DP-1", true, true),
+            ],
+        };
+
+        // Act
+        let names: Vec<_> = screen
+            .connected_outputs()
+            .map(|output| output.name.as_str())
+            .collect();
+
+        // Assert
+        assert_eq!(names, ["eDP-1", "DP-1"]);
+    }
+
+    #[test]
+    fn enabled_outputs_skips_disabled() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output::test("eDP-1", true, false),
+                Output::test("HDMI-1", false, true),
+                Output::test("DP-1", true, true),
+            ],
+        };
+
+        // Act
+        let names: Vec<_> = screen
+            .enabled_outputs()
+            .map(|output| output.name.as_str())
+            .collect();
+
+        // Assert
+        assert_eq!(names, ["HDMI-1", "DP-1"]);
+    }
+
+    #[test]
+    fn dedup_modes_collapses_refresh_rates_of_a_resolution() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz, preferred| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let mut output = Output::test("eDP-1", true, true);
+        output.modes = [60020, 60010, 59970, 59960, 59930, 48020]
+            .into_iter()
+            .enumerate()
+            .map(|(i, refresh_rate_millihz)| mode(1920, 1080, refresh_rate_millihz, i == 0))
+            .chain([mode(1280, 720, 60000, false)])
+            .collect();
+        let mut screen = Screen {
+            outputs: vec![output],
+        };
+
+        // Act
+        screen.dedup_modes();
+
+        // Assert
+        assert_eq!(
+            screen.outputs[0].modes,
+            [
+                Mode {
+                    refresh_rates_millihz: vec![60020, 60010, 59970, 59960, 59930, 48020],
+                    ..mode(1920, 1080, 60020, true)
+                },
+                Mode {
+                    refresh_rates_millihz: vec![60000],
+                    ..mode(1280, 720, 60000, false)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn connectivity_eq_ignores_modes_order_and_enabled() {
+        // Arrange
+        let mut hdmi = Output::test("HDMI-1", true, true);
+        hdmi.modes.push(Mode {
+            resolution: Resolution {
+                width: 1920,
+                height: 1080,
+            },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        });
+        let screen = Screen {
+            outputs: vec![Output::test("eDP-1", true, false), hdmi],
+        };
+        let other = Screen {
+            outputs: vec![
+                Output::test("HDMI-1", true, false),
+                Output::test("eDP-1", true, true),
+            ],
+        };
+
+        // Act & Assert
+        assert!(screen.connectivity_eq(&other));
+        assert_ne!(screen, other);
+    }
+
+    #[test]
+    fn connectivity_eq_detects_connects_and_disconnects() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output::test("eDP-1", true, true),
+                Output::test("HDMI-1", false, false),
+            ],
+        };
+        let connected = Screen {
+            outputs: vec![
+                Output::test("eDP-1", true, true),
+                Output::test("HDMI-1", true, false),
+            ],
+        };
+        let undocked = Screen {
+            outputs: vec![Output::test("eDP-1", true, true)],
+        };
+
+        // Act & Assert
+        assert!(!screen.connectivity_eq(&connected));
+        assert!(!screen.connectivity_eq(&undocked));
+    }
+
+    #[test]
+    fn internal_and_external_outputs_filter_by_location() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output::test("eDP-1", true, true),
+                Output::test("HDMI-1", false, false),
+                Output::test("VIRTUAL-1", true, true),
+                Output::test("DP-1", true, false),
+            ],
+        };
+
+        // Act
+        let names = |outputs: &mut dyn Iterator<Item = &Output>| {
+            outputs
+                .map(|output| output.name.clone())
+                .collect::<Vec<_>>()
+        };
+        let internal = names(&mut screen.internal_outputs());
+        let external = names(&mut screen.external_outputs());
+
+        // Assert
+        assert_eq!(internal, ["eDP-1"]);
+        assert_eq!(external, ["HDMI-1", "DP-1"]);
+    }
+
     #[test]
     fn large_resolution_area() {
         assert_eq!(
@@ -77,4 +930,414 @@ mod tests {
             18446744065119617025
         );
     }
+
+    #[test]
+    fn test_resolution_from_str() {
+        assert_eq!(
+            "1920x1080".parse::<Resolution>(),
+            Ok(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+        assert!("1920".parse::<Resolution>().is_err());
+        assert!("1920x".parse::<Resolution>().is_err());
+        assert!("x1080".parse::<Resolution>().is_err());
+        assert!("1920x1080x1".parse::<Resolution>().is_err());
+        assert!("-1920x1080".parse::<Resolution>().is_err());
+    }
+
+    #[test]
+    fn test_modeline_from_str() {
+        assert_eq!(
+            r#"Modeline "1920x1080_60.00" 148.50 1920 2008 2052 2200 1080 1084 1089 1125 +hsync +vsync"#
+                .parse::<Modeline>(),
+            Ok(Modeline {
+                name: "1920x1080_60.00".to_string(),
+                clock_khz: 148_500,
+                hdisplay: 1920,
+                hsync_start: 2008,
+                hsync_end: 2052,
+                htotal: 2200,
+                vdisplay: 1080,
+                vsync_start: 1084,
+                vsync_end: 1089,
+                vtotal: 1125,
+                hsync: Some(SyncPolarity::Positive),
+                vsync: Some(SyncPolarity::Positive),
+            })
+        );
+        assert_eq!(
+            "1024x768 63.5 1024 1072 1176 1328 768 771 775 798 -HSync"
+                .parse::<Modeline>()
+                .map(|modeline| (modeline.clock_khz, modeline.hsync, modeline.vsync)),
+            Ok((63_500, Some(SyncPolarity::Negative), None))
+        );
+        assert!("Modeline".parse::<Modeline>().is_err());
+        assert!(
+            "1920x1080 148.50 1920 2008 2052 2200 1080 1084 1089"
+                .parse::<Modeline>()
+                .is_err()
+        );
+        assert!(
+            "1920x1080 148.5000 1920 2008 2052 2200 1080 1084 1089 1125"
+                .parse::<Modeline>()
+                .is_err()
+        );
+        assert!(
+            "1920x1080 148.50 1920 2008 2052 2200 1080 1084 1089 1125 interlace"
+                .parse::<Modeline>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn modeline_from_str_rejects_values_randr_cannot_create() {
+        assert!(
+            "65535x1080 148.50 65535 65535 65535 65535 1080 1084 1089 1125"
+                .parse::<Modeline>()
+                .is_ok()
+        );
+        assert!(
+            "65536x1080 148.50 65536 65536 65536 65536 1080 1084 1089 1125"
+                .parse::<Modeline>()
+                .is_err()
+        );
+        assert_eq!(
+            "1920x1080 4294.967 1920 2008 2052 2200 1080 1084 1089 1125"
+                .parse::<Modeline>()
+                .map(|modeline| modeline.clock_khz),
+            Ok(4_294_967)
+        );
+        assert!(
+            "1920x1080 4294.968 1920 2008 2052 2200 1080 1084 1089 1125"
+                .parse::<Modeline>()
+                .is_err()
+        );
+        let name = "x".repeat(usize::from(u16::MAX) + 1);
+        assert!(
+            format!("{name} 148.50 1920 2008 2052 2200 1080 1084 1089 1125")
+                .parse::<Modeline>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn modeline_from_str_rejects_timings_out_of_order() {
+        assert!(
+            "1920x1080 148.50 1920 1920 1920 1920 1080 1080 1080 1080"
+                .parse::<Modeline>()
+                .is_ok()
+        );
+        assert!(
+            "1920x1080 148.50 1920 1900 2052 2200 1080 1084 1089 1125"
+                .parse::<Modeline>()
+                .is_err()
+        );
+        assert!(
+            "1920x1080 148.50 1920 2008 2000 2200 1080 1084 1089 1125"
+                .parse::<Modeline>()
+                .is_err()
+        );
+        assert!(
+            "1920x1080 148.50 1920 2008 2052 2050 1080 1084 1089 1125"
+                .parse::<Modeline>()
+                .is_err()
+        );
+        assert!(
+            "1920x1080 148.50 1920 2008 2052 2200 1080 1084 1089 1088"
+                .parse::<Modeline>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_color_from_str() {
+        assert_eq!(
+            "#00ff7F".parse::<Color>(),
+            Ok(Color {
+                red: 0,
+                green: 255,
+                blue: 127,
+            })
+        );
+        assert!("00ff7f".parse::<Color>().is_err());
+        assert!("#00ff7".parse::<Color>().is_err());
+        assert!("#00ff7f0".parse::<Color>().is_err());
+        assert!("#00gg7f".parse::<Color>().is_err());
+        assert!("#+0ff7f".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_color_to_string() {
+        assert_eq!(
+            Color {
+                red: 0,
+                green: 255,
+                blue: 10,
+            }
+            .to_string(),
+            "#00ff0a"
+        );
+    }
+
+    #[test]
+    fn test_gamma_from_str() {
+        assert_eq!(
+            "1.0:0.8:2".parse::<Gamma>(),
+            Ok(Gamma {
+                red: 1.0,
+                green: 0.8,
+                blue: 2.0,
+            })
+        );
+        assert!("1.0:0.8".parse::<Gamma>().is_err());
+        assert!("1.0:0.8:0.8:0.8".parse::<Gamma>().is_err());
+        assert!("1.0:x:0.8".parse::<Gamma>().is_err());
+        assert!("1.0:0.05:0.8".parse::<Gamma>().is_err());
+        assert!("1.0:10.5:0.8".parse::<Gamma>().is_err());
+    }
+
+    #[test]
+    fn test_parse_refresh_rate() {
+        assert_eq!(parse_refresh_rate("60"), Ok(60000));
+        assert_eq!(parse_refresh_rate("60Hz"), Ok(60000));
+        assert_eq!(parse_refresh_rate("59.94Hz"), Ok(59940));
+        assert_eq!(parse_refresh_rate("60000mHz"), Ok(60000));
+        assert_eq!(parse_refresh_rate("50000"), Ok(50000));
+        assert!(parse_refresh_rate("60.5mHz").is_err());
+        assert!(parse_refresh_rate("-60Hz").is_err());
+        assert!(parse_refresh_rate("60 Hz").is_err());
+        assert!(parse_refresh_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_max_render_time() {
+        assert_eq!(parse_max_render_time("off"), Ok(0));
+        assert_eq!(parse_max_render_time("7"), Ok(7));
+        assert!(parse_max_render_time("0").is_err());
+        assert!(parse_max_render_time("-1").is_err());
+        assert!(parse_max_render_time("7ms").is_err());
+    }
+
+    #[test]
+    fn test_parse_brightness() {
+        assert_eq!(parse_brightness("0.5"), Ok(0.5));
+        assert_eq!(parse_brightness("1"), Ok(1.0));
+        assert!(parse_brightness("0.05").is_err());
+        assert!(parse_brightness("1.5").is_err());
+        assert!(parse_brightness("bright").is_err());
+    }
+
+    #[test]
+    fn test_parse_dpi() {
+        assert_eq!(parse_dpi("96"), Ok(96.0));
+        assert_eq!(parse_dpi("110.5"), Ok(110.5));
+        assert!(parse_dpi("0").is_err());
+        assert!(parse_dpi("-96").is_err());
+        assert!(parse_dpi("NaN").is_err());
+        assert!(parse_dpi("inf").is_err());
+        assert!(parse_dpi("dense").is_err());
+    }
+
+    #[test]
+    fn test_mode_refresh_rate_hz_rounded() {
+        let mode = |refresh_rate_millihz| Mode {
+            resolution: Resolution {
+                width: 1920,
+                height: 1080,
+            },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+
+        assert_eq!(mode(59940).refresh_rate_hz_rounded(), 60);
+        assert_eq!(mode(59970).refresh_rate_hz_rounded(), 60);
+        assert_eq!(mode(60020).refresh_rate_hz_rounded(), 60);
+        assert_eq!(mode(59500).refresh_rate_hz_rounded(), 60);
+        assert_eq!(mode(59499).refresh_rate_hz_rounded(), 59);
+        assert_eq!(mode(0).refresh_rate_hz_rounded(), 0);
+    }
+
+    #[test]
+    fn test_mode_filter_from_str() {
+        assert_eq!(
+            "HDMI-1=1920x1080@50".parse::<ModeFilter>(),
+            Ok(ModeFilter {
+                output: "HDMI-1".to_string(),
+                resolution: Resolution {
+                    width: 1920,
+                    height: 1080
+                },
+                refresh_rate_hz: Some(50),
+            })
+        );
+        assert_eq!(
+            "HDMI-1=1920x1080@*"
+                .parse::<ModeFilter>()
+                .map(|filter| filter.refresh_rate_hz),
+            Ok(None)
+        );
+        assert!("1920x1080@50".parse::<ModeFilter>().is_err());
+        assert!("HDMI-1=1920x1080".parse::<ModeFilter>().is_err());
+        assert!("HDMI-1=1920@50".parse::<ModeFilter>().is_err());
+        assert!("HDMI-1=1920x1080@fast".parse::<ModeFilter>().is_err());
+    }
+
+    #[test]
+    fn blacklisted_modes_are_removed() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name: &str| Output {
+            modes: vec![
+                mode(1920, 1080, 60000),
+                mode(1920, 1080, 50000),
+                mode(1280, 720, 60000),
+                mode(1280, 720, 50000),
+            ],
+            ..Output::test(name, true, false)
+        };
+        let test_screen = || Screen {
+            outputs: vec![output("eDP-1"), output("HDMI-1")],
+        };
+        let mut screen = test_screen();
+        let restrictions = ModeRestrictions {
+            blacklist: vec![
+                "HDMI-1=1920x1080@50".parse().unwrap(),
+                "HDMI-1=1280x720@*".parse().unwrap(),
+            ],
+            whitelist: Vec::new(),
+        };
+        let blacklisting_all = ModeRestrictions {
+            blacklist: vec![
+                "HDMI-1=1920x1080@*".parse().unwrap(),
+                "HDMI-1=1280x720@*".parse().unwrap(),
+            ],
+            whitelist: Vec::new(),
+        };
+
+        // Act
+        let result = screen.restrict_modes(&restrictions);
+        let result_blacklisting_all = test_screen().restrict_modes(&blacklisting_all);
+
+        // Assert
+        assert_eq!(result, Ok(()));
+        assert_eq!(screen.outputs[0].modes.len(), 4);
+        assert_eq!(screen.outputs[1].modes, [mode(1920, 1080, 60000)]);
+        assert!(result_blacklisting_all.is_err());
+    }
+
+    #[test]
+    #[cfg(any(feature = "xrandr", feature = "sway"))]
+    fn choose_mode_pins_allowed_mode_of_restricted_outputs() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz, preferred| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name: &str| Output {
+            modes: vec![
+                mode(1920, 1080, 60000, true),
+                mode(1920, 1080, 50000, false),
+                mode(1280, 720, 60000, false),
+            ],
+            ..Output::test(name, true, false)
+        };
+        let (internal, external) = (output("eDP-1"), output("HDMI-1"));
+        let restrictions = ModeRestrictions {
+            blacklist: vec!["HDMI-1=1920x1080@60".parse().unwrap()],
+            whitelist: Vec::new(),
+        };
+        let full_hd = Resolution {
+            width: 1920,
+            height: 1080,
+        };
+
+        // Act
+        let unrestricted = restrictions.choose_mode(&internal, Some(full_hd));
+        let at_resolution = restrictions.choose_mode(&external, Some(full_hd));
+        let without_resolution = restrictions.choose_mode(&external, None);
+
+        // Assert
+        assert_eq!(unrestricted, None);
+        assert_eq!(at_resolution, Some(&mode(1920, 1080, 50000, false)));
+        assert_eq!(without_resolution, Some(&mode(1920, 1080, 50000, false)));
+    }
+
+    #[test]
+    fn whitelisted_modes_are_the_only_ones_left() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name: &str| Output {
+            modes: vec![
+                mode(3840, 2160, 60000),
+                mode(3840, 2160, 24000),
+                mode(3840, 2160, 23976),
+                mode(1920, 1080, 24000),
+            ],
+            ..Output::test(name, true, false)
+        };
+        let test_screen = || Screen {
+            outputs: vec![output("eDP-1"), output("HDMI-1")],
+        };
+        let restrictions = |whitelist: &str| ModeRestrictions {
+            blacklist: Vec::new(),
+            whitelist: vec![whitelist.parse().unwrap()],
+        };
+        let mut screen = test_screen();
+
+        // Act
+        let result = screen.restrict_modes(&restrictions("HDMI-1=3840x2160@24"));
+        let result_without_match = test_screen().restrict_modes(&restrictions("eDP-1=1280x720@*"));
+
+        // Assert
+        assert_eq!(result, Ok(()));
+        assert_eq!(screen.outputs[0].modes.len(), 4);
+        assert_eq!(
+            screen.outputs[1].modes,
+            [mode(3840, 2160, 24000), mode(3840, 2160, 23976)]
+        );
+        assert!(result_without_match.is_err());
+    }
+
+    #[test]
+    fn test_output_dpi() {
+        // Arrange
+        let output = |current_resolution, physical_size_mm| Output {
+            current_resolution,
+            physical_size_mm,
+            ..Output::test("eDP-1", true, true)
+        };
+        let resolution = Resolution {
+            width: 1920,
+            height: 1080,
+        };
+
+        // Act
+        let dpi = output(Some(resolution), Some((344, 194))).dpi();
+        let dpi_without_size = output(Some(resolution), None).dpi();
+        let dpi_when_disabled = output(None, Some((344, 194))).dpi();
+
+        // Assert
+        assert!((dpi.unwrap() - 141.7).abs() < 0.05);
+        assert_eq!(dpi_without_size, None);
+        assert_eq!(dpi_when_disabled, None);
+    }
 }
@@ -0,0 +1,131 @@
+use crate::screen::{Modeline, Resolution, SyncPolarity};
+
+/// Refresh rate of synthesized modes, which displays without EDID are most likely to accept.
+const REFRESH_RATE_HZ: u32 = 60;
+
+const H_GRANULARITY: u32 = 8;
+const MIN_V_PORCH: u32 = 3;
+const MIN_V_BACK_PORCH: u32 = 6;
+const MIN_VSYNC_AND_BACK_PORCH_US: f64 = 550.0;
+const HSYNC_PERCENTAGE: u32 = 8;
+const CLOCK_STEP_KHZ: u32 = 250;
+/// Blanking formula gradient and offset, already scaled by the weighting factor.
+const M_PRIME: f64 = 300.0;
+const C_PRIME: f64 = 30.0;
+
+/// Computes the timings of a mode of the resolution with the VESA Coordinated Video Timings
+/// formula and normal blanking at [`REFRESH_RATE_HZ`], like `cvt` does, naming it after the
+/// resolution. Modes use negative horizontal and positive vertical sync. The blanking is computed
+/// from the width rounded down to the character cell, but the full width stays visible.
+pub(super) fn modeline(resolution: Resolution) -> Modeline {
+    let hdisplay = resolution.width;
+    let hdisplay_rounded = hdisplay - hdisplay % H_GRANULARITY;
+    let vdisplay = resolution.height;
+
+    // The length of the vertical sync encodes the aspect ratio.
+    let is_aspect = |width: u32, height: u32| {
+        vdisplay.is_multiple_of(height) && vdisplay * width / height == hdisplay_rounded
+    };
+    let vsync = if is_aspect(4, 3) {
+        4
+    } else if is_aspect(16, 9) {
+        5
+    } else if is_aspect(16, 10) {
+        6
+    } else if is_aspect(5, 4) || is_aspect(15, 9) {
+        7
+    } else {
+        10
+    };
+
+    let hperiod_us = (1_000_000.0 / REFRESH_RATE_HZ as f64 - MIN_VSYNC_AND_BACK_PORCH_US)
+        / (vdisplay + MIN_V_PORCH) as f64;
+    let vsync_and_back_porch =
+        ((MIN_VSYNC_AND_BACK_PORCH_US / hperiod_us) as u32 + 1).max(vsync + MIN_V_BACK_PORCH);
+    let vtotal = vdisplay + vsync_and_back_porch + MIN_V_PORCH;
+
+    let hblank_percentage = (C_PRIME - M_PRIME * hperiod_us / 1000.0).max(20.0);
+    let hblank = (hdisplay_rounded as f64 * hblank_percentage / (100.0 - hblank_percentage)) as u32;
+    let hblank = hblank - hblank % (2 * H_GRANULARITY);
+    let htotal = hdisplay_rounded + hblank;
+    let hsync_end = hdisplay_rounded + hblank / 2;
+    let hsync_start = hsync_end - htotal * HSYNC_PERCENTAGE / 100;
+    let hsync_start = hsync_start + H_GRANULARITY - hsync_start % H_GRANULARITY;
+
+    let clock_khz = (htotal as f64 * 1000.0 / hperiod_us) as u32;
+
+    Modeline {
+        name: resolution.to_string(),
+        clock_khz: clock_khz - clock_khz % CLOCK_STEP_KHZ,
+        hdisplay,
+        hsync_start,
+        hsync_end,
+        htotal,
+        vdisplay,
+        vsync_start: vdisplay + MIN_V_PORCH,
+        vsync_end: vdisplay + MIN_V_PORCH + vsync,
+        vtotal,
+        hsync: Some(SyncPolarity::Negative),
+        vsync: Some(SyncPolarity::Positive),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modeline_matches_cvt_tool() {
+        // Arrange
+        let full_hd = Resolution {
+            width: 1920,
+            height: 1080,
+        };
+        let xga = Resolution {
+            width: 1024,
+            height: 768,
+        };
+
+        // Act
+        let full_hd_modeline = modeline(full_hd);
+        let xga_modeline = modeline(xga);
+
+        // Assert
+        // Modeline "1920x1080_60.00"  173.00  1920 2048 2248 2576  1080 1083 1088 1120 -hsync +vsync
+        assert_eq!(
+            full_hd_modeline,
+            Modeline {
+                name: "1920x1080".to_string(),
+                clock_khz: 173_000,
+                hdisplay: 1920,
+                hsync_start: 2048,
+                hsync_end: 2248,
+                htotal: 2576,
+                vdisplay: 1080,
+                vsync_start: 1083,
+                vsync_end: 1088,
+                vtotal: 1120,
+                hsync: Some(SyncPolarity::Negative),
+                vsync: Some(SyncPolarity::Positive),
+            }
+        );
+        // Modeline "1024x768_60.00"  63.50  1024 1072 1176 1328  768 771 775 798 -hsync +vsync
+        assert_eq!(
+            xga_modeline,
+            Modeline {
+                name: "1024x768".to_string(),
+                clock_khz: 63_500,
+                hdisplay: 1024,
+                hsync_start: 1072,
+                hsync_end: 1176,
+                htotal: 1328,
+                vdisplay: 768,
+                vsync_start: 771,
+                vsync_end: 775,
+                vtotal: 798,
+                hsync: Some(SyncPolarity::Negative),
+                vsync: Some(SyncPolarity::Positive),
+            }
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use super::SwitchError;
+use crate::screen::{Output, Resolution, Screen};
+use crate::switch::{OwnedSwitchPlan, SwitchOptions, SwitchPlan};
+
+/// Controller for tests, recording the switches and applying them to the outputs it reports
+/// instead of carrying them out.
+pub(super) struct FakeController {
+    pub(super) screen: Screen,
+    /// Fail to switch, like the real controllers do when e.g. their command fails.
+    pub(super) fail: bool,
+    pub(super) switches: Vec<(OwnedSwitchPlan, Option<Resolution>)>,
+    /// Whether each switch scaled mirrored outputs, and from which output.
+    pub(super) mirror_scaling: Vec<(bool, Option<String>)>,
+}
+
+impl FakeController {
+    pub(super) fn switch_outputs(
+        &mut self,
+        switch_plan: &SwitchPlan,
+        resolution: Option<Resolution>,
+        options: &SwitchOptions,
+    ) -> Result<(), SwitchError> {
+        if self.fail {
+            return Err(SwitchError::Fake);
+        }
+        self.switches
+            .push((OwnedSwitchPlan::from(switch_plan), resolution));
+        self.mirror_scaling
+            .push((options.scale_mirrored, options.scale_from.clone()));
+
+        for output in &switch_plan.outputs_to_disable {
+            let output = self.output_mut(&output.name);
+            output.enabled = false;
+            output.current_resolution = None;
+            output.current_position = None;
+            for mode in &mut output.modes {
+                mode.active = false;
+            }
+        }
+        for output in &switch_plan.outputs_to_enable {
+            // Pick the mode the way the real controllers do: an allowed one if the output is
+            // restricted, otherwise the preferred one at the resolution, or the largest one.
+            let chosen_mode = options
+                .mode_restrictions
+                .choose_mode(output, resolution)
+                .or_else(|| {
+                    output
+                        .modes
+                        .iter()
+                        .filter(|mode| {
+                            resolution.is_none_or(|resolution| mode.resolution == resolution)
+                        })
+                        .max_by_key(|mode| {
+                            (
+                                mode.preferred,
+                                mode.resolution.area(),
+                                mode.refresh_rate_millihz,
+                            )
+                        })
+                })
+                .cloned();
+            let position = options.layout.position(&output.name);
+            let output = self.output_mut(&output.name);
+            output.enabled = true;
+            output.current_resolution = chosen_mode.as_ref().map(|mode| mode.resolution);
+            output.current_position = Some(position);
+            for mode in &mut output.modes {
+                mode.active = chosen_mode.as_ref() == Some(mode);
+            }
+        }
+        Ok(())
+    }
+
+    fn output_mut(&mut self, name: &str) -> &mut Output {
+        self.screen
+            .outputs
+            .iter_mut()
+            .find(|output| output.name == name)
+            .expect("switched outputs should be among the fake ones")
+    }
+}
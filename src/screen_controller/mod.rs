@@ -1,13 +1,24 @@
+#[cfg(any(feature = "xrandr", feature = "randr"))]
+mod cvt;
+#[cfg(test)]
+mod fake;
 #[cfg(feature = "randr")]
 mod randr;
 #[cfg(feature = "sway")]
 mod sway;
+#[cfg(any(feature = "xrandr", feature = "sway"))]
 mod utils;
 #[cfg(feature = "xrandr")]
 mod xrandr;
 
 use crate::screen::{Resolution, Screen};
-use crate::switch::SwitchPlan;
+use crate::switch::{SwitchOptions, SwitchPlan};
+use std::fmt;
+
+#[cfg(not(any(feature = "xrandr", feature = "sway", feature = "randr")))]
+compile_error!(
+    "no screen controller is compiled in: enable at least one of the \"xrandr\", \"sway\" or \"randr\" features"
+);
 
 #[derive(Copy, Clone, Debug, clap::ValueEnum)]
 pub(super) enum ScreenControllerType {
@@ -19,6 +30,199 @@ pub(super) enum ScreenControllerType {
     Randr,
 }
 
+impl ScreenControllerType {
+    /// Names of the controllers compiled into this binary, as accepted by `--controller`.
+    pub(super) fn compiled_in() -> Vec<String> {
+        use clap::ValueEnum;
+
+        Self::value_variants()
+            .iter()
+            .filter_map(|variant| variant.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect()
+    }
+
+    /// Whether the controller talks to an X server, which under Wayland is Xwayland or
+    /// a nested server, so that it cannot reconfigure the real outputs.
+    pub(super) fn is_x11(self) -> bool {
+        match self {
+            #[cfg(feature = "xrandr")]
+            ScreenControllerType::Xrandr => true,
+            #[cfg(feature = "sway")]
+            ScreenControllerType::Sway => false,
+            #[cfg(feature = "randr")]
+            ScreenControllerType::Randr => true,
+        }
+    }
+
+    /// Actions this controller supports, as reported by `--capabilities`.
+    pub(super) fn actions(self) -> &'static [&'static str] {
+        match self {
+            #[cfg(feature = "xrandr")]
+            ScreenControllerType::Xrandr => &[
+                "mirror",
+                "layout",
+                "rotate",
+                "gamma",
+                "brightness",
+                "scale-mirrored",
+                "scale-filter",
+                "primary",
+                "auto-scale-mirror",
+                "provider-output-source",
+                "modeline",
+            ],
+            #[cfg(feature = "sway")]
+            ScreenControllerType::Sway => &[
+                "mirror",
+                "layout",
+                "rotate",
+                "disabled-background",
+                "scale-filter",
+                "dpms-on-disable",
+                "tearing",
+                "max-render-time",
+            ],
+            #[cfg(feature = "randr")]
+            ScreenControllerType::Randr => &[
+                "mirror",
+                "layout",
+                "rotate",
+                "gamma",
+                "brightness",
+                "hardware-mirror",
+                "primary",
+                "no-resize",
+                "no-reposition",
+                "modeline",
+            ],
+        }
+    }
+}
+
+/// Failure of a controller, reported without a backtrace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum SwitchError {
+    /// The program the controller runs is not installed.
+    #[cfg(any(feature = "xrandr", feature = "sway"))]
+    BackendNotFound { binary: String },
+    /// The program the controller runs cannot be started, e.g. because it is not executable.
+    #[cfg(any(feature = "xrandr", feature = "sway"))]
+    BackendNotStarted { binary: String, reason: String },
+    /// The program the controller runs exited unsuccessfully, e.g. because there is no display.
+    #[cfg(any(feature = "xrandr", feature = "sway"))]
+    CommandFailed {
+        command: String,
+        status: std::process::ExitStatus,
+        stderr: String,
+    },
+    /// The outputs sway reports cannot be parsed, e.g. because its IPC format changed.
+    #[cfg(feature = "sway")]
+    ParseOutputs { reason: String },
+    /// Talking to the X server failed, e.g. because there is none or it went away.
+    #[cfg(feature = "randr")]
+    X11 { reason: String },
+    /// A provider given by the user does not exist, e.g. because of a typo.
+    #[cfg(feature = "xrandr")]
+    UnknownProvider { name: String, known: Vec<String> },
+    /// The enabled outputs do not fit into the largest screen the X server supports.
+    #[cfg(feature = "randr")]
+    ScreenTooLarge { reason: String },
+    /// The output to enable has no CRTC left to drive it, e.g. because more outputs are enabled
+    /// than the GPU has CRTCs.
+    #[cfg(feature = "randr")]
+    NoFreeCrtc { output: String },
+    /// The fake controller was told to fail.
+    #[cfg(test)]
+    Fake,
+}
+
+impl fmt::Display for SwitchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            #[cfg(any(feature = "xrandr", feature = "sway"))]
+            SwitchError::BackendNotFound { ref binary } => {
+                write!(f, "Binary '{binary}' not found. Is it installed?")
+            }
+            #[cfg(any(feature = "xrandr", feature = "sway"))]
+            SwitchError::BackendNotStarted {
+                ref binary,
+                ref reason,
+            } => write!(f, "Unable to start '{binary}': {reason}"),
+            #[cfg(any(feature = "xrandr", feature = "sway"))]
+            SwitchError::CommandFailed {
+                ref command,
+                status,
+                ref stderr,
+            } => {
+                write!(f, "{command} exited with {status}")?;
+                if !stderr.is_empty() {
+                    write!(f, ": {stderr}")?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "sway")]
+            SwitchError::ParseOutputs { ref reason } => {
+                write!(f, "Unable to parse the outputs sway reports: {reason}")
+            }
+            #[cfg(feature = "randr")]
+            SwitchError::X11 { ref reason } => write!(f, "X11 request failed: {reason}"),
+            #[cfg(feature = "xrandr")]
+            SwitchError::UnknownProvider {
+                ref name,
+                ref known,
+            } => {
+                write!(
+                    f,
+                    "Provider {name:?} does not exist, xrandr lists {known:?}"
+                )
+            }
+            #[cfg(feature = "randr")]
+            SwitchError::ScreenTooLarge { ref reason } => {
+                write!(f, "Unable to switch outputs: {reason}")
+            }
+            #[cfg(feature = "randr")]
+            SwitchError::NoFreeCrtc { ref output } => {
+                write!(
+                    f,
+                    "No free CRTC to enable output {output}, disable another output first"
+                )
+            }
+            #[cfg(test)]
+            SwitchError::Fake => write!(f, "fake controller failed to switch"),
+        }
+    }
+}
+
+impl std::error::Error for SwitchError {}
+
+impl SwitchError {
+    /// Whether the failure may go away by itself, e.g. once a restarting X server is back,
+    /// so that querying the outputs again is worth it.
+    pub(super) fn is_transient(&self) -> bool {
+        match *self {
+            #[cfg(any(feature = "xrandr", feature = "sway"))]
+            SwitchError::BackendNotFound { .. } => false,
+            #[cfg(any(feature = "xrandr", feature = "sway"))]
+            SwitchError::BackendNotStarted { .. } => false,
+            #[cfg(any(feature = "xrandr", feature = "sway"))]
+            SwitchError::CommandFailed { .. } => true,
+            #[cfg(feature = "sway")]
+            SwitchError::ParseOutputs { .. } => false,
+            #[cfg(feature = "randr")]
+            SwitchError::X11 { .. } => true,
+            #[cfg(feature = "xrandr")]
+            SwitchError::UnknownProvider { .. } => false,
+            #[cfg(feature = "randr")]
+            SwitchError::ScreenTooLarge { .. } => false,
+            #[cfg(feature = "randr")]
+            SwitchError::NoFreeCrtc { .. } => false,
+            #[cfg(test)]
+            SwitchError::Fake => true,
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 enum ScreenControllerData {
     #[cfg(feature = "xrandr")]
@@ -27,47 +231,202 @@ enum ScreenControllerData {
     Sway,
     #[cfg(feature = "randr")]
     Randr(randr::RandrClient),
+    #[cfg(test)]
+    Fake(fake::FakeController),
 }
 
 pub(super) struct ScreenController(ScreenControllerData);
 
 impl ScreenController {
-    pub(super) fn new(controller_type: ScreenControllerType) -> Self {
-        Self(match controller_type {
+    pub(super) fn new(controller_type: ScreenControllerType) -> Result<Self, SwitchError> {
+        Ok(Self(match controller_type {
             #[cfg(feature = "xrandr")]
             ScreenControllerType::Xrandr => ScreenControllerData::Xrandr,
             #[cfg(feature = "sway")]
             ScreenControllerType::Sway => ScreenControllerData::Sway,
             #[cfg(feature = "randr")]
-            ScreenControllerType::Randr => ScreenControllerData::Randr(randr::RandrClient::new()),
-        })
+            ScreenControllerType::Randr => ScreenControllerData::Randr(randr::RandrClient::new()?),
+        }))
+    }
+
+    /// Controller reporting the given outputs, failing to switch them if `fail` is set.
+    #[cfg(test)]
+    pub(super) fn fake(screen: Screen, fail: bool) -> Self {
+        Self(ScreenControllerData::Fake(fake::FakeController {
+            screen,
+            fail,
+            switches: Vec::new(),
+            mirror_scaling: Vec::new(),
+        }))
     }
 
-    pub(super) fn get_outputs(&self) -> Screen {
+    /// Plans and resolutions the fake controller was asked to switch to.
+    #[cfg(test)]
+    pub(super) fn fake_switches(&self) -> &[(crate::switch::OwnedSwitchPlan, Option<Resolution>)] {
         match &self.0 {
+            ScreenControllerData::Fake(fake_controller) => &fake_controller.switches,
+            _ => panic!("not a fake controller"),
+        }
+    }
+
+    /// Whether the fake controller was asked to scale mirrored outputs, and from which output.
+    #[cfg(test)]
+    pub(super) fn fake_mirror_scaling(&self) -> &[(bool, Option<String>)] {
+        match &self.0 {
+            ScreenControllerData::Fake(fake_controller) => &fake_controller.mirror_scaling,
+            _ => panic!("not a fake controller"),
+        }
+    }
+
+    /// Makes the next [`Self::get_outputs`] report the current outputs, for controllers
+    /// caching them, so that one controller can be polled repeatedly.
+    pub(super) fn refresh(&mut self) -> Result<(), SwitchError> {
+        match self.0 {
+            #[cfg(feature = "xrandr")]
+            ScreenControllerData::Xrandr => Ok(()),
+            #[cfg(feature = "sway")]
+            ScreenControllerData::Sway => Ok(()),
+            #[cfg(feature = "randr")]
+            ScreenControllerData::Randr(ref mut randr_client) => randr_client.refresh(),
+            #[cfg(test)]
+            ScreenControllerData::Fake(_) => Ok(()),
+        }
+    }
+
+    pub(super) fn get_outputs(&self) -> Result<Screen, SwitchError> {
+        match self.0 {
             #[cfg(feature = "xrandr")]
             ScreenControllerData::Xrandr => xrandr::get_outputs(),
             #[cfg(feature = "sway")]
             ScreenControllerData::Sway => sway::get_outputs(),
             #[cfg(feature = "randr")]
-            ScreenControllerData::Randr(randr_client) => randr_client.get_outputs(),
+            ScreenControllerData::Randr(ref randr_client) => Ok(randr_client.get_outputs()),
+            #[cfg(test)]
+            ScreenControllerData::Fake(ref fake_controller) => Ok(fake_controller.screen.clone()),
         }
     }
 
+    /// Carries out the plan, returning whether the outputs needed reconfiguring.
     pub(super) fn switch_outputs(
         &mut self,
         switch_plan: &SwitchPlan,
         resolution: Option<Resolution>,
-    ) {
-        match &mut self.0 {
+        options: &SwitchOptions,
+    ) -> Result<bool, SwitchError> {
+        if !options.force && switch_plan.is_applied(resolution, options) {
+            log::info!("Outputs already match the switch plan, not reconfiguring them");
+            return Ok(false);
+        }
+
+        match self.0 {
             #[cfg(feature = "xrandr")]
-            ScreenControllerData::Xrandr => xrandr::switch_outputs(switch_plan, resolution),
+            ScreenControllerData::Xrandr => {
+                if options.disabled_background.is_some() {
+                    log::warn!("xrandr cannot set a background color of outputs, ignoring");
+                }
+                if options.dpms_on_disable {
+                    log::warn!("xrandr cannot power off individual outputs, ignoring");
+                }
+                if options.mirror_mode == crate::switch::MirrorMode::Hardware {
+                    log::warn!("xrandr controller cannot mirror outputs in hardware, ignoring");
+                }
+                if options.allow_tearing.is_some() || options.max_render_time_ms.is_some() {
+                    log::warn!("xrandr cannot set tearing or the max render time, ignoring");
+                }
+                if options.no_resize {
+                    log::warn!(
+                        "xrandr always fits the screen to the outputs, ignoring --no-resize"
+                    );
+                }
+                if options.no_reposition {
+                    log::warn!("xrandr controller cannot keep the position of outputs, ignoring");
+                }
+                xrandr::switch_outputs(switch_plan, resolution, options)?;
+            }
             #[cfg(feature = "sway")]
-            ScreenControllerData::Sway => sway::switch_outputs(switch_plan, resolution),
+            ScreenControllerData::Sway => {
+                if options.scale_mirrored || options.auto_scale_mirror {
+                    log::warn!("sway controller cannot scale mirrored outputs, ignoring");
+                }
+                if options.mirror_mode == crate::switch::MirrorMode::Hardware {
+                    log::warn!("sway controller cannot mirror outputs in hardware, ignoring");
+                }
+                if options.primary.is_some() {
+                    log::warn!("Sway has no primary output, ignoring");
+                }
+                if options.provider_output_source.is_some() {
+                    log::warn!(
+                        "sway controller cannot set the output source of providers, ignoring"
+                    );
+                }
+                if options.no_resize {
+                    log::warn!("Sway has no X screen to keep the size of, ignoring --no-resize");
+                }
+                if options.no_reposition {
+                    log::warn!("sway controller cannot keep the position of outputs, ignoring");
+                }
+                if options.modeline.is_some() {
+                    log::warn!("sway controller cannot create modes from a modeline, ignoring");
+                }
+                sway::switch_outputs(switch_plan, resolution, options)?;
+            }
             #[cfg(feature = "randr")]
-            ScreenControllerData::Randr(randr_client) => {
-                randr_client.switch_outputs(switch_plan, resolution)
+            ScreenControllerData::Randr(ref mut randr_client) => {
+                if options.disabled_background.is_some() {
+                    log::warn!("RandR cannot set a background color of outputs, ignoring");
+                }
+                if options.dpms_on_disable {
+                    log::warn!("RandR cannot power off individual outputs, ignoring");
+                }
+                if options.scale_mirrored || options.auto_scale_mirror {
+                    log::warn!("RandR controller cannot scale mirrored outputs, ignoring");
+                }
+                if options.scale_filter.is_some() {
+                    log::warn!(
+                        "RandR controller does not scale outputs, ignoring the scale filter"
+                    );
+                }
+                if options.allow_tearing.is_some() || options.max_render_time_ms.is_some() {
+                    log::warn!("RandR cannot set tearing or the max render time, ignoring");
+                }
+                if options.provider_output_source.is_some() {
+                    log::warn!(
+                        "RandR controller cannot set the output source of providers, ignoring"
+                    );
+                }
+                randr_client.switch_outputs(switch_plan, resolution, options)?;
+            }
+            #[cfg(test)]
+            ScreenControllerData::Fake(ref mut fake_controller) => {
+                fake_controller.switch_outputs(switch_plan, resolution, options)?;
             }
         }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiled_in_controllers_match_enabled_features() {
+        // Arrange
+        let expected: Vec<&str> = [
+            cfg!(feature = "xrandr").then_some("xrandr"),
+            cfg!(feature = "sway").then_some("sway"),
+            cfg!(feature = "randr").then_some("randr"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        // Act
+        let controllers = ScreenControllerType::compiled_in();
+
+        // Assert
+        assert!(!controllers.is_empty());
+        assert_eq!(controllers, expected);
     }
 }
@@ -1,6 +1,9 @@
+use super::SwitchError;
+use super::cvt;
+use crate::layout::Rotation;
 use crate::screen;
-use crate::switch::SwitchPlan;
-use std::collections::HashMap;
+use crate::switch::{MirrorMode, SwitchOptions, SwitchPlan};
+use std::collections::{HashMap, HashSet};
 use std::iter::Iterator;
 use x11rb::CURRENT_TIME;
 use x11rb::connection::Connection;
@@ -18,17 +21,28 @@ pub(super) struct RandrClient {
 }
 
 impl RandrClient {
-    pub(super) fn new() -> Self {
-        let (conn, screen_num) =
-            RustConnection::connect(None).expect("unable to connect to X11 display");
+    pub(super) fn new() -> Result<Self, SwitchError> {
+        let (conn, screen_num) = RustConnection::connect(None)?;
 
-        let screen = &conn.setup().roots[screen_num];
+        let mut client = Self {
+            conn,
+            screen_num,
+            config_timestamp: CURRENT_TIME,
+            modes: HashMap::new(),
+            outputs: HashMap::new(),
+            crtcs: HashMap::new(),
+        };
+        client.refresh()?;
+        Ok(client)
+    }
 
-        let screen_resources = conn
-            .randr_get_screen_resources(screen.root)
-            .expect("randr_get_screen_resources call failed")
-            .reply()
-            .expect("randr_get_screen_resources returned an error");
+    /// Queries the screen resources again, which are cached since the last query,
+    /// e.g. after outputs got connected.
+    pub(super) fn refresh(&mut self) -> Result<(), SwitchError> {
+        let conn = &self.conn;
+        let screen = &conn.setup().roots[self.screen_num];
+
+        let screen_resources = conn.randr_get_screen_resources(screen.root)?.reply()?;
 
         log::trace!("screen_resources = {screen_resources:?}");
 
@@ -38,52 +52,57 @@ impl RandrClient {
             .map(|mode| (mode.id, mode))
             .collect();
 
-        let outputs: HashMap<_, _> = screen_resources
+        // All requests are sent before waiting for any reply,
+        // so that querying takes two round trips instead of one per output and CRTC.
+        let output_cookies = screen_resources
             .outputs
             .iter()
-            .copied()
-            .map(|output_id| {
-                (
+            .map(|&output_id| {
+                Ok((
                     output_id,
-                    conn.randr_get_output_info(output_id, screen_resources.config_timestamp)
-                        .expect("randr_get_output_info call failed")
-                        .reply()
-                        .expect("randr_get_output_info returned an error"),
-                )
+                    conn.randr_get_output_info(output_id, screen_resources.config_timestamp)?,
+                ))
             })
-            .inspect(|(output_id, output)| log::trace!("outputs[{output_id}] = {output:?}"))
-            .collect();
+            .collect::<Result<Vec<_>, SwitchError>>()?;
 
-        let crtcs: HashMap<_, _> = screen_resources
+        let crtc_cookies = screen_resources
             .crtcs
             .iter()
-            .copied()
-            .map(|crtc_id| {
-                (
+            .map(|&crtc_id| {
+                Ok((
                     crtc_id,
-                    conn.randr_get_crtc_info(crtc_id, screen_resources.config_timestamp)
-                        .expect("randr_get_crtc_info call failed")
-                        .reply()
-                        .expect("randr_get_crtc_info returned an error"),
-                )
+                    conn.randr_get_crtc_info(crtc_id, screen_resources.config_timestamp)?,
+                ))
             })
-            .collect();
+            .collect::<Result<Vec<_>, SwitchError>>()?;
 
-        Self {
-            conn,
-            screen_num,
-            config_timestamp: screen_resources.config_timestamp,
-            modes,
-            outputs,
-            crtcs,
-        }
+        let outputs = output_cookies
+            .into_iter()
+            .map(|(output_id, cookie)| Ok((output_id, cookie.reply()?)))
+            .inspect(|result: &Result<_, SwitchError>| {
+                if let Ok((output_id, output)) = result {
+                    log::trace!("outputs[{output_id}] = {output:?}");
+                }
+            })
+            .collect::<Result<HashMap<_, _>, SwitchError>>()?;
+
+        let crtcs = crtc_cookies
+            .into_iter()
+            .map(|(crtc_id, cookie)| Ok((crtc_id, cookie.reply()?)))
+            .collect::<Result<HashMap<_, _>, SwitchError>>()?;
+
+        self.config_timestamp = screen_resources.config_timestamp;
+        self.modes = modes;
+        self.outputs = outputs;
+        self.crtcs = crtcs;
+        Ok(())
     }
 
     pub(super) fn get_outputs(&self) -> screen::Screen {
         let outputs = self
             .outputs
             .values()
-            .map(|output| randr_output_to_output(output, &self.modes))
+            .map(|output| randr_output_to_output(output, &self.modes, &self.crtcs))
             .collect();
 
         screen::Screen { outputs }
@@ -93,48 +112,228 @@ impl RandrClient {
         &mut self,
         switch_plan: &SwitchPlan,
         resolution: Option<screen::Resolution>,
-    ) {
+        options: &SwitchOptions,
+    ) -> Result<(), SwitchError> {
+        if let Some(resolution) = resolution {
+            self.add_missing_modes(switch_plan, resolution, options.modeline.as_ref())?;
+        }
+
         update_crtcs(
             switch_plan,
             resolution,
+            options,
             &self.modes,
             &mut self.outputs,
             &mut self.crtcs,
-        );
+        )?;
 
         let screen = &self.conn.setup().roots[self.screen_num];
 
-        for (&crtc_id, crtc_config) in &self.crtcs {
-            log::trace!("crtc_id = {crtc_id} crtc_config = {crtc_config:?}");
+        let size_range = self
+            .conn
+            .randr_get_screen_size_range(screen.root)?
+            .reply()?;
+        log::trace!("size_range = {size_range:?}");
+
+        let screen_size = compute_screen_size(&self.modes, &self.outputs, &self.crtcs, &size_range)
+            .map_err(|err| SwitchError::ScreenTooLarge {
+                reason: err.to_string(),
+            })?;
+
+        for request in build_config_requests(&self.crtcs, screen_size, options) {
+            match request {
+                ConfigRequest::SetCrtcConfig(crtc_id) => {
+                    let crtc_config = &self.crtcs[&crtc_id];
+                    log::trace!("crtc_id = {crtc_id} crtc_config = {crtc_config:?}");
+                    self.conn
+                        .randr_set_crtc_config(
+                            crtc_id,
+                            CURRENT_TIME,
+                            self.config_timestamp,
+                            crtc_config.x,
+                            crtc_config.y,
+                            crtc_config.mode,
+                            crtc_config.rotation,
+                            &crtc_config.outputs,
+                        )?
+                        .reply()?;
+                }
+                ConfigRequest::SetScreenSize(screen_size) => {
+                    log::trace!("screen_size = {screen_size:?}");
+                    self.conn
+                        .randr_set_screen_size(
+                            screen.root,
+                            screen_size.width,
+                            screen_size.height,
+                            screen_size.mm_width,
+                            screen_size.mm_height,
+                        )?
+                        .check()?;
+                }
+            }
+        }
+
+        if let Some(primary) = &options.primary {
+            self.set_primary(screen.root, primary)?;
+        }
+
+        if options.color_adjustment.is_requested() {
+            self.apply_color_adjustment(switch_plan, options.color_adjustment)?;
+        }
+
+        // The connection buffers requests until it needs to wait for a reply. Every request
+        // above is checked, but one that is not would otherwise stay in the buffer until the
+        // connection is dropped, after the switch seemingly succeeded.
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    /// Adds a mode of the resolution to the outputs to enable that have no modes, e.g. force-enabled
+    /// ones without EDID, creating it unless the X server already has a mode of that size.
+    /// With `modeline`, the mode has its timings, and only a mode of the same timings is reused.
+    fn add_missing_modes(
+        &mut self,
+        switch_plan: &SwitchPlan,
+        resolution: screen::Resolution,
+        modeline: Option<&screen::Modeline>,
+    ) -> Result<(), SwitchError> {
+        let output_ids: Vec<_> = self
+            .outputs
+            .iter()
+            .filter(|(_, output)| output.modes.is_empty())
+            .filter(|(_, output)| {
+                switch_plan
+                    .outputs_to_enable
+                    .iter()
+                    .any(|output_to_enable| output_to_enable.name.as_bytes() == output.name)
+            })
+            .map(|(&output_id, _)| output_id)
+            .collect();
+        if output_ids.is_empty() {
+            return Ok(());
+        }
+
+        let existing_mode_id = match modeline {
+            Some(modeline) => find_mode_by_timings(&self.modes, &modeline_to_randr_mode(modeline)),
+            None => find_mode_by_resolution(&self.modes, resolution),
+        };
+        let mode_id = match existing_mode_id {
+            Some(mode_id) => mode_id,
+            None => {
+                let modeline = modeline
+                    .cloned()
+                    .unwrap_or_else(|| cvt::modeline(resolution));
+                let name = &modeline.name;
+                let mode_info = modeline_to_randr_mode(&modeline);
+                let root = self.conn.setup().roots[self.screen_num].root;
+                let mode_id = self
+                    .conn
+                    .randr_create_mode(root, mode_info, name.as_bytes())?
+                    .reply()?
+                    .mode;
+                log::info!("Created mode {name} for outputs without modes");
+                self.modes.insert(
+                    mode_id,
+                    randr::ModeInfo {
+                        id: mode_id,
+                        ..mode_info
+                    },
+                );
+                mode_id
+            }
+        };
+
+        for output_id in output_ids {
             self.conn
-                .randr_set_crtc_config(
-                    crtc_id,
-                    CURRENT_TIME,
-                    self.config_timestamp,
-                    crtc_config.x,
-                    crtc_config.y,
-                    crtc_config.mode,
-                    crtc_config.rotation,
-                    &crtc_config.outputs,
-                )
-                .expect("randr_set_crtc_config call failed")
-                .reply()
-                .expect("randr_set_crtc_config returned an error");
+                .randr_add_output_mode(output_id, mode_id)?
+                .check()?;
+            self.outputs
+                .get_mut(&output_id)
+                .expect("invalid output id")
+                .modes
+                .push(mode_id);
         }
+        Ok(())
+    }
+
+    fn set_primary(
+        &self,
+        root: x11rb::protocol::xproto::Window,
+        name: &str,
+    ) -> Result<(), SwitchError> {
+        let Some(&output_id) = self
+            .outputs
+            .iter()
+            .find_map(|(output_id, output)| (output.name == name.as_bytes()).then_some(output_id))
+        else {
+            log::error!("Output {name:?} to make the primary one does not exist");
+            return Ok(());
+        };
+
+        log::trace!("primary output_id = {output_id}");
+        self.conn
+            .randr_set_output_primary(root, output_id)?
+            .check()?;
+        Ok(())
+    }
+
+    fn apply_color_adjustment(
+        &self,
+        switch_plan: &SwitchPlan,
+        color_adjustment: screen::ColorAdjustment,
+    ) -> Result<(), SwitchError> {
+        let gamma = color_adjustment.gamma.unwrap_or(screen::Gamma::IDENTITY);
+        let brightness = color_adjustment.brightness.unwrap_or(1.0);
+
+        let crtc_ids: HashSet<_> = self
+            .outputs
+            .values()
+            .filter(|output| output.crtc != 0)
+            .filter(|output| {
+                switch_plan
+                    .outputs_to_enable
+                    .iter()
+                    .any(|output_to_enable| output_to_enable.name.as_bytes() == output.name)
+            })
+            .map(|output| output.crtc)
+            .collect();
+
+        for crtc_id in crtc_ids {
+            let size = self.conn.randr_get_crtc_gamma_size(crtc_id)?.reply()?.size;
+            log::trace!("crtc_id = {crtc_id} gamma_size = {size}");
+
+            let red = compute_gamma_ramp(size, gamma.red, brightness);
+            let green = compute_gamma_ramp(size, gamma.green, brightness);
+            let blue = compute_gamma_ramp(size, gamma.blue, brightness);
 
-        if let Some(screen_size) = compute_screen_size(&self.modes, &self.outputs, &self.crtcs) {
-            log::trace!("screen_size = {screen_size:?}");
             self.conn
-                .randr_set_screen_size(
-                    screen.root,
-                    screen_size.width,
-                    screen_size.height,
-                    screen_size.mm_width,
-                    screen_size.mm_height,
-                )
-                .expect("randr_set_screen_size call failed")
-                .check()
-                .expect("randr_set_screen_size returned an error");
+                .randr_set_crtc_gamma(crtc_id, &red, &green, &blue)?
+                .check()?;
+        }
+        Ok(())
+    }
+}
+
+impl From<x11rb::errors::ConnectError> for SwitchError {
+    fn from(err: x11rb::errors::ConnectError) -> Self {
+        SwitchError::X11 {
+            reason: format!("unable to connect to X11 display: {err}"),
+        }
+    }
+}
+
+impl From<x11rb::errors::ConnectionError> for SwitchError {
+    fn from(err: x11rb::errors::ConnectionError) -> Self {
+        SwitchError::X11 {
+            reason: err.to_string(),
+        }
+    }
+}
+
+impl From<x11rb::errors::ReplyError> for SwitchError {
+    fn from(err: x11rb::errors::ReplyError) -> Self {
+        SwitchError::X11 {
+            reason: err.to_string(),
         }
     }
 }
@@ -142,6 +341,7 @@ impl RandrClient {
 fn randr_output_to_output(
     output: &randr::GetOutputInfoReply,
     modes: &HashMap<randr::Mode, randr::ModeInfo>,
+    crtcs: &HashMap<randr::Crtc, randr::GetCrtcInfoReply>,
 ) -> screen::Output {
     let name = String::from_utf8(output.name.clone())
         .expect("output name should normally be a valid UTF-8");
@@ -149,9 +349,23 @@ fn randr_output_to_output(
     let enabled = output.crtc != 0;
     let location = screen::Location::from_output_name(&name);
 
+    let crtc = crtcs.get(&output.crtc).filter(|_| enabled);
+    let current_resolution = crtc
+        .and_then(|crtc| modes.get(&crtc.mode))
+        .map(randr_mode_to_resolution);
+    let current_position = crtc.map(|crtc| screen::Position {
+        x: crtc.x as i32,
+        y: crtc.y as i32,
+    });
+
+    // The preferred modes come first.
     let modes = mode_ids_to_modes(&output.modes, modes)
-        .filter(|mode| is_admissible(mode))
-        .map(randr_mode_to_mode)
+        .enumerate()
+        .filter(|(_, mode)| is_admissible(mode))
+        .map(|(i, mode)| screen::Mode {
+            active: crtc.is_some_and(|crtc| crtc.mode == mode.id),
+            ..randr_mode_to_mode(mode, i < output.num_preferred as usize)
+        })
         .collect();
 
     screen::Output {
@@ -160,6 +374,16 @@ fn randr_output_to_output(
         enabled,
         modes,
         location,
+        // DPMS is a property of the whole X screen, not of individual outputs.
+        powered: true,
+        current_resolution,
+        current_position,
+        scale_filter: None,
+        // Projectors and some virtual outputs report a size of zero.
+        physical_size_mm: (output.mm_width != 0 && output.mm_height != 0)
+            .then_some((output.mm_width, output.mm_height)),
+        allow_tearing: None,
+        max_render_time_ms: None,
     }
 }
 
@@ -174,14 +398,75 @@ fn mode_ids_to_modes<'a>(
     })
 }
 
+fn find_mode_by_resolution(
+    modes: &HashMap<randr::Mode, randr::ModeInfo>,
+    resolution: screen::Resolution,
+) -> Option<randr::Mode> {
+    modes
+        .values()
+        .filter(|mode| is_admissible(mode) && randr_mode_to_resolution(mode) == resolution)
+        .min_by_key(|mode| mode.id)
+        .map(|mode| mode.id)
+}
+
+/// Mode of the X server with the same timings and flags, whatever its name.
+fn find_mode_by_timings(
+    modes: &HashMap<randr::Mode, randr::ModeInfo>,
+    mode_info: &randr::ModeInfo,
+) -> Option<randr::Mode> {
+    let timings = |mode: &randr::ModeInfo| randr::ModeInfo {
+        id: 0,
+        name_len: 0,
+        ..*mode
+    };
+    modes
+        .values()
+        .filter(|mode| timings(mode) == timings(mode_info))
+        .min_by_key(|mode| mode.id)
+        .map(|mode| mode.id)
+}
+
+fn modeline_to_randr_mode(modeline: &screen::Modeline) -> randr::ModeInfo {
+    let to_u16 = |value: u32| u16::try_from(value).expect("mode timing should fit into u16");
+    let mut mode_flags = randr::ModeFlag::from(0u32);
+    match modeline.hsync {
+        Some(screen::SyncPolarity::Positive) => mode_flags |= randr::ModeFlag::HSYNC_POSITIVE,
+        Some(screen::SyncPolarity::Negative) => mode_flags |= randr::ModeFlag::HSYNC_NEGATIVE,
+        None => {}
+    }
+    match modeline.vsync {
+        Some(screen::SyncPolarity::Positive) => mode_flags |= randr::ModeFlag::VSYNC_POSITIVE,
+        Some(screen::SyncPolarity::Negative) => mode_flags |= randr::ModeFlag::VSYNC_NEGATIVE,
+        None => {}
+    }
+    randr::ModeInfo {
+        id: 0,
+        width: to_u16(modeline.hdisplay),
+        height: to_u16(modeline.vdisplay),
+        dot_clock: modeline.clock_khz * 1000,
+        hsync_start: to_u16(modeline.hsync_start),
+        hsync_end: to_u16(modeline.hsync_end),
+        htotal: to_u16(modeline.htotal),
+        hskew: 0,
+        vsync_start: to_u16(modeline.vsync_start),
+        vsync_end: to_u16(modeline.vsync_end),
+        vtotal: to_u16(modeline.vtotal),
+        name_len: u16::try_from(modeline.name.len()).expect("mode name should fit into u16"),
+        mode_flags,
+    }
+}
+
 fn is_admissible(mode: &randr::ModeInfo) -> bool {
     !mode.mode_flags.contains(randr::ModeFlag::DOUBLE_SCAN)
 }
 
-fn randr_mode_to_mode(mode: &randr::ModeInfo) -> screen::Mode {
+fn randr_mode_to_mode(mode: &randr::ModeInfo, preferred: bool) -> screen::Mode {
     screen::Mode {
         resolution: randr_mode_to_resolution(mode),
         refresh_rate_millihz: compute_refresh_rate_millihz(mode),
+        preferred,
+        active: false,
+        refresh_rates_millihz: Vec::new(),
     }
 }
 
@@ -194,7 +479,9 @@ fn randr_mode_to_resolution(mode: &randr::ModeInfo) -> screen::Resolution {
 
 fn compute_refresh_rate_millihz(mode: &randr::ModeInfo) -> u32 {
     if mode.htotal > 0 && mode.vtotal > 0 {
-        u32::try_from(mode.dot_clock as u64 * 1000 / (mode.htotal as u64 * mode.vtotal as u64))
+        let total = mode.htotal as u64 * mode.vtotal as u64;
+        // Rounded to the nearest millihertz rather than truncated.
+        u32::try_from((mode.dot_clock as u64 * 1000 + total / 2) / total)
             .expect("refresh rate should fit into u32")
     } else {
         0
@@ -204,10 +491,11 @@ fn compute_refresh_rate_millihz(mode: &randr::ModeInfo) -> u32 {
 fn update_crtcs(
     switch_plan: &SwitchPlan,
     resolution: Option<screen::Resolution>,
+    options: &SwitchOptions,
     modes: &HashMap<u32, randr::ModeInfo>,
     outputs: &mut HashMap<randr::Output, randr::GetOutputInfoReply>,
     crtcs: &mut HashMap<randr::Crtc, randr::GetCrtcInfoReply>,
-) {
+) -> Result<(), SwitchError> {
     let outputs_to_disable = outputs
         .iter_mut()
         .filter(|(_, output)| output.crtc != 0)
@@ -229,19 +517,71 @@ fn update_crtcs(
         output.crtc = 0;
     }
 
-    let outputs_to_enable = outputs.iter_mut().filter(|(_, output)| {
-        switch_plan
-            .outputs_to_enable
-            .iter()
-            .any(|output_to_enable| output_to_enable.name.as_bytes() == output.name)
-    });
+    let mut outputs_to_enable: Vec<_> = outputs
+        .iter_mut()
+        .filter(|(_, output)| {
+            switch_plan
+                .outputs_to_enable
+                .iter()
+                .any(|output_to_enable| output_to_enable.name.as_bytes() == output.name)
+        })
+        .collect();
+    // Outputs keeping their CRTC are configured first, so that others can share it.
+    outputs_to_enable.sort_by_key(|(output_id, output)| (output.crtc == 0, **output_id));
+
+    // CRTCs configured so far, which outputs mirroring them in hardware can share.
+    let mut configured_crtcs = Vec::new();
 
     for (output_id, output) in outputs_to_enable {
-        let crtc = if output.crtc != 0 {
-            let crtc = crtcs.get_mut(&output.crtc).expect("invalid crtc id");
+        let name = std::str::from_utf8(&output.name).expect("output name should be valid UTF-8");
+        let position = options.layout.position(name);
+        let x = i16::try_from(position.x).expect("layout positions should be in range");
+        let y = i16::try_from(position.y).expect("layout positions should be in range");
+        let mode = choose_best_mode(output, modes, resolution, &options.mode_restrictions)
+            .expect("output has no modes");
+        let rotation = match options.layout.rotation(name) {
+            Rotation::Normal => randr::Rotation::ROTATE0,
+            Rotation::Left => randr::Rotation::ROTATE90,
+        };
+
+        let shared_crtc_id = (options.mirror_mode == MirrorMode::Hardware && output.crtc == 0)
+            .then(|| {
+                configured_crtcs.iter().copied().find(|crtc_id| {
+                    let crtc = crtcs.get(crtc_id).expect("invalid crtc id");
+                    output.crtcs.contains(crtc_id)
+                        && (crtc.x, crtc.y, crtc.mode, crtc.rotation) == (x, y, mode, rotation)
+                        && crtc
+                            .outputs
+                            .iter()
+                            .all(|clone_id| output.clones.contains(clone_id))
+                })
+            })
+            .flatten();
+
+        let keeps_crtc = output.crtc != 0;
+        let crtc_id = if keeps_crtc {
+            let crtc = crtcs.get(&output.crtc).expect("invalid crtc id");
             assert!(crtc.outputs.contains(output_id));
-            crtc
+            output.crtc
+        } else if let Some(crtc_id) = shared_crtc_id {
+            log::info!("Mirroring {name} in hardware");
+            crtcs
+                .get_mut(&crtc_id)
+                .expect("invalid crtc id")
+                .outputs
+                .push(*output_id);
+            output.crtc = crtc_id;
+            crtc_id
         } else {
+            if options.mirror_mode == MirrorMode::Hardware
+                && configured_crtcs.iter().any(|crtc_id| {
+                    let crtc = crtcs.get(crtc_id).expect("invalid crtc id");
+                    (crtc.x, crtc.y) == (x, y)
+                })
+            {
+                log::info!("Unable to mirror {name} in hardware, mirroring it in software");
+            }
+
             let crtc_id = output
                 .crtcs
                 .iter()
@@ -253,19 +593,27 @@ fn update_crtcs(
                         .outputs
                         .is_empty()
                 })
-                .expect("no free crtcs available for output");
+                .ok_or_else(|| SwitchError::NoFreeCrtc {
+                    output: name.to_string(),
+                })?;
 
             let crtc = crtcs.get_mut(&crtc_id).expect("invalid crtc id");
             assert!(!crtc.outputs.contains(output_id));
             crtc.outputs.push(*output_id);
             output.crtc = crtc_id;
-            crtc
+            crtc_id
         };
 
-        crtc.x = 0;
-        crtc.y = 0;
-        crtc.mode = choose_best_mode(output, modes, resolution).expect("output has no modes");
-        crtc.rotation = randr::Rotation::ROTATE0;
+        let crtc = crtcs.get_mut(&crtc_id).expect("invalid crtc id");
+        if options.no_reposition && keeps_crtc {
+            log::debug!("Keeping {name} at ({}, {})", crtc.x, crtc.y);
+        } else {
+            crtc.x = x;
+            crtc.y = y;
+        }
+        crtc.mode = mode;
+        crtc.rotation = rotation;
+        configured_crtcs.push(crtc_id);
     }
 
     assert!(crtcs.iter().all(
@@ -284,13 +632,17 @@ fn update_crtcs(
                 .get(&output.crtc)
                 .is_some_and(|crtc| crtc.outputs.contains(output_id)))
     );
+    Ok(())
 }
 
 fn choose_best_mode(
     output: &randr::GetOutputInfoReply,
     modes: &HashMap<randr::Mode, randr::ModeInfo>,
     resolution: Option<screen::Resolution>,
+    mode_restrictions: &screen::ModeRestrictions,
 ) -> Option<randr::Mode> {
+    let name = std::str::from_utf8(&output.name).expect("output name should be valid UTF-8");
+
     struct Candidate<'a> {
         preferred: bool,
         mode: &'a randr::ModeInfo,
@@ -303,6 +655,12 @@ fn choose_best_mode(
             mode,
         })
         .filter(|candidate| candidate.preferred || is_admissible(candidate.mode))
+        .filter(|candidate| {
+            mode_restrictions.allows(
+                name,
+                &randr_mode_to_mode(candidate.mode, candidate.preferred),
+            )
+        })
         .collect();
 
     if let Some(resolution) = resolution
@@ -331,6 +689,40 @@ fn choose_best_mode(
         .map(|candidate| candidate.mode.id)
 }
 
+fn compute_gamma_ramp(size: u16, gamma: f32, brightness: f32) -> Vec<u16> {
+    (0..size)
+        .map(|i| {
+            let value =
+                (i as f64 / size as f64).powf(1.0 / gamma as f64) * brightness as f64 * 65535.0;
+            value.round().clamp(0.0, 65535.0) as u16
+        })
+        .collect()
+}
+
+/// A request reconfiguring the screen, in the order [`RandrClient::switch_outputs`] issues them.
+#[derive(Debug, PartialEq, Eq)]
+enum ConfigRequest {
+    SetCrtcConfig(randr::Crtc),
+    SetScreenSize(ScreenSize),
+}
+
+fn build_config_requests(
+    crtcs: &HashMap<randr::Crtc, randr::GetCrtcInfoReply>,
+    screen_size: Option<ScreenSize>,
+    options: &SwitchOptions,
+) -> Vec<ConfigRequest> {
+    let mut requests: Vec<_> = crtcs
+        .keys()
+        .map(|&crtc_id| ConfigRequest::SetCrtcConfig(crtc_id))
+        .collect();
+    if options.no_resize {
+        log::debug!("Keeping the screen size, as requested");
+    } else if let Some(screen_size) = screen_size {
+        requests.push(ConfigRequest::SetScreenSize(screen_size));
+    }
+    requests
+}
+
 #[derive(Debug, PartialEq, Eq)]
 struct ScreenSize {
     width: u16,
@@ -339,21 +731,58 @@ struct ScreenSize {
     mm_height: u32,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum ScreenSizeError {
+    TooLarge {
+        width: i32,
+        height: i32,
+        max_width: u16,
+        max_height: u16,
+    },
+}
+
+impl std::fmt::Display for ScreenSizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreenSizeError::TooLarge {
+                width,
+                height,
+                max_width,
+                max_height,
+            } => write!(
+                f,
+                "screen size {width}x{height} exceeds the maximum of {max_width}x{max_height}"
+            ),
+        }
+    }
+}
+
+/// Computes the size of the screen containing all enabled CRTCs, clamped from below
+/// to the minimum size supported by the X server, or `None` if no CRTCs are enabled.
 fn compute_screen_size(
     modes: &HashMap<randr::Mode, randr::ModeInfo>,
     outputs: &HashMap<randr::Output, randr::GetOutputInfoReply>,
     crtcs: &HashMap<randr::Crtc, randr::GetCrtcInfoReply>,
-) -> Option<ScreenSize> {
+    size_range: &randr::GetScreenSizeRangeReply,
+) -> Result<Option<ScreenSize>, ScreenSizeError> {
     let bboxes: Vec<_> = crtcs
         .values()
         .filter(|crtc| crtc.mode != 0)
         .map(|crtc| {
             let mode = modes.get(&crtc.mode).expect("invalid mode id");
+            let (width, height) = if crtc
+                .rotation
+                .intersects(randr::Rotation::ROTATE90 | randr::Rotation::ROTATE270)
+            {
+                (mode.height, mode.width)
+            } else {
+                (mode.width, mode.height)
+            };
             (
                 crtc.x as i32,
                 crtc.y as i32,
-                crtc.x as i32 + mode.width as i32,
-                crtc.y as i32 + mode.height as i32,
+                crtc.x as i32 + width as i32,
+                crtc.y as i32 + height as i32,
             )
         })
         .collect();
@@ -364,8 +793,22 @@ fn compute_screen_size(
     let max_y = bboxes.iter().map(|bbox| bbox.3).max();
 
     if let (Some(min_x), Some(min_y), Some(max_x), Some(max_y)) = (min_x, min_y, max_x, max_y) {
-        let width = u16::try_from(max_x - min_x).expect("too large screen width");
-        let height = u16::try_from(max_y - min_y).expect("too large screen height");
+        let too_large = || ScreenSizeError::TooLarge {
+            width: max_x - min_x,
+            height: max_y - min_y,
+            max_width: size_range.max_width,
+            max_height: size_range.max_height,
+        };
+        let width = u16::try_from(max_x - min_x)
+            .ok()
+            .filter(|width| *width <= size_range.max_width)
+            .ok_or_else(too_large)?
+            .max(size_range.min_width);
+        let height = u16::try_from(max_y - min_y)
+            .ok()
+            .filter(|height| *height <= size_range.max_height)
+            .ok_or_else(too_large)?
+            .max(size_range.min_height);
 
         let (mm_width, mm_height) = crtcs
             .values()
@@ -376,14 +819,14 @@ fn compute_screen_size(
             .max_by_key(|(w, h)| *w as u64 * *h as u64)
             .unwrap_or_else(|| (px_to_mm(width), px_to_mm(height)));
 
-        Some(ScreenSize {
+        Ok(Some(ScreenSize {
             width,
             height,
             mm_width,
             mm_height,
-        })
+        }))
     } else {
-        None
+        Ok(None)
     }
 }
 
@@ -404,7 +847,7 @@ mod tests {
     #[ignore = "needs X11, manual"]
     fn get_outputs_smoke_test() {
         // Arrange
-        let client = RandrClient::new();
+        let client = RandrClient::new().expect("unable to connect to X11 display");
 
         // Act
         let screen = client.get_outputs();
@@ -417,11 +860,28 @@ mod tests {
         }
     }
 
+    #[test]
+    #[ignore = "needs X11, manual"]
+    fn new_benchmark() {
+        // Arrange
+        const ITERATIONS: u32 = 100;
+
+        // Act
+        let start = std::time::Instant::now();
+        for _ in 0..ITERATIONS {
+            RandrClient::new().expect("unable to connect to X11 display");
+        }
+        let elapsed = start.elapsed();
+
+        // Assert
+        println!("RandrClient::new() takes {:?}", elapsed / ITERATIONS);
+    }
+
     #[test]
     #[ignore = "needs X11, manual"]
     fn switch_outputs_smoke_test() {
         // Arrange
-        let mut client = RandrClient::new();
+        let mut client = RandrClient::new().expect("unable to connect to X11 display");
         let switch_plan = SwitchPlan {
             outputs_to_disable: Vec::new(),
             outputs_to_enable: Vec::new(),
@@ -429,7 +889,9 @@ mod tests {
 
         // Act
         let screen = client.get_outputs();
-        client.switch_outputs(&switch_plan, None);
+        client
+            .switch_outputs(&switch_plan, None, &SwitchOptions::default())
+            .expect("switch_outputs should succeed");
         let new_screen = client.get_outputs();
 
         // Assert
@@ -444,6 +906,8 @@ mod tests {
             connection: randr::Connection::CONNECTED,
             modes: vec![1, 2],
             name: b"eDP-1".to_vec(),
+            mm_width: 344,
+            mm_height: 194,
             ..Default::default()
         };
 
@@ -469,28 +933,93 @@ mod tests {
             },
         };
 
+        let crtcs = hashmap! {
+            42 => randr::GetCrtcInfoReply {
+                mode: 1,
+                outputs: vec![10],
+                ..Default::default()
+            },
+        };
+
         // Act
-        let output = randr_output_to_output(&randr_output, &modes);
+        let output = randr_output_to_output(&randr_output, &modes, &crtcs);
 
         // Assert
         assert_eq!(
             output,
             screen::Output {
-                name: "eDP-1".to_owned(),
-                enabled: true,
-                connected: true,
                 modes: vec! {screen::Mode {
                     resolution: screen::Resolution {
                         width: 1920,
                         height: 1080,
                     },
                     refresh_rate_millihz: 60020,
+                    preferred: false,
+                    active: true,
+                    refresh_rates_millihz: Vec::new(),
                 }},
                 location: screen::Location::Internal,
+                current_resolution: Some(screen::Resolution {
+                    width: 1920,
+                    height: 1080,
+                }),
+                current_position: Some(screen::Position { x: 0, y: 0 }),
+                physical_size_mm: Some((344, 194)),
+                ..screen::Output::test("eDP-1", true, true)
             }
         );
     }
 
+    #[test]
+    fn test_randr_output_to_output_on_output_with_crtc() {
+        // Arrange
+        let randr_output = randr::GetOutputInfoReply {
+            crtc: 42,
+            connection: randr::Connection::CONNECTED,
+            modes: vec![1],
+            name: b"HDMI-1".to_vec(),
+            mm_width: 527,
+            mm_height: 296,
+            ..Default::default()
+        };
+
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                id: 1,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+        };
+
+        let crtcs = hashmap! {
+            42 => randr::GetCrtcInfoReply {
+                x: 1280,
+                y: -10,
+                mode: 1,
+                outputs: vec![10],
+                ..Default::default()
+            },
+        };
+
+        // Act
+        let output = randr_output_to_output(&randr_output, &modes, &crtcs);
+
+        // Assert
+        assert_eq!(
+            output.current_resolution,
+            Some(screen::Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+        assert_eq!(
+            output.current_position,
+            Some(screen::Position { x: 1280, y: -10 })
+        );
+        assert_eq!(output.physical_size_mm, Some((527, 296)));
+    }
+
     #[test]
     fn test_randr_output_to_output_on_external_disconnected_output() {
         // Arrange
@@ -503,17 +1032,14 @@ mod tests {
         let modes = HashMap::new();
 
         // Act
-        let output = randr_output_to_output(&randr_output, &modes);
+        let output = randr_output_to_output(&randr_output, &modes, &HashMap::new());
 
         // Assert
         assert_eq!(
             output,
             screen::Output {
-                name: "HDMI-1".to_owned(),
-                enabled: false,
-                connected: false,
-                modes: Vec::new(),
                 location: screen::Location::External,
+                ..screen::Output::test("HDMI-1", false, false)
             }
         );
     }
@@ -532,20 +1058,26 @@ mod tests {
     #[test]
     fn test_randr_mode_to_mode() {
         assert_eq!(
-            randr_mode_to_mode(&randr::ModeInfo {
-                width: 1920,
-                height: 1080,
-                dot_clock: 138700000,
-                htotal: 2080,
-                vtotal: 1111,
-                ..Default::default()
-            }),
+            randr_mode_to_mode(
+                &randr::ModeInfo {
+                    width: 1920,
+                    height: 1080,
+                    dot_clock: 138700000,
+                    htotal: 2080,
+                    vtotal: 1111,
+                    ..Default::default()
+                },
+                true
+            ),
             screen::Mode {
                 resolution: screen::Resolution {
                     width: 1920,
                     height: 1080,
                 },
                 refresh_rate_millihz: 60020,
+                preferred: true,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
             }
         );
     }
@@ -576,6 +1108,24 @@ mod tests {
             }),
             60020
         );
+        assert_eq!(
+            compute_refresh_rate_millihz(&randr::ModeInfo {
+                dot_clock: 148500000,
+                htotal: 2200,
+                vtotal: 1125,
+                ..Default::default()
+            }),
+            60000
+        );
+        assert_eq!(
+            compute_refresh_rate_millihz(&randr::ModeInfo {
+                dot_clock: 241500000,
+                htotal: 2720,
+                vtotal: 1481,
+                ..Default::default()
+            }),
+            59951
+        );
         assert_eq!(
             compute_refresh_rate_millihz(&randr::ModeInfo {
                 dot_clock: 138700000,
@@ -597,51 +1147,171 @@ mod tests {
     }
 
     #[test]
-    fn test_update_crtcs() {
+    fn find_mode_by_resolution_returns_admissible_mode_of_resolution() {
         // Arrange
         let modes = hashmap! {
             1 => randr::ModeInfo {
                 id: 1,
                 width: 1920,
                 height: 1080,
-                dot_clock: 138700000,
-                htotal: 2080,
-                vtotal: 1111,
-                ..Default::default()
-            }
-        };
-
-        let mut randr_outputs = hashmap! {
-            10 => randr::GetOutputInfoReply {
-                crtc: 20,
-                connection: randr::Connection::CONNECTED,
-                crtcs: vec![20, 21, 22],
-                modes: vec![1],
-                name: b"eDP-1".to_vec(),
-                ..Default::default()
-            },
-            11 => randr::GetOutputInfoReply {
-                crtc: 0,
-                connection: randr::Connection::DISCONNECTED,
-                crtcs: vec![20, 21, 22],
-                modes: vec![1],
-                name: b"HDMI-1".to_vec(),
+                mode_flags: randr::ModeFlag::DOUBLE_SCAN,
                 ..Default::default()
             },
-            12 => randr::GetOutputInfoReply {
-                crtc: 0,
-                connection: randr::Connection::CONNECTED,
-                crtcs: vec![20, 21, 22],
-                modes: vec![1],
-                name: b"HDMI-2".to_vec(),
+            2 => randr::ModeInfo {
+                id: 2,
+                width: 1920,
+                height: 1080,
                 ..Default::default()
             },
-            13 => randr::GetOutputInfoReply {
-                crtc: 21,
-                connection: randr::Connection::CONNECTED,
-                crtcs: vec![20, 21, 22],
-                modes: vec![1],
-                name: b"HDMI-3".to_vec(),
+            3 => randr::ModeInfo {
+                id: 3,
+                width: 1280,
+                height: 720,
+                ..Default::default()
+            },
+        };
+
+        // Act
+        let full_hd = find_mode_by_resolution(
+            &modes,
+            screen::Resolution {
+                width: 1920,
+                height: 1080,
+            },
+        );
+        let missing = find_mode_by_resolution(
+            &modes,
+            screen::Resolution {
+                width: 1024,
+                height: 768,
+            },
+        );
+
+        // Assert
+        assert_eq!(full_hd, Some(2));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn test_modeline_to_randr_mode() {
+        // Arrange
+        let modeline = cvt::modeline(screen::Resolution {
+            width: 1920,
+            height: 1080,
+        });
+
+        // Act
+        let randr_mode = modeline_to_randr_mode(&modeline);
+
+        // Assert
+        assert_eq!(
+            randr_mode,
+            randr::ModeInfo {
+                id: 0,
+                width: 1920,
+                height: 1080,
+                dot_clock: 173_000_000,
+                hsync_start: 2048,
+                hsync_end: 2248,
+                htotal: 2576,
+                hskew: 0,
+                vsync_start: 1083,
+                vsync_end: 1088,
+                vtotal: 1120,
+                name_len: 9,
+                mode_flags: randr::ModeFlag::HSYNC_NEGATIVE | randr::ModeFlag::VSYNC_POSITIVE,
+            }
+        );
+        assert_eq!(compute_refresh_rate_millihz(&randr_mode), 59_963);
+    }
+
+    #[test]
+    fn modeline_to_randr_mode_sets_sync_polarity_flags() {
+        // Arrange
+        let modeline: screen::Modeline =
+            r#"Modeline "1920x1080_60.00" 148.50 1920 2008 2052 2200 1080 1084 1089 1125 +hsync -vsync"#
+                .parse()
+                .expect("modeline should parse");
+        let without_flags: screen::Modeline =
+            "1920x1080 148.50 1920 2008 2052 2200 1080 1084 1089 1125"
+                .parse()
+                .expect("modeline should parse");
+
+        // Act
+        let randr_mode = modeline_to_randr_mode(&modeline);
+        let randr_mode_without_flags = modeline_to_randr_mode(&without_flags);
+        let modes = hashmap! {
+            7 => randr::ModeInfo {
+                id: 7,
+                name_len: 9,
+                ..randr_mode
+            },
+        };
+
+        // Assert
+        assert_eq!(randr_mode.dot_clock, 148_500_000);
+        assert_eq!(randr_mode.name_len, 15);
+        assert_eq!(
+            randr_mode.mode_flags,
+            randr::ModeFlag::HSYNC_POSITIVE | randr::ModeFlag::VSYNC_NEGATIVE
+        );
+        assert_eq!(
+            randr_mode_without_flags.mode_flags,
+            randr::ModeFlag::from(0u32)
+        );
+        assert_eq!(find_mode_by_timings(&modes, &randr_mode), Some(7));
+        assert_eq!(
+            find_mode_by_timings(&modes, &randr_mode_without_flags),
+            None
+        );
+    }
+
+    #[test]
+    fn test_update_crtcs() {
+        // Arrange
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                id: 1,
+                width: 1920,
+                height: 1080,
+                dot_clock: 138700000,
+                htotal: 2080,
+                vtotal: 1111,
+                ..Default::default()
+            }
+        };
+
+        let mut randr_outputs = hashmap! {
+            10 => randr::GetOutputInfoReply {
+                crtc: 20,
+                connection: randr::Connection::CONNECTED,
+                crtcs: vec![20, 21, 22],
+                modes: vec![1],
+                name: b"eDP-1".to_vec(),
+                ..Default::default()
+            },
+            11 => randr::GetOutputInfoReply {
+                crtc: 0,
+                connection: randr::Connection::DISCONNECTED,
+                crtcs: vec![20, 21, 22],
+                modes: vec![1],
+                name: b"HDMI-1".to_vec(),
+                ..Default::default()
+            },
+            12 => randr::GetOutputInfoReply {
+                crtc: 0,
+                connection: randr::Connection::CONNECTED,
+                crtcs: vec![20, 21, 22],
+                modes: vec![1],
+                name: b"HDMI-2".to_vec(),
+                ..Default::default()
+            },
+            13 => randr::GetOutputInfoReply {
+                crtc: 21,
+                connection: randr::Connection::CONNECTED,
+                crtcs: vec![20, 21, 22],
+                modes: vec![1],
+                name: b"HDMI-3".to_vec(),
                 ..Default::default()
             },
             14 => randr::GetOutputInfoReply {
@@ -679,7 +1349,9 @@ mod tests {
 
         let outputs: Vec<_> = [10, 11, 12, 13]
             .iter()
-            .map(|output_id| randr_output_to_output(randr_outputs.get(output_id).unwrap(), &modes))
+            .map(|output_id| {
+                randr_output_to_output(randr_outputs.get(output_id).unwrap(), &modes, &crtcs)
+            })
             .collect();
 
         let switch_plan = SwitchPlan {
@@ -691,10 +1363,12 @@ mod tests {
         update_crtcs(
             &switch_plan,
             resolution,
+            &SwitchOptions::default(),
             &modes,
             &mut randr_outputs,
             &mut crtcs,
-        );
+        )
+        .expect("update_crtcs should succeed");
 
         // Assert
         assert_eq!(randr_outputs.get(&10).unwrap().crtc, 0);
@@ -714,6 +1388,199 @@ mod tests {
         assert_eq!(crtc2.rotation, randr::Rotation::ROTATE0);
     }
 
+    fn hardware_mirror_test_outputs(
+        clones: bool,
+    ) -> HashMap<randr::Output, randr::GetOutputInfoReply> {
+        hashmap! {
+            10 => randr::GetOutputInfoReply {
+                crtc: 20,
+                connection: randr::Connection::CONNECTED,
+                crtcs: vec![20, 21],
+                modes: vec![1],
+                clones: if clones { vec![11] } else { Vec::new() },
+                name: b"eDP-1".to_vec(),
+                ..Default::default()
+            },
+            11 => randr::GetOutputInfoReply {
+                crtc: 0,
+                connection: randr::Connection::CONNECTED,
+                crtcs: vec![20, 21],
+                modes: vec![1],
+                clones: if clones { vec![10] } else { Vec::new() },
+                name: b"HDMI-1".to_vec(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn hardware_mirror_test_crtcs() -> HashMap<randr::Crtc, randr::GetCrtcInfoReply> {
+        hashmap! {
+            20 => randr::GetCrtcInfoReply {
+                mode: 1,
+                outputs: vec![10],
+                ..Default::default()
+            },
+            21 => randr::GetCrtcInfoReply::default(),
+        }
+    }
+
+    fn update_crtcs_with_hardware_mirror(
+        randr_outputs: &mut HashMap<randr::Output, randr::GetOutputInfoReply>,
+        crtcs: &mut HashMap<randr::Crtc, randr::GetCrtcInfoReply>,
+    ) -> Result<(), SwitchError> {
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                id: 1,
+                width: 1920,
+                height: 1080,
+                dot_clock: 138700000,
+                htotal: 2080,
+                vtotal: 1111,
+                ..Default::default()
+            }
+        };
+
+        let outputs: Vec<_> = [10, 11]
+            .iter()
+            .map(|output_id| {
+                randr_output_to_output(randr_outputs.get(output_id).unwrap(), &modes, crtcs)
+            })
+            .collect();
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        update_crtcs(
+            &switch_plan,
+            None,
+            &SwitchOptions {
+                mirror_mode: MirrorMode::Hardware,
+                ..Default::default()
+            },
+            &modes,
+            randr_outputs,
+            crtcs,
+        )
+    }
+
+    #[test]
+    fn when_outputs_are_clones_update_crtcs_shares_crtc_in_hardware_mirror_mode() {
+        // Arrange
+        let mut randr_outputs = hardware_mirror_test_outputs(true);
+        let mut crtcs = hardware_mirror_test_crtcs();
+
+        // Act
+        update_crtcs_with_hardware_mirror(&mut randr_outputs, &mut crtcs)
+            .expect("update_crtcs should succeed");
+
+        // Assert
+        assert_eq!(randr_outputs.get(&11).unwrap().crtc, 20);
+        assert_eq!(crtcs.get(&20).unwrap().outputs.as_slice(), [10, 11]);
+        assert!(crtcs.get(&21).unwrap().outputs.is_empty());
+    }
+
+    #[test]
+    fn when_outputs_are_not_clones_update_crtcs_falls_back_to_separate_crtcs() {
+        // Arrange
+        let mut randr_outputs = hardware_mirror_test_outputs(false);
+        let mut crtcs = hardware_mirror_test_crtcs();
+
+        // Act
+        update_crtcs_with_hardware_mirror(&mut randr_outputs, &mut crtcs)
+            .expect("update_crtcs should succeed");
+
+        // Assert
+        assert_eq!(randr_outputs.get(&11).unwrap().crtc, 21);
+        assert_eq!(crtcs.get(&20).unwrap().outputs.as_slice(), [10]);
+        let crtc = crtcs.get(&21).unwrap();
+        assert_eq!(crtc.outputs.as_slice(), [11]);
+        assert_eq!((crtc.x, crtc.y, crtc.mode), (0, 0, 1));
+    }
+
+    #[test]
+    fn when_no_crtc_is_free_update_crtcs_returns_error() {
+        // Arrange
+        let mut randr_outputs = hardware_mirror_test_outputs(false);
+        for output in randr_outputs.values_mut() {
+            output.crtcs = vec![20];
+        }
+        let mut crtcs = hardware_mirror_test_crtcs();
+        crtcs.remove(&21);
+
+        // Act
+        let result = update_crtcs_with_hardware_mirror(&mut randr_outputs, &mut crtcs);
+
+        // Assert
+        assert_eq!(
+            result,
+            Err(SwitchError::NoFreeCrtc {
+                output: "HDMI-1".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn when_no_reposition_update_crtcs_keeps_position_of_enabled_outputs() {
+        // Arrange
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                id: 1,
+                width: 1920,
+                height: 1080,
+                dot_clock: 138700000,
+                htotal: 2080,
+                vtotal: 1111,
+                ..Default::default()
+            }
+        };
+        let mut randr_outputs = hardware_mirror_test_outputs(false);
+        let mut crtcs = hashmap! {
+            20 => randr::GetCrtcInfoReply {
+                x: 1920,
+                y: 0,
+                mode: 1,
+                outputs: vec![10],
+                ..Default::default()
+            },
+            21 => randr::GetCrtcInfoReply::default(),
+        };
+
+        let outputs: Vec<_> = [10, 11]
+            .iter()
+            .map(|output_id| {
+                randr_output_to_output(randr_outputs.get(output_id).unwrap(), &modes, &crtcs)
+            })
+            .collect();
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        // Act
+        update_crtcs(
+            &switch_plan,
+            None,
+            &SwitchOptions {
+                no_reposition: true,
+                ..Default::default()
+            },
+            &modes,
+            &mut randr_outputs,
+            &mut crtcs,
+        )
+        .expect("update_crtcs should succeed");
+
+        // Assert
+        let kept = crtcs.get(&20).unwrap();
+        assert_eq!((kept.x, kept.y, kept.mode), (1920, 0, 1));
+        let enabled = crtcs.get(&21).unwrap();
+        assert_eq!(enabled.outputs.as_slice(), [11]);
+        assert_eq!((enabled.x, enabled.y, enabled.mode), (0, 0, 1));
+    }
+
     #[test]
     fn when_no_modes_available_choose_best_mode_returns_none() {
         // Arrange
@@ -724,7 +1591,12 @@ mod tests {
         let resolution = None;
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert!(mode_id.is_none());
@@ -743,7 +1615,12 @@ mod tests {
         let resolution = None;
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert!(mode_id.is_none());
@@ -763,7 +1640,12 @@ mod tests {
         let resolution = None;
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert_eq!(mode_id, Some(1));
@@ -782,7 +1664,12 @@ mod tests {
         let resolution = None;
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert_eq!(mode_id, Some(1));
@@ -803,12 +1690,53 @@ mod tests {
         let resolution = None;
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert_eq!(mode_id, Some(1));
     }
 
+    #[test]
+    fn choose_best_mode_never_chooses_blacklisted_mode() {
+        // Arrange
+        let output = randr::GetOutputInfoReply {
+            name: b"HDMI-1".to_vec(),
+            modes: vec![1, 2, 3],
+            num_preferred: 1,
+            ..Default::default()
+        };
+        let modes = hashmap!(
+            1 => randr::ModeInfo{id: 1, width: 1920, height: 1080, dot_clock: 50, htotal: 1, vtotal: 1, ..Default::default()},
+            2 => randr::ModeInfo{id: 2, width: 1920, height: 1080, dot_clock: 60, htotal: 1, vtotal: 1, ..Default::default()},
+            3 => randr::ModeInfo{id: 3, width: 1280, height: 720, dot_clock: 60, htotal: 1, vtotal: 1, ..Default::default()},
+        );
+        let mode_restrictions = screen::ModeRestrictions {
+            blacklist: vec!["HDMI-1=1920x1080@50".parse().unwrap()],
+            whitelist: Vec::new(),
+        };
+
+        // Act
+        let mode_id = choose_best_mode(&output, &modes, None, &mode_restrictions);
+        let mode_id_with_resolution = choose_best_mode(
+            &output,
+            &modes,
+            Some(screen::Resolution {
+                width: 1920,
+                height: 1080,
+            }),
+            &mode_restrictions,
+        );
+
+        // Assert
+        assert_eq!(mode_id, Some(2));
+        assert_eq!(mode_id_with_resolution, Some(2));
+    }
+
     #[test]
     fn choose_best_mode_prefers_larger_mode() {
         // Arrange
@@ -823,7 +1751,12 @@ mod tests {
         let resolution = None;
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert_eq!(mode_id, Some(2));
@@ -843,7 +1776,12 @@ mod tests {
         let resolution = None;
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert_eq!(mode_id, Some(2));
@@ -868,7 +1806,12 @@ mod tests {
         });
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert_eq!(mode_id, Some(1));
@@ -891,7 +1834,12 @@ mod tests {
         });
 
         // Act
-        let mode_id = choose_best_mode(&output, &modes, resolution);
+        let mode_id = choose_best_mode(
+            &output,
+            &modes,
+            resolution,
+            &screen::ModeRestrictions::default(),
+        );
 
         // Assert
         assert_eq!(mode_id, Some(2));
@@ -900,15 +1848,16 @@ mod tests {
     #[test]
     fn when_no_crtcs_compute_screen_size_returns_none() {
         // Arrange
-        let modes = HashMap::new();
-        let outputs = HashMap::new();
-        let crtcs = HashMap::new();
+        // Explicit types keep the maps from being silently transposed by type inference.
+        let modes: HashMap<randr::Mode, randr::ModeInfo> = HashMap::new();
+        let outputs: HashMap<randr::Output, randr::GetOutputInfoReply> = HashMap::new();
+        let crtcs: HashMap<randr::Crtc, randr::GetCrtcInfoReply> = HashMap::new();
 
         // Act
-        let size = compute_screen_size(&modes, &crtcs, &outputs);
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &TEST_SIZE_RANGE);
 
         // Assert
-        assert!(size.is_none());
+        assert_eq!(size, Ok(None));
     }
 
     #[test]
@@ -923,10 +1872,10 @@ mod tests {
         };
 
         // Act
-        let size = compute_screen_size(&modes, &outputs, &crtcs);
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &TEST_SIZE_RANGE);
 
         // Assert
-        assert!(size.is_none());
+        assert_eq!(size, Ok(None));
     }
 
     #[test]
@@ -949,17 +1898,17 @@ mod tests {
         };
 
         // Act
-        let size = compute_screen_size(&modes, &outputs, &crtcs);
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &TEST_SIZE_RANGE);
 
         // Assert
         assert_eq!(
             size,
-            Some(ScreenSize {
+            Ok(Some(ScreenSize {
                 width: 650,
                 height: 490,
                 mm_width: px_to_mm(650),
                 mm_height: px_to_mm(490)
-            })
+            }))
         );
     }
 
@@ -984,20 +1933,267 @@ mod tests {
         };
 
         // Act
-        let size = compute_screen_size(&modes, &outputs, &crtcs);
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &TEST_SIZE_RANGE);
 
         // Assert
         assert_eq!(
             size,
-            Some(ScreenSize {
+            Ok(Some(ScreenSize {
                 width: 650,
                 height: 490,
                 mm_width: 220,
                 mm_height: 220,
+            }))
+        );
+    }
+
+    #[test]
+    fn when_some_crtcs_disabled_compute_screen_size_ignores_them() {
+        // Arrange
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            }
+        };
+        let outputs = hashmap! {
+            10 => randr::GetOutputInfoReply { mm_width: 300, mm_height: 200, ..Default::default() },
+            11 => randr::GetOutputInfoReply { mm_width: 600, mm_height: 400, ..Default::default() },
+        };
+        let crtcs = hashmap! {
+            20 => randr::GetCrtcInfoReply { x: 0, y: 0, mode: 1, outputs: vec!{10}, ..Default::default() },
+            21 => randr::GetCrtcInfoReply { x: 1920, y: 0, mode: 0, outputs: Vec::new(), ..Default::default() },
+        };
+
+        // Act
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &TEST_SIZE_RANGE);
+
+        // Assert
+        assert_eq!(
+            size,
+            Ok(Some(ScreenSize {
+                width: 1920,
+                height: 1080,
+                mm_width: 300,
+                mm_height: 200,
+            }))
+        );
+    }
+
+    #[test]
+    fn when_crtcs_rotated_compute_screen_size_swaps_their_dimensions() {
+        // Arrange
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            }
+        };
+        let outputs = hashmap! {
+            10 => randr::GetOutputInfoReply { mm_width: 600, mm_height: 340, ..Default::default() },
+            11 => randr::GetOutputInfoReply { mm_width: 600, mm_height: 340, ..Default::default() },
+        };
+        let crtcs = hashmap! {
+            20 => randr::GetCrtcInfoReply {
+                x: 0,
+                y: 0,
+                mode: 1,
+                rotation: randr::Rotation::ROTATE90,
+                outputs: vec!{10},
+                ..Default::default()
+            },
+            21 => randr::GetCrtcInfoReply {
+                x: 0,
+                y: 1920,
+                mode: 1,
+                rotation: randr::Rotation::ROTATE90,
+                outputs: vec!{11},
+                ..Default::default()
+            },
+        };
+
+        // Act
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &TEST_SIZE_RANGE);
+
+        // Assert
+        assert_eq!(
+            size,
+            Ok(Some(ScreenSize {
+                width: 1080,
+                height: 3840,
+                mm_width: 600,
+                mm_height: 340,
+            }))
+        );
+    }
+
+    #[test]
+    fn when_crtcs_exceed_max_size_compute_screen_size_returns_error() {
+        // Arrange
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            }
+        };
+        let outputs = hashmap! {
+            10 => randr::GetOutputInfoReply { ..Default::default() },
+            11 => randr::GetOutputInfoReply { ..Default::default() },
+        };
+        let crtcs = hashmap! {
+            20 => randr::GetCrtcInfoReply { x: 0, y: 0, mode: 1, outputs: vec!{10}, ..Default::default() },
+            21 => randr::GetCrtcInfoReply { x: 1920, y: 0, mode: 1, outputs: vec!{11}, ..Default::default() },
+        };
+        let size_range = randr::GetScreenSizeRangeReply {
+            max_width: 2048,
+            max_height: 2048,
+            ..TEST_SIZE_RANGE
+        };
+
+        // Act
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &size_range);
+
+        // Assert
+        assert_eq!(
+            size,
+            Err(ScreenSizeError::TooLarge {
+                width: 3840,
+                height: 1080,
+                max_width: 2048,
+                max_height: 2048,
             })
         );
     }
 
+    #[test]
+    fn when_crtcs_overflow_u16_compute_screen_size_returns_error() {
+        // Arrange
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                width: 40000,
+                height: 1080,
+                ..Default::default()
+            }
+        };
+        let outputs = hashmap! {
+            10 => randr::GetOutputInfoReply { ..Default::default() },
+        };
+        let crtcs = hashmap! {
+            20 => randr::GetCrtcInfoReply { x: 30000, y: 0, mode: 1, outputs: vec!{10}, ..Default::default() },
+            21 => randr::GetCrtcInfoReply { x: -10000, y: 0, mode: 1, outputs: vec!{10}, ..Default::default() },
+        };
+
+        // Act
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &TEST_SIZE_RANGE);
+
+        // Assert
+        assert!(size.is_err());
+    }
+
+    #[test]
+    fn when_crtcs_are_smaller_than_min_size_compute_screen_size_returns_min_size() {
+        // Arrange
+        let modes = hashmap! {
+            1 => randr::ModeInfo {
+                width: 640,
+                height: 480,
+                ..Default::default()
+            }
+        };
+        let outputs = hashmap! {
+            10 => randr::GetOutputInfoReply { mm_width: 400, mm_height: 300, ..Default::default() },
+        };
+        let crtcs = hashmap! {
+            20 => randr::GetCrtcInfoReply { x: 0, y: 0, mode: 1, outputs: vec!{10}, ..Default::default() },
+        };
+        let size_range = randr::GetScreenSizeRangeReply {
+            min_width: 800,
+            min_height: 600,
+            ..TEST_SIZE_RANGE
+        };
+
+        // Act
+        let size = compute_screen_size(&modes, &outputs, &crtcs, &size_range);
+
+        // Assert
+        assert_eq!(
+            size,
+            Ok(Some(ScreenSize {
+                width: 800,
+                height: 600,
+                mm_width: 400,
+                mm_height: 300,
+            }))
+        );
+    }
+
+    #[test]
+    fn when_no_resize_build_config_requests_skips_screen_size() {
+        // Arrange
+        let crtcs = hashmap! {
+            20 => randr::GetCrtcInfoReply { x: 0, y: 0, mode: 1, outputs: vec!{10}, ..Default::default() },
+        };
+        let screen_size = || {
+            Some(ScreenSize {
+                width: 1920,
+                height: 1080,
+                mm_width: 344,
+                mm_height: 194,
+            })
+        };
+        let options = SwitchOptions {
+            no_resize: true,
+            ..Default::default()
+        };
+
+        // Act
+        let resized = build_config_requests(&crtcs, screen_size(), &SwitchOptions::default());
+        let not_resized = build_config_requests(&crtcs, screen_size(), &options);
+
+        // Assert
+        assert_eq!(
+            resized,
+            vec![
+                ConfigRequest::SetCrtcConfig(20),
+                ConfigRequest::SetScreenSize(screen_size().unwrap()),
+            ]
+        );
+        assert_eq!(not_resized, vec![ConfigRequest::SetCrtcConfig(20)]);
+    }
+
+    const TEST_SIZE_RANGE: randr::GetScreenSizeRangeReply = randr::GetScreenSizeRangeReply {
+        sequence: 0,
+        length: 0,
+        min_width: 0,
+        min_height: 0,
+        max_width: u16::MAX,
+        max_height: u16::MAX,
+    };
+
+    #[test]
+    fn when_gamma_size_is_zero_compute_gamma_ramp_returns_empty_ramp() {
+        assert!(compute_gamma_ramp(0, 1.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn when_gamma_and_brightness_are_identity_compute_gamma_ramp_returns_linear_ramp() {
+        assert_eq!(compute_gamma_ramp(4, 1.0, 1.0), [0, 16384, 32768, 49151]);
+    }
+
+    #[test]
+    fn compute_gamma_ramp_scales_by_brightness() {
+        assert_eq!(compute_gamma_ramp(4, 1.0, 0.5), [0, 8192, 16384, 24576]);
+    }
+
+    #[test]
+    fn compute_gamma_ramp_applies_gamma() {
+        assert_eq!(compute_gamma_ramp(4, 2.0, 1.0), [0, 32768, 46340, 56755]);
+        assert_eq!(compute_gamma_ramp(4, 0.5, 1.0), [0, 4096, 16384, 36863]);
+    }
+
     #[test]
     fn px_to_mm_test() {
         assert_eq!(px_to_mm(0), 0);
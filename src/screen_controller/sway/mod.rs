@@ -4,12 +4,22 @@ use std::fmt::Write;
 use std::process;
 
 use crate::{
-    screen::{Resolution, Screen},
-    switch::SwitchPlan,
+    layout::Rotation,
+    screen::{Color, Position, Resolution, ScaleFilter, Screen},
+    switch::{self, SwitchOptions, SwitchPlan},
 };
 
+use super::SwitchError;
 use super::utils::run;
 
+/// Properties sway keeps when re-enabling an output, set explicitly when requested or known.
+struct OutputProperties {
+    scale_filter: Option<ScaleFilter>,
+    allow_tearing: Option<bool>,
+    /// In milliseconds, 0 for off.
+    max_render_time_ms: Option<u32>,
+}
+
 struct Swaymsg {
     command: process::Command,
 }
@@ -26,82 +36,192 @@ impl Swaymsg {
         self
     }
 
+    fn background(mut self, output_name: &str, color: Color) -> Self {
+        self.command.arg(format!(
+            "output \"{output_name}\" background {color} solid_color"
+        ));
+        self
+    }
+
     fn disable(mut self, output_name: &str) -> Self {
         self.command
             .arg(format!("output \"{output_name}\" disable"));
         self
     }
 
-    fn enable(mut self, output_name: &str, resolution: Option<Resolution>) -> Self {
-        let mut msg = format!("output \"{output_name}\" enable position 0 0");
-        if let Some(resolution) = resolution {
-            write!(
+    fn enable(
+        mut self,
+        output_name: &str,
+        resolution: Option<Resolution>,
+        refresh_rate_millihz: Option<u32>,
+        position: Position,
+        rotation: Rotation,
+        properties: OutputProperties,
+    ) -> Self {
+        let mut msg = format!(
+            "output \"{output_name}\" enable position {} {}",
+            position.x, position.y
+        );
+        match (resolution, refresh_rate_millihz) {
+            (Some(resolution), Some(refresh_rate_millihz)) => write!(
+                &mut msg,
+                " mode \"{}x{}@{}.{:03}Hz\"",
+                resolution.width,
+                resolution.height,
+                refresh_rate_millihz / 1000,
+                refresh_rate_millihz % 1000
+            )
+            .expect("unable to append to msg"),
+            (Some(resolution), None) => write!(
                 &mut msg,
                 " mode \"{}x{}\"",
                 resolution.width, resolution.height
             )
-            .expect("unable to append to msg");
+            .expect("unable to append to msg"),
+            (None, _) => {}
+        }
+        match rotation {
+            Rotation::Normal => {}
+            // Sway transforms rotate clockwise.
+            Rotation::Left => msg.push_str(" transform 270"),
+        }
+        match properties.scale_filter {
+            None => {}
+            Some(ScaleFilter::Bilinear) => msg.push_str(" scale_filter linear"),
+            Some(ScaleFilter::Nearest) => msg.push_str(" scale_filter nearest"),
+        }
+        match properties.allow_tearing {
+            None => {}
+            Some(true) => msg.push_str(" allow_tearing on"),
+            Some(false) => msg.push_str(" allow_tearing off"),
+        }
+        match properties.max_render_time_ms {
+            None => {}
+            Some(0) => msg.push_str(" max_render_time off"),
+            Some(max_render_time_ms) => {
+                write!(&mut msg, " max_render_time {max_render_time_ms}")
+                    .expect("unable to append to msg");
+            }
         }
         self.command.arg(msg);
         self
     }
 
+    fn power_off(mut self, output_name: &str) -> Self {
+        self.command
+            .arg(format!("output \"{output_name}\" power off"));
+        self
+    }
+
+    fn power_on(mut self, output_name: &str) -> Self {
+        self.command
+            .arg(format!("output \"{output_name}\" power on"));
+        self
+    }
+
     fn command(self) -> process::Command {
         self.command
     }
 }
 
-pub(super) fn get_outputs() -> Screen {
-    parsing::parse(&run(Swaymsg::new().get_outputs().command()).stdout)
+pub(super) fn get_outputs() -> Result<Screen, SwitchError> {
+    let output = run(Swaymsg::new().get_outputs().command())?;
+    parsing::parse(&output.stdout).map_err(|err| SwitchError::ParseOutputs {
+        reason: err.to_string(),
+    })
 }
 
 fn build_switch_commands(
     switch_plan: &SwitchPlan,
     resolution: Option<Resolution>,
+    options: &SwitchOptions,
 ) -> Vec<process::Command> {
-    let disable_commands = switch_plan
-        .outputs_to_disable
-        .iter()
-        .map(|output| Swaymsg::new().disable(&output.name).command());
+    let disable_commands = switch_plan.outputs_to_disable.iter().flat_map(|output| {
+        // Some panels stay powered when disabled, so blank them first.
+        let background_command = options
+            .disabled_background
+            .map(|color| Swaymsg::new().background(&output.name, color).command());
+        // `power` replaces `dpms`, which is deprecated since sway 1.8.
+        let power_off_command = options
+            .dpms_on_disable
+            .then(|| Swaymsg::new().power_off(&output.name).command());
+        background_command
+            .into_iter()
+            .chain(std::iter::once(
+                Swaymsg::new().disable(&output.name).command(),
+            ))
+            .chain(power_off_command)
+    });
 
-    let enable_commands = switch_plan
-        .outputs_to_enable
-        .iter()
-        .map(|output| Swaymsg::new().enable(&output.name, resolution).command());
+    let enable_commands = switch_plan.outputs_to_enable.iter().filter(|output| {
+        // Sway reports the current mode of active outputs, so ones already configured
+        // as planned can be left alone.
+        let unchanged = !options.force
+            && resolution.is_some_and(|resolution| {
+                switch::is_enabled_as_planned(output, resolution, options)
+            });
+        if unchanged {
+            log::debug!("Output {} is already enabled as planned", output.name);
+        }
+        !unchanged
+    });
+    let enable_commands = enable_commands.flat_map(|output| {
+        // Without a refresh rate, sway picks the highest one, and without a mode the preferred one,
+        // so restricted outputs get an allowed mode set explicitly.
+        let mode = options.mode_restrictions.choose_mode(output, resolution);
+        let enable_command = Swaymsg::new()
+            .enable(
+                &output.name,
+                mode.map(|mode| mode.resolution).or(resolution),
+                mode.map(|mode| mode.refresh_rate_millihz),
+                options.layout.position(&output.name),
+                options.layout.rotation(&output.name),
+                // Sway keeps these for re-enabled outputs, but be explicit about them.
+                OutputProperties {
+                    scale_filter: options.scale_filter.or(output.scale_filter),
+                    allow_tearing: options.allow_tearing.or(output.allow_tearing),
+                    max_render_time_ms: options.max_render_time_ms.or(output.max_render_time_ms),
+                },
+            )
+            .command();
+        // Enabling an output does not power it on if it was powered off while enabled,
+        // or by a previous switch with --dpms-on-disable.
+        let power_on_command = (!output.powered && (output.enabled || options.dpms_on_disable))
+            .then(|| Swaymsg::new().power_on(&output.name).command());
+        std::iter::once(enable_command).chain(power_on_command)
+    });
 
     disable_commands.chain(enable_commands).collect()
 }
 
-pub(super) fn switch_outputs(switch_plan: &SwitchPlan, resolution: Option<Resolution>) {
-    for command in build_switch_commands(switch_plan, resolution) {
-        run(command);
+pub(super) fn switch_outputs(
+    switch_plan: &SwitchPlan,
+    resolution: Option<Resolution>,
+    options: &SwitchOptions,
+) -> Result<(), SwitchError> {
+    if options.color_adjustment.is_requested() {
+        log::warn!("Sway does not support gamma and brightness adjustment, ignoring");
+    }
+
+    for command in build_switch_commands(switch_plan, resolution, options) {
+        run(command)?;
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::utils::assert_command_eq;
     use super::*;
-    use crate::screen::{Location, Output};
+    use crate::layout::{self, Direction, Relation};
+    use crate::screen::{Location, Mode, ModeRestrictions, Output};
 
     #[test]
     fn test_make_switch_commands_without_resolution() {
         // Arrange
         let outputs = [
-            Output {
-                name: "eDP-1".to_string(),
-                connected: true,
-                enabled: true,
-                modes: Vec::new(),
-                location: Location::Internal,
-            },
-            Output {
-                name: "HDMI-A-2".to_string(),
-                connected: true,
-                enabled: false,
-                modes: Vec::new(),
-                location: Location::External,
-            },
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-A-2", true, false),
         ];
 
         let switch_plan = SwitchPlan {
@@ -112,7 +232,7 @@ mod tests {
         let resolution = None;
 
         // Act
-        let commands = build_switch_commands(&switch_plan, resolution);
+        let commands = build_switch_commands(&switch_plan, resolution, &SwitchOptions::default());
 
         // Assert
         assert!(commands.len() == 2);
@@ -129,23 +249,218 @@ mod tests {
     }
 
     #[test]
-    fn test_make_switch_commands_with_resolution() {
+    fn test_make_switch_commands_pins_refresh_rate_of_restricted_outputs() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = Output {
+            modes: vec![mode(1920, 1080, 60000), mode(1920, 1080, 50000)],
+            ..Output::test("HDMI-1", true, false)
+        };
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&output],
+        };
+
+        let options = SwitchOptions {
+            mode_restrictions: ModeRestrictions {
+                blacklist: vec!["HDMI-1=1920x1080@60".parse().unwrap()],
+                whitelist: Vec::new(),
+            },
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 1);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"HDMI-1\" enable position 0 0 mode \"1920x1080@50.000Hz\""],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_scale_filter() {
         // Arrange
+        let outputs = [Output::test("HDMI-A-2", true, false)];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0]],
+        };
+
+        let options = SwitchOptions {
+            scale_filter: Some(ScaleFilter::Nearest),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 1);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"HDMI-A-2\" enable position 0 0 scale_filter nearest"],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_keeps_current_scale_filter() {
+        // Arrange
+        let outputs = [Output {
+            scale_filter: Some(ScaleFilter::Bilinear),
+            ..Output::test("HDMI-A-2", true, true)
+        }];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0]],
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &SwitchOptions::default());
+
+        // Assert
+        assert!(commands.len() == 1);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"HDMI-A-2\" enable position 0 0 scale_filter linear"],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_tearing_and_max_render_time() {
+        // Arrange
+        let output = |name: &str, max_render_time_ms| Output {
+            location: Location::External,
+            allow_tearing: Some(false),
+            max_render_time_ms,
+            ..Output::test(name, true, false)
+        };
+        let outputs = [output("HDMI-A-2", None), output("DP-1", Some(7))];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0]],
+        };
+        let kept_switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[1]],
+        };
+
+        let options = SwitchOptions {
+            allow_tearing: Some(true),
+            max_render_time_ms: Some(0),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+        let kept_commands =
+            build_switch_commands(&kept_switch_plan, None, &SwitchOptions::default());
+
+        // Assert
+        assert!(commands.len() == 1);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"HDMI-A-2\" enable position 0 0 allow_tearing on max_render_time off"],
+        );
+        assert!(kept_commands.len() == 1);
+        assert_command_eq(
+            &kept_commands[0],
+            "swaymsg",
+            &["output \"DP-1\" enable position 0 0 allow_tearing off max_render_time 7"],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_skips_output_already_enabled_as_planned() {
+        // Arrange
+        let resolution = Resolution {
+            width: 1920,
+            height: 1080,
+        };
         let outputs = [
             Output {
-                name: "eDP-1".to_string(),
-                connected: true,
-                enabled: true,
-                modes: Vec::new(),
-                location: Location::Internal,
+                current_resolution: Some(resolution),
+                current_position: Some(Position { x: 0, y: 0 }),
+                ..Output::test("eDP-1", true, true)
             },
-            Output {
-                name: "HDMI-A-2".to_string(),
-                connected: true,
-                enabled: true,
-                modes: Vec::new(),
-                location: Location::External,
+            Output::test("HDMI-A-2", true, false),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        // Act
+        let commands =
+            build_switch_commands(&switch_plan, Some(resolution), &SwitchOptions::default());
+        let forced_commands = build_switch_commands(
+            &switch_plan,
+            Some(resolution),
+            &SwitchOptions {
+                force: true,
+                ..Default::default()
             },
+        );
+
+        // Assert
+        assert!(commands.len() == 1);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"HDMI-A-2\" enable position 0 0 mode \"1920x1080\""],
+        );
+        assert!(forced_commands.len() == 2);
+    }
+
+    #[test]
+    fn test_make_switch_commands_powers_on_powered_off_output() {
+        // Arrange
+        let outputs = [Output {
+            powered: false,
+            ..Output::test("eDP-1", true, true)
+        }];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0]],
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &SwitchOptions::default());
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"eDP-1\" enable position 0 0"],
+        );
+        assert_command_eq(&commands[1], "swaymsg", &["output \"eDP-1\" power on"]);
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_resolution() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-A-2", true, true),
         ];
 
         let switch_plan = SwitchPlan {
@@ -159,7 +474,7 @@ mod tests {
         });
 
         // Act
-        let commands = build_switch_commands(&switch_plan, resolution);
+        let commands = build_switch_commands(&switch_plan, resolution, &SwitchOptions::default());
 
         // Assert
         assert!(commands.len() == 2);
@@ -170,4 +485,154 @@ mod tests {
             &["output \"HDMI-A-2\" enable position 0 0 mode \"1920x1080\""],
         );
     }
+
+    #[test]
+    fn test_make_switch_commands_with_disabled_background() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-A-2", true, true),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&outputs[0]],
+            outputs_to_enable: vec![&outputs[1]],
+        };
+
+        let options = SwitchOptions {
+            disabled_background: Some(Color {
+                red: 0,
+                green: 0,
+                blue: 0,
+            }),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 3);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"eDP-1\" background #000000 solid_color"],
+        );
+        assert_command_eq(&commands[1], "swaymsg", &["output \"eDP-1\" disable"]);
+        assert_command_eq(
+            &commands[2],
+            "swaymsg",
+            &["output \"HDMI-A-2\" enable position 0 0"],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_dpms_on_disable() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output {
+                powered: false,
+                ..Output::test("HDMI-A-2", true, false)
+            },
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&outputs[0]],
+            outputs_to_enable: vec![&outputs[1]],
+        };
+
+        let options = SwitchOptions {
+            dpms_on_disable: true,
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 4);
+        assert_command_eq(&commands[0], "swaymsg", &["output \"eDP-1\" disable"]);
+        assert_command_eq(&commands[1], "swaymsg", &["output \"eDP-1\" power off"]);
+        assert_command_eq(
+            &commands[2],
+            "swaymsg",
+            &["output \"HDMI-A-2\" enable position 0 0"],
+        );
+        assert_command_eq(&commands[3], "swaymsg", &["output \"HDMI-A-2\" power on"]);
+    }
+
+    #[test]
+    fn test_make_switch_commands_without_dpms_on_disable_leaves_power_alone() {
+        // Arrange
+        let outputs = [Output {
+            powered: false,
+            ..Output::test("HDMI-A-2", true, false)
+        }];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0]],
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &SwitchOptions::default());
+
+        // Assert
+        assert!(commands.len() == 1);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"HDMI-A-2\" enable position 0 0"],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_layout() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-A-2", true, false),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        let resolution = Some(Resolution {
+            width: 1920,
+            height: 1080,
+        });
+
+        let relations = [Relation {
+            output: "eDP-1".to_string(),
+            direction: Direction::LeftOf,
+            anchor: "HDMI-A-2".to_string(),
+        }];
+        let options = SwitchOptions {
+            layout: layout::resolve(
+                &relations,
+                &layout::estimate_sizes(&switch_plan.outputs_to_enable, resolution),
+            )
+            .expect("layout should resolve"),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, resolution, &options);
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(
+            &commands[0],
+            "swaymsg",
+            &["output \"eDP-1\" enable position 0 0 mode \"1920x1080\""],
+        );
+        assert_command_eq(
+            &commands[1],
+            "swaymsg",
+            &["output \"HDMI-A-2\" enable position 1920 0 mode \"1920x1080\""],
+        );
+    }
 }
@@ -1,26 +1,48 @@
-use crate::screen::{Location, Mode, Output, Resolution, Screen};
+use crate::screen::{Location, Mode, Output, Position, Resolution, ScaleFilter, Screen};
 
 use serde::Deserialize;
 
+// Unknown fields are ignored, and fields that are not essential
+// for switching are allowed to be missing or null.
 #[derive(Debug, Deserialize)]
 struct RpcOutput<'a> {
     name: &'a str,
+    #[serde(default)]
     active: bool,
-    modes: Vec<RpcMode>,
+    modes: Option<Vec<RpcMode>>,
+    current_mode: Option<RpcMode>,
+    rect: Option<RpcRect>,
+    /// Reported by sway 1.8 and later, replacing `dpms`.
+    power: Option<bool>,
+    dpms: Option<bool>,
+    /// One of "linear", "nearest" or "smart".
+    scale_filter: Option<&'a str>,
+    /// In milliseconds, 0 if off.
+    max_render_time: Option<u32>,
+    allow_tearing: Option<bool>,
 }
 
+/// Area of the output in the layout, in logical pixels, i.e. after scaling.
 #[derive(Debug, Deserialize)]
+struct RpcRect {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
 struct RpcMode {
     width: u32,
     height: u32,
+    #[serde(default)]
     refresh: u32,
 }
 
-pub(super) fn parse(swaymsg_output: &[u8]) -> Screen {
-    let rpc_outputs: Vec<RpcOutput> = serde_json::from_slice(swaymsg_output)
-        .expect("failed to parse output of swaymsg -t get_outputs");
+pub(super) fn parse(swaymsg_output: &[u8]) -> Result<Screen, serde_json::Error> {
+    let rpc_outputs: Vec<RpcOutput> = serde_json::from_slice(swaymsg_output)?;
 
-    Screen {
+    Ok(Screen {
         outputs: rpc_outputs
             .iter()
             .map(|rpc_output| Output {
@@ -31,18 +53,58 @@ pub(super) fn parse(swaymsg_output: &[u8]) -> Screen {
                 modes: rpc_output
                     .modes
                     .iter()
+                    .flatten()
                     .map(|rpc_mode| Mode {
                         resolution: Resolution {
                             width: rpc_mode.width,
                             height: rpc_mode.height,
                         },
                         refresh_rate_millihz: rpc_mode.refresh,
+                        // Sway does not report which modes are preferred.
+                        preferred: false,
+                        active: rpc_output.active
+                            && rpc_output.current_mode.as_ref() == Some(rpc_mode),
+                        refresh_rates_millihz: Vec::new(),
                     })
                     .collect(),
                 location: Location::from_output_name(rpc_output.name),
+                powered: rpc_output.power.or(rpc_output.dpms).unwrap_or(true),
+                // The size of the rect matches the mode only if the output is not scaled,
+                // so it is used only when the mode is not reported.
+                current_resolution: rpc_output
+                    .current_mode
+                    .as_ref()
+                    .map(|rpc_mode| Resolution {
+                        width: rpc_mode.width,
+                        height: rpc_mode.height,
+                    })
+                    .or_else(|| {
+                        rpc_output.rect.as_ref().map(|rpc_rect| Resolution {
+                            width: rpc_rect.width,
+                            height: rpc_rect.height,
+                        })
+                    })
+                    .filter(|_| rpc_output.active),
+                current_position: rpc_output.rect.as_ref().filter(|_| rpc_output.active).map(
+                    |rpc_rect| Position {
+                        x: rpc_rect.x,
+                        y: rpc_rect.y,
+                    },
+                ),
+                scale_filter: rpc_output
+                    .scale_filter
+                    .and_then(|scale_filter| match scale_filter {
+                        "linear" => Some(ScaleFilter::Bilinear),
+                        "nearest" => Some(ScaleFilter::Nearest),
+                        _ => None,
+                    }),
+                // Sway does not report the physical size of outputs.
+                physical_size_mm: None,
+                allow_tearing: rpc_output.allow_tearing,
+                max_render_time_ms: rpc_output.max_render_time,
             })
             .collect(),
-    }
+    })
 }
 
 #[cfg(test)]
@@ -54,7 +116,7 @@ mod tests {
         // Arrange
 
         // Act
-        let screen = parse(TEST_GET_OUTPUTS.as_bytes());
+        let screen = parse(TEST_GET_OUTPUTS.as_bytes()).expect("failed to parse");
 
         // Assert
         assert_eq!(screen.outputs.len(), 2);
@@ -69,15 +131,232 @@ mod tests {
                     width: 4096,
                     height: 2160
                 },
-                refresh_rate_millihz: 30000
+                refresh_rate_millihz: 30000,
+                preferred: false,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
             }
         );
+        assert_eq!(
+            screen.outputs[0].current_resolution,
+            Some(Resolution {
+                width: 1920,
+                height: 1080
+            })
+        );
+        assert_eq!(
+            screen.outputs[0].current_position,
+            Some(Position { x: 0, y: 0 })
+        );
+        let mut active_modes: Vec<_> = screen.outputs[0]
+            .modes
+            .iter()
+            .filter(|mode| mode.active)
+            .map(|mode| (mode.resolution, mode.refresh_rate_millihz))
+            .collect();
+        // The current mode is listed twice, like other modes with several picture aspect ratios.
+        active_modes.dedup();
+        assert_eq!(
+            active_modes,
+            [(
+                Resolution {
+                    width: 1920,
+                    height: 1080
+                },
+                60000
+            )]
+        );
+        assert_eq!(screen.outputs[0].scale_filter, Some(ScaleFilter::Bilinear));
         assert_eq!(screen.outputs[1].name, "eDP-1");
         assert!(screen.outputs[1].connected);
         assert!(!screen.outputs[1].enabled);
+        assert!(screen.outputs[1].current_resolution.is_none());
+        assert!(screen.outputs[1].current_position.is_none());
         assert_eq!(screen.outputs[1].modes.len(), 2);
     }
 
+    #[test]
+    fn test_get_outputs_output_with_missing_optional_fields_parses_ok() {
+        // Arrange
+
+        // Act
+        let screen = parse(TEST_GET_OUTPUTS_MINIMAL.as_bytes()).expect("failed to parse");
+
+        // Assert
+        assert_eq!(screen.outputs.len(), 3);
+        assert_eq!(screen.outputs[0].name, "eDP-1");
+        assert!(!screen.outputs[0].enabled);
+        assert!(screen.outputs[0].modes.is_empty());
+        assert_eq!(screen.outputs[1].name, "HDMI-A-1");
+        assert!(screen.outputs[1].enabled);
+        assert!(screen.outputs[1].modes.is_empty());
+        assert_eq!(screen.outputs[2].name, "DP-1");
+        assert_eq!(
+            screen.outputs[2].modes,
+            [Mode {
+                resolution: Resolution {
+                    width: 1920,
+                    height: 1080
+                },
+                refresh_rate_millihz: 0,
+                preferred: false,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_get_outputs_output_power_state_parses_ok() {
+        // Arrange
+        let swaymsg_output = br#"[
+            {"name": "eDP-1", "active": true, "power": false, "dpms": true},
+            {"name": "HDMI-A-1", "active": true, "dpms": false},
+            {"name": "DP-1", "active": true}
+        ]"#;
+
+        // Act
+        let screen = parse(swaymsg_output).expect("failed to parse");
+
+        // Assert
+        assert!(screen.outputs[0].enabled);
+        assert!(!screen.outputs[0].powered);
+        assert!(!screen.outputs[1].powered);
+        assert!(screen.outputs[2].powered);
+    }
+
+    #[test]
+    fn test_get_outputs_output_scale_filter_parses_ok() {
+        // Arrange
+        let swaymsg_output = br#"[
+            {"name": "eDP-1", "active": true, "scale_filter": "nearest"},
+            {"name": "HDMI-A-1", "active": true, "scale_filter": "smart"},
+            {"name": "DP-1", "active": true}
+        ]"#;
+
+        // Act
+        let screen = parse(swaymsg_output).expect("failed to parse");
+
+        // Assert
+        assert_eq!(screen.outputs[0].scale_filter, Some(ScaleFilter::Nearest));
+        assert_eq!(screen.outputs[1].scale_filter, None);
+        assert_eq!(screen.outputs[2].scale_filter, None);
+    }
+
+    #[test]
+    fn test_get_outputs_output_render_settings_parse_ok() {
+        // Arrange
+        let swaymsg_output = br#"[
+            {"name": "eDP-1", "active": true, "max_render_time": 0, "allow_tearing": false},
+            {"name": "DP-1", "active": true, "max_render_time": 7, "allow_tearing": true},
+            {"name": "HDMI-A-1", "active": true}
+        ]"#;
+
+        // Act
+        let screen = parse(swaymsg_output).expect("failed to parse");
+
+        // Assert
+        assert_eq!(screen.outputs[0].max_render_time_ms, Some(0));
+        assert_eq!(screen.outputs[0].allow_tearing, Some(false));
+        assert_eq!(screen.outputs[1].max_render_time_ms, Some(7));
+        assert_eq!(screen.outputs[1].allow_tearing, Some(true));
+        assert_eq!(screen.outputs[2].max_render_time_ms, None);
+        assert_eq!(screen.outputs[2].allow_tearing, None);
+    }
+
+    #[test]
+    fn test_get_outputs_output_current_mode_parses_ok() {
+        // Arrange
+        let swaymsg_output = br#"[
+            {"name": "eDP-1", "active": true, "current_mode": {"width": 2560, "height": 1600, "refresh": 165000}},
+            {"name": "HDMI-A-1", "active": false, "current_mode": {"width": 1920, "height": 1080, "refresh": 60000}},
+            {"name": "DP-1", "active": true}
+        ]"#;
+
+        // Act
+        let screen = parse(swaymsg_output).expect("failed to parse");
+
+        // Assert
+        assert_eq!(
+            screen.outputs[0].current_resolution,
+            Some(Resolution {
+                width: 2560,
+                height: 1600
+            })
+        );
+        // Inactive outputs may still report the mode they were last set to.
+        assert_eq!(screen.outputs[1].current_resolution, None);
+        assert_eq!(screen.outputs[2].current_resolution, None);
+    }
+
+    #[test]
+    fn test_get_outputs_output_rect_parses_ok() {
+        // Arrange
+        let swaymsg_output = br#"[
+            {"name": "eDP-1", "active": true, "rect": {"x": 1920, "y": 0, "width": 1280, "height": 800}},
+            {"name": "HDMI-A-1", "active": true, "rect": {"x": 0, "y": 0, "width": 1536, "height": 864},
+             "current_mode": {"width": 1920, "height": 1080, "refresh": 60000}},
+            {"name": "DP-1", "active": false, "rect": {"x": 0, "y": 0, "width": 0, "height": 0}}
+        ]"#;
+
+        // Act
+        let screen = parse(swaymsg_output).expect("failed to parse");
+
+        // Assert
+        assert_eq!(
+            screen.outputs[0].current_resolution,
+            Some(Resolution {
+                width: 1280,
+                height: 800
+            })
+        );
+        assert_eq!(
+            screen.outputs[0].current_position,
+            Some(Position { x: 1920, y: 0 })
+        );
+        // A scaled output reports its logical size in the rect.
+        assert_eq!(
+            screen.outputs[1].current_resolution,
+            Some(Resolution {
+                width: 1920,
+                height: 1080
+            })
+        );
+        assert_eq!(screen.outputs[2].current_resolution, None);
+        assert_eq!(screen.outputs[2].current_position, None);
+    }
+
+    #[test]
+    fn test_malformed_get_outputs_output_returns_error() {
+        assert!(parse(b"").is_err());
+        assert!(parse(b"{}").is_err());
+        assert!(parse(br#"[{"active": true}]"#).is_err());
+        assert!(parse(br#"[{"name": "eDP-1", "modes": [{"width": 1920}]}]"#).is_err());
+    }
+
+    const TEST_GET_OUTPUTS_MINIMAL: &str = r#"
+[
+  {
+    "name": "eDP-1"
+  },
+  {
+    "name": "HDMI-A-1",
+    "active": true,
+    "modes": null,
+    "some_future_field": {"answer": 42}
+  },
+  {
+    "name": "DP-1",
+    "modes": [
+      {
+        "width": 1920,
+        "height": 1080
+      }
+    ]
+  }
+]
+    "#;
+
     const TEST_GET_OUTPUTS: &str = r#"
 [
   {
@@ -1,17 +1,40 @@
-use std::process;
+use super::SwitchError;
+use std::{io, process};
 
-pub(super) fn run(mut command: process::Command) -> process::Output {
+/// Runs the command, failing if it exits unsuccessfully.
+pub(super) fn run(command: process::Command) -> Result<process::Output, SwitchError> {
+    let description = format!("{command:?}");
+    let output = run_unchecked(command)?;
+
+    if !output.status.success() {
+        return Err(SwitchError::CommandFailed {
+            command: description,
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(output)
+}
+
+/// Runs the command, leaving it to the caller to check whether it succeeded.
+pub(super) fn run_unchecked(mut command: process::Command) -> Result<process::Output, SwitchError> {
     log::debug!("Running {command:?}");
-    let output = command.output().expect("failed to start");
+    let output = command.output().map_err(|err| {
+        let binary = command.get_program().to_string_lossy().into_owned();
+        if err.kind() == io::ErrorKind::NotFound {
+            SwitchError::BackendNotFound { binary }
+        } else {
+            SwitchError::BackendNotStarted {
+                binary,
+                reason: err.to_string(),
+            }
+        }
+    })?;
 
     log::debug!("Output: {output:?}");
 
-    assert!(
-        output.status.success(),
-        "{command:?} exited with {output:?}"
-    );
-
-    output
+    Ok(output)
 }
 
 #[cfg(test)]
@@ -47,9 +70,71 @@ mod tests {
         command.arg("OK");
 
         // Act
-        let output = run(command);
+        let output = run(command).unwrap();
 
         // Assert
         assert_eq!(output.stdout, b"OK\n");
     }
+
+    #[test]
+    fn run_reports_missing_binary() {
+        // Arrange
+        let command = process::Command::new("switch-display-missing-binary");
+
+        // Act
+        let result = run(command);
+
+        // Assert
+        let err = result.unwrap_err();
+        assert_eq!(
+            err,
+            SwitchError::BackendNotFound {
+                binary: "switch-display-missing-binary".to_string()
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Binary 'switch-display-missing-binary' not found. Is it installed?"
+        );
+    }
+
+    #[test]
+    fn run_reports_unstartable_binary() {
+        // Arrange
+        let command = process::Command::new("/");
+
+        // Act
+        let result = run(command);
+
+        // Assert
+        let err = result.unwrap_err();
+        assert_eq!(
+            err,
+            SwitchError::BackendNotStarted {
+                binary: "/".to_string(),
+                reason: "Permission denied (os error 13)".to_string(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Unable to start '/': Permission denied (os error 13)"
+        );
+    }
+
+    #[test]
+    fn run_reports_failed_command() {
+        // Arrange
+        let mut command = process::Command::new("sh");
+        command.args(["-c", "echo \"Can't open display\" >&2; exit 1"]);
+
+        // Act
+        let result = run(command);
+
+        // Assert
+        let Err(SwitchError::CommandFailed { status, stderr, .. }) = result else {
+            panic!("expected a failed command, got {result:?}");
+        };
+        assert_eq!(status.code(), Some(1));
+        assert_eq!(stderr, "Can't open display");
+    }
 }
@@ -1,8 +1,14 @@
 mod parsing;
 
-use super::utils::run;
-use crate::screen::{Resolution, Screen};
-use crate::switch::SwitchPlan;
+use super::SwitchError;
+use super::cvt;
+use super::utils::{run, run_unchecked};
+use crate::layout::{Direction, Rotation};
+use crate::screen::{
+    ColorAdjustment, Gamma, ModeRestrictions, Modeline, Output, Position, Resolution, ScaleFilter,
+    Screen, SyncPolarity,
+};
+use crate::switch::{self, ProviderOutputSource, SwitchOptions, SwitchPlan};
 use std::process;
 
 struct Xrandr {
@@ -31,11 +37,172 @@ impl Xrandr {
         self
     }
 
+    /// Sets the mode of the output, pinning an allowed refresh rate if its modes are restricted,
+    /// as `--mode` alone picks the highest refresh rate and `--auto` the preferred mode.
+    fn output_mode(
+        self,
+        output: &Output,
+        resolution: Option<Resolution>,
+        restrictions: &ModeRestrictions,
+    ) -> Self {
+        match restrictions.choose_mode(output, resolution) {
+            Some(mode) => self
+                .mode(Some(mode.resolution))
+                .rate(mode.refresh_rate_millihz),
+            None => self.mode(resolution),
+        }
+    }
+
+    fn rate(mut self, refresh_rate_millihz: u32) -> Self {
+        self.command.arg("--rate").arg(format!(
+            "{}.{:03}",
+            refresh_rate_millihz / 1000,
+            refresh_rate_millihz % 1000
+        ));
+        self
+    }
+
+    fn scale_from(mut self, resolution: Resolution) -> Self {
+        self.command.arg("--scale-from").arg(resolution.to_string());
+        self
+    }
+
+    fn scale(mut self, scale: f64) -> Self {
+        self.command.arg("--scale").arg(format!("{scale}x{scale}"));
+        self
+    }
+
+    fn filter(mut self, scale_filter: Option<ScaleFilter>) -> Self {
+        if let Some(scale_filter) = scale_filter {
+            let name = match scale_filter {
+                ScaleFilter::Bilinear => "bilinear",
+                ScaleFilter::Nearest => "nearest",
+            };
+            self.command.arg("--filter").arg(name);
+        }
+        self
+    }
+
+    fn rotate(mut self, rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Normal => {}
+            Rotation::Left => {
+                self.command.arg("--rotate").arg("left");
+            }
+        }
+        self
+    }
+
+    fn primary(mut self, primary: bool) -> Self {
+        if primary {
+            self.command.arg("--primary");
+        }
+        self
+    }
+
     fn same_as(mut self, output_name: &str) -> Self {
         self.command.arg("--same-as").arg(output_name);
         self
     }
 
+    fn gamma(mut self, gamma: Gamma) -> Self {
+        self.command.arg("--gamma").arg(format!(
+            "{:.2}:{:.2}:{:.2}",
+            gamma.red, gamma.green, gamma.blue
+        ));
+        self
+    }
+
+    fn brightness(mut self, value: f32) -> Self {
+        self.command.arg("--brightness").arg(format!("{value:.2}"));
+        self
+    }
+
+    fn color_adjustment(self, color_adjustment: ColorAdjustment) -> Self {
+        let mut xrandr = self;
+        if let Some(gamma) = color_adjustment.gamma {
+            xrandr = xrandr.gamma(gamma);
+        }
+        if let Some(brightness) = color_adjustment.brightness {
+            xrandr = xrandr.brightness(brightness);
+        }
+        xrandr
+    }
+
+    fn relative(mut self, direction: Direction, output_name: &str) -> Self {
+        let option = match direction {
+            Direction::LeftOf => "--left-of",
+            Direction::RightOf => "--right-of",
+            Direction::Above => "--above",
+            Direction::Below => "--below",
+        };
+        self.command.arg(option).arg(output_name);
+        self
+    }
+
+    fn position(mut self, position: Position) -> Self {
+        self.command
+            .arg("--pos")
+            .arg(format!("{}x{}", position.x, position.y));
+        self
+    }
+
+    fn set_provider_output_source(mut self, provider_output_source: &ProviderOutputSource) -> Self {
+        self.command
+            .arg("--setprovideroutputsource")
+            .arg(&provider_output_source.sink)
+            .arg(&provider_output_source.source);
+        self
+    }
+
+    fn new_mode(mut self, modeline: &Modeline) -> Self {
+        self.command
+            .arg("--newmode")
+            .arg(&modeline.name)
+            .arg(format!(
+                "{}.{:03}",
+                modeline.clock_khz / 1000,
+                modeline.clock_khz % 1000
+            ))
+            .args(
+                [
+                    modeline.hdisplay,
+                    modeline.hsync_start,
+                    modeline.hsync_end,
+                    modeline.htotal,
+                    modeline.vdisplay,
+                    modeline.vsync_start,
+                    modeline.vsync_end,
+                    modeline.vtotal,
+                ]
+                .map(|value| value.to_string()),
+            );
+        let sign = |polarity| match polarity {
+            SyncPolarity::Positive => "+",
+            SyncPolarity::Negative => "-",
+        };
+        if let Some(hsync) = modeline.hsync {
+            self.command.arg(format!("{}hsync", sign(hsync)));
+        }
+        if let Some(vsync) = modeline.vsync {
+            self.command.arg(format!("{}vsync", sign(vsync)));
+        }
+        self
+    }
+
+    fn add_mode(mut self, output_name: &str, mode_name: &str) -> Self {
+        self.command
+            .arg("--addmode")
+            .arg(output_name)
+            .arg(mode_name);
+        self
+    }
+
+    fn list_providers(mut self) -> Self {
+        self.command.arg("--listproviders");
+        self
+    }
+
     fn off(mut self) -> Self {
         self.command.arg("--off");
         self
@@ -46,31 +213,169 @@ impl Xrandr {
     }
 }
 
-pub(super) fn get_outputs() -> Screen {
-    let status = run(Xrandr::new().command());
+pub(super) fn get_outputs() -> Result<Screen, SwitchError> {
+    let status = run(Xrandr::new().command())?;
     let xrandr_output = String::from_utf8(status.stdout).expect("xrandr output is invalid utf-8");
-    parsing::parse(&xrandr_output)
+    Ok(parsing::parse(&xrandr_output))
+}
+
+/// Fails unless both providers exist, so that a typo is not silently ignored by xrandr.
+fn check_provider_output_source(
+    provider_output_source: &ProviderOutputSource,
+) -> Result<(), SwitchError> {
+    let status = run(Xrandr::new().list_providers().command())?;
+    let xrandr_output = String::from_utf8(status.stdout).expect("xrandr output is invalid utf-8");
+    let providers = parsing::parse_providers(&xrandr_output);
+
+    let find_provider = |name: &str| {
+        providers
+            .iter()
+            .find(|provider| provider.name == name)
+            .ok_or_else(|| SwitchError::UnknownProvider {
+                name: name.to_string(),
+                known: providers
+                    .iter()
+                    .map(|provider| provider.name.clone())
+                    .collect(),
+            })
+    };
+    if !find_provider(&provider_output_source.sink)?.sink_output {
+        log::warn!(
+            "Provider {:?} cannot show the output of other providers, trying anyway",
+            provider_output_source.sink
+        );
+    }
+    if !find_provider(&provider_output_source.source)?.source_output {
+        log::warn!(
+            "Provider {:?} cannot render for other providers, trying anyway",
+            provider_output_source.source
+        );
+    }
+    Ok(())
 }
 
 fn build_switch_commands(
     switch_plan: &SwitchPlan,
     resolution: Option<Resolution>,
+    options: &SwitchOptions,
 ) -> Vec<process::Command> {
     let disable_commands = switch_plan
         .outputs_to_disable
         .iter()
         .map(|output| Xrandr::new().output(&output.name).off().command());
 
-    let enable_commands = switch_plan
-        .outputs_to_enable
+    let is_primary = |output: &Output| options.primary.as_deref() == Some(output.name.as_str());
+
+    // Outputs positioned relative to others are enabled after their anchors, and the outputs
+    // of a fixed layout at their positions. All other outputs mirror the first one.
+    let (mut positioned, mut mirrored): (Vec<&&Output>, Vec<_>) =
+        switch_plan.outputs_to_enable.iter().partition(|output| {
+            options.layout.is_fixed() || options.layout.relation(&output.name).is_some()
+        });
+    positioned.sort_by_key(|output| options.layout.order(&output.name));
+
+    // When scaling, the others show the picture of the largest or explicitly chosen output,
+    // so it is enabled first, whatever the order of the outputs.
+    if options.scale_mirrored {
+        let reference = options
+            .scale_from
+            .as_deref()
+            .and_then(|name| mirrored.iter().position(|output| output.name == name))
+            .or_else(|| {
+                mirrored
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(i, output)| {
+                        (
+                            output
+                                .largest_resolution()
+                                .map(|resolution| resolution.area()),
+                            std::cmp::Reverse(*i),
+                        )
+                    })
+                    .map(|(i, _)| i)
+            });
+        if let Some(reference) = reference {
+            let output = mirrored.remove(reference);
+            mirrored.insert(0, output);
+        }
+    }
+
+    // When scaling automatically, every output runs at its own largest resolution,
+    // and the larger ones are scaled down to show the area of the smallest one.
+    let logical_resolution = mirrored
+        .iter()
+        .filter_map(|output| output.largest_resolution())
+        .min_by_key(Resolution::area)
+        .filter(|_| options.auto_scale_mirror);
+    let auto_scaled =
+        |xrandr: Xrandr, output: &Output| match (logical_resolution, output.largest_resolution()) {
+            (Some(logical_resolution), Some(native_resolution)) => {
+                let xrandr =
+                    xrandr.output_mode(output, Some(native_resolution), &options.mode_restrictions);
+                if native_resolution == logical_resolution {
+                    xrandr
+                } else {
+                    xrandr
+                        .scale(switch::mirror_scale(native_resolution, logical_resolution))
+                        .filter(options.scale_filter)
+                }
+            }
+            _ => xrandr.output_mode(output, resolution, &options.mode_restrictions),
+        };
+
+    let mirrored_commands = mirrored
         .split_first()
         .map(|(first, other)| {
-            let first_command = Xrandr::new().output(&first.name).mode(resolution).command();
+            // When scaling, the first output renders at its own largest resolution,
+            // and the others sample their picture from a framebuffer of that size.
+            let first_resolution = options
+                .scale_mirrored
+                .then(|| first.largest_resolution())
+                .flatten();
+
+            let first_xrandr = Xrandr::new().output(&first.name);
+            let first_xrandr = if logical_resolution.is_some() {
+                auto_scaled(first_xrandr, first)
+            } else {
+                first_xrandr.output_mode(
+                    first,
+                    first_resolution.or(resolution),
+                    &options.mode_restrictions,
+                )
+            };
+            let first_command = first_xrandr
+                .rotate(options.layout.rotation(&first.name))
+                .color_adjustment(options.color_adjustment)
+                .primary(is_primary(first))
+                .command();
 
-            let other_commands = other.iter().map(|output| {
-                Xrandr::new()
-                    .output(&output.name)
-                    .mode(resolution)
+            let other_commands = other.iter().map(move |output| {
+                let xrandr = Xrandr::new().output(&output.name);
+                let xrandr = match (first_resolution, output.largest_resolution()) {
+                    (Some(first_resolution), Some(native_resolution))
+                        if native_resolution != first_resolution =>
+                    {
+                        xrandr
+                            .output_mode(
+                                output,
+                                Some(native_resolution),
+                                &options.mode_restrictions,
+                            )
+                            .scale_from(first_resolution)
+                            .filter(options.scale_filter)
+                    }
+                    (Some(first_resolution), Some(_)) => xrandr.output_mode(
+                        output,
+                        Some(first_resolution),
+                        &options.mode_restrictions,
+                    ),
+                    _ => auto_scaled(xrandr, output),
+                };
+                xrandr
+                    .rotate(options.layout.rotation(&output.name))
+                    .color_adjustment(options.color_adjustment)
+                    .primary(is_primary(output))
                     .same_as(&first.name)
                     .command()
             });
@@ -80,46 +385,107 @@ fn build_switch_commands(
         .into_iter()
         .flatten();
 
-    disable_commands.chain(enable_commands).collect()
+    let positioned_commands = positioned.iter().map(|output| {
+        let xrandr = Xrandr::new()
+            .output(&output.name)
+            .output_mode(output, resolution, &options.mode_restrictions)
+            .rotate(options.layout.rotation(&output.name))
+            .color_adjustment(options.color_adjustment)
+            .primary(is_primary(output));
+        match options.layout.relation(&output.name) {
+            Some((direction, anchor)) => xrandr.relative(direction, anchor),
+            None => xrandr.position(options.layout.position(&output.name)),
+        }
+        .command()
+    });
+
+    // The outputs of the sink only become usable once it is connected to the source.
+    let provider_commands = options
+        .provider_output_source
+        .iter()
+        .map(|provider_output_source| {
+            Xrandr::new()
+                .set_provider_output_source(provider_output_source)
+                .command()
+        });
+
+    provider_commands
+        .chain(disable_commands)
+        .chain(mirrored_commands)
+        .chain(positioned_commands)
+        .collect()
+}
+
+/// Commands creating a mode of the resolution, with the timings of `modeline` if given,
+/// and adding it to the outputs to enable that have no modes, e.g. force-enabled ones
+/// without EDID, or `None` if there are no such outputs.
+fn build_add_mode_commands(
+    switch_plan: &SwitchPlan,
+    resolution: Option<Resolution>,
+    modeline: Option<&Modeline>,
+) -> Option<(process::Command, Vec<process::Command>)> {
+    let resolution = resolution?;
+    let modeline = modeline
+        .cloned()
+        .unwrap_or_else(|| cvt::modeline(resolution));
+    let add_mode_commands: Vec<_> = switch_plan
+        .outputs_to_enable
+        .iter()
+        .filter(|output| output.modes.is_empty())
+        .map(|output| {
+            Xrandr::new()
+                .add_mode(&output.name, &modeline.name)
+                .command()
+        })
+        .collect();
+    if add_mode_commands.is_empty() {
+        return None;
+    }
+
+    let new_mode_command = Xrandr::new().new_mode(&modeline).command();
+    Some((new_mode_command, add_mode_commands))
 }
 
-pub(super) fn switch_outputs(switch_plan: &SwitchPlan, resolution: Option<Resolution>) {
-    for command in build_switch_commands(switch_plan, resolution) {
-        run(command);
+pub(super) fn switch_outputs(
+    switch_plan: &SwitchPlan,
+    resolution: Option<Resolution>,
+    options: &SwitchOptions,
+) -> Result<(), SwitchError> {
+    if let Some(provider_output_source) = &options.provider_output_source {
+        check_provider_output_source(provider_output_source)?;
     }
+    if let Some((new_mode_command, add_mode_commands)) =
+        build_add_mode_commands(switch_plan, resolution, options.modeline.as_ref())
+    {
+        // Fails if a mode of that name already exists, e.g. from an earlier run, which is reused then.
+        let output = run_unchecked(new_mode_command)?;
+        if !output.status.success() {
+            log::debug!("Unable to create a new mode, assuming it already exists: {output:?}");
+        }
+        for command in add_mode_commands {
+            run(command)?;
+        }
+    }
+    for command in build_switch_commands(switch_plan, resolution, options) {
+        run(command)?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::utils::assert_command_eq;
     use super::*;
-    use crate::screen::{Location, Output};
+    use crate::layout::{self, Layout, LayoutPreset, Relation};
+    use crate::screen::Mode;
 
     #[test]
     fn test_make_switch_commands_without_resolution() {
         // Arrange
         let outputs = [
-            Output {
-                name: "eDP-1".to_string(),
-                connected: true,
-                enabled: true,
-                modes: Vec::new(),
-                location: Location::Internal,
-            },
-            Output {
-                name: "HDMI-1".to_string(),
-                connected: true,
-                enabled: false,
-                modes: Vec::new(),
-                location: Location::External,
-            },
-            Output {
-                name: "HDMI-2".to_string(),
-                connected: false,
-                enabled: true,
-                modes: Vec::new(),
-                location: Location::External,
-            },
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-1", true, false),
+            Output::test("HDMI-2", false, true),
         ];
 
         let switch_plan = SwitchPlan {
@@ -130,7 +496,7 @@ mod tests {
         let resolution = None;
 
         // Act
-        let commands = build_switch_commands(&switch_plan, resolution);
+        let commands = build_switch_commands(&switch_plan, resolution, &SwitchOptions::default());
 
         // Assert
         assert!(commands.len() == 3);
@@ -144,30 +510,90 @@ mod tests {
     }
 
     #[test]
-    fn test_make_switch_commands_with_resolution() {
+    fn test_make_switch_commands_with_single_output_has_no_same_as() {
         // Arrange
         let outputs = [
-            Output {
-                name: "eDP-1".to_string(),
-                connected: true,
-                enabled: true,
-                modes: Vec::new(),
-                location: Location::Internal,
-            },
-            Output {
-                name: "HDMI-1".to_string(),
-                connected: true,
-                enabled: false,
-                modes: Vec::new(),
-                location: Location::External,
-            },
-            Output {
-                name: "HDMI-2".to_string(),
-                connected: false,
-                enabled: true,
-                modes: Vec::new(),
-                location: Location::External,
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-1", true, false),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&outputs[0]],
+            outputs_to_enable: vec![&outputs[1]],
+        };
+
+        let resolution = Some(Resolution {
+            width: 1920,
+            height: 1080,
+        });
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, resolution, &SwitchOptions::default());
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(&commands[0], "xrandr", &["--output", "eDP-1", "--off"]);
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &["--output", "HDMI-1", "--mode", "1920x1080"],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_pins_rate_of_restricted_outputs() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = Output {
+            modes: vec![mode(1920, 1080, 60000), mode(1920, 1080, 50000)],
+            ..Output::test("HDMI-1", true, false)
+        };
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&output],
+        };
+
+        let options = SwitchOptions {
+            mode_restrictions: ModeRestrictions {
+                blacklist: vec!["HDMI-1=1920x1080@60".parse().unwrap()],
+                whitelist: Vec::new(),
             },
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 1);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &[
+                "--output",
+                "HDMI-1",
+                "--mode",
+                "1920x1080",
+                "--rate",
+                "50.000",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_resolution() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-1", true, false),
+            Output::test("HDMI-2", false, true),
         ];
 
         let switch_plan = SwitchPlan {
@@ -181,7 +607,7 @@ mod tests {
         });
 
         // Act
-        let commands = build_switch_commands(&switch_plan, resolution);
+        let commands = build_switch_commands(&switch_plan, resolution, &SwitchOptions::default());
 
         // Assert
         assert!(commands.len() == 3);
@@ -204,4 +630,729 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn test_make_switch_commands_with_color_adjustment() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-1", true, false),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        let color_adjustment = ColorAdjustment {
+            gamma: Some(Gamma {
+                red: 1.0,
+                green: 0.8,
+                blue: 0.75,
+            }),
+            brightness: Some(0.5),
+        };
+
+        let options = SwitchOptions {
+            color_adjustment,
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &[
+                "--output",
+                "eDP-1",
+                "--auto",
+                "--gamma",
+                "1.00:0.80:0.75",
+                "--brightness",
+                "0.50",
+            ],
+        );
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &[
+                "--output",
+                "HDMI-1",
+                "--auto",
+                "--gamma",
+                "1.00:0.80:0.75",
+                "--brightness",
+                "0.50",
+                "--same-as",
+                "eDP-1",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_brightness() {
+        // Arrange
+        let outputs = [Output::test("eDP-1", true, false)];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0]],
+        };
+
+        let color_adjustment = ColorAdjustment {
+            gamma: None,
+            brightness: Some(0.8),
+        };
+
+        let options = SwitchOptions {
+            color_adjustment,
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 1);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &["--output", "eDP-1", "--auto", "--brightness", "0.80"],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_primary() {
+        // Arrange
+        let output = |name: &str| Output::test(name, true, false);
+        let outputs = [output("eDP-1"), output("HDMI-1")];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        let options = SwitchOptions {
+            primary: Some("HDMI-1".to_string()),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(&commands[0], "xrandr", &["--output", "eDP-1", "--auto"]);
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &[
+                "--output",
+                "HDMI-1",
+                "--auto",
+                "--primary",
+                "--same-as",
+                "eDP-1",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_provider_output_source() {
+        // Arrange
+        let output = |name: &str, enabled| Output::test(name, true, enabled);
+        let outputs = [output("eDP-1", true), output("HDMI-1-0", false)];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&outputs[0]],
+            outputs_to_enable: vec![&outputs[1]],
+        };
+
+        let options = SwitchOptions {
+            provider_output_source: Some(ProviderOutputSource {
+                sink: "NVIDIA-G0".to_string(),
+                source: "modesetting".to_string(),
+            }),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 3);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &["--setprovideroutputsource", "NVIDIA-G0", "modesetting"],
+        );
+        assert_command_eq(&commands[1], "xrandr", &["--output", "eDP-1", "--off"]);
+        assert_command_eq(&commands[2], "xrandr", &["--output", "HDMI-1-0", "--auto"]);
+    }
+
+    #[test]
+    fn test_make_switch_commands_scaling_from_preferred_output() {
+        // Arrange
+        let mode = |width, height| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name: &str, modes| Output {
+            modes,
+            ..Output::test(name, true, true)
+        };
+        let internal = output("eDP-1", vec![mode(1920, 1200)]);
+        let external = output("HDMI-1", vec![mode(3840, 2160), mode(2560, 1440)]);
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&internal, &external],
+        };
+
+        let options = SwitchOptions {
+            scale_mirrored: true,
+            scale_from: Some("eDP-1".to_string()),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &["--output", "eDP-1", "--mode", "1920x1200"],
+        );
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &[
+                "--output",
+                "HDMI-1",
+                "--mode",
+                "3840x2160",
+                "--scale-from",
+                "1920x1200",
+                "--same-as",
+                "eDP-1",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_scale_mirrored() {
+        // Arrange
+        let mode = |width, height| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let outputs = [
+            Output {
+                modes: vec![mode(1920, 1080), mode(1280, 720)],
+                ..Output::test("eDP-1", true, true)
+            },
+            Output {
+                modes: vec![mode(3840, 2160), mode(1920, 1080)],
+                ..Output::test("HDMI-1", true, false)
+            },
+            Output {
+                modes: vec![mode(1920, 1080)],
+                ..Output::test("HDMI-2", true, false)
+            },
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1], &outputs[2]],
+        };
+
+        let options = SwitchOptions {
+            scale_mirrored: true,
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(
+            &switch_plan,
+            Some(Resolution {
+                width: 1280,
+                height: 720,
+            }),
+            &options,
+        );
+
+        // Assert
+        assert!(commands.len() == 3);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &["--output", "HDMI-1", "--mode", "3840x2160"],
+        );
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &[
+                "--output",
+                "eDP-1",
+                "--mode",
+                "1920x1080",
+                "--scale-from",
+                "3840x2160",
+                "--same-as",
+                "HDMI-1",
+            ],
+        );
+        assert_command_eq(
+            &commands[2],
+            "xrandr",
+            &[
+                "--output",
+                "HDMI-2",
+                "--mode",
+                "1920x1080",
+                "--scale-from",
+                "3840x2160",
+                "--same-as",
+                "HDMI-1",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_auto_scale_mirror() {
+        // Arrange
+        let mode = |width, height| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let outputs = [
+            Output {
+                modes: vec![mode(1920, 1080), mode(1280, 720)],
+                ..Output::test("eDP-1", true, true)
+            },
+            Output {
+                modes: vec![mode(3840, 2160), mode(1920, 1080)],
+                ..Output::test("HDMI-1", true, false)
+            },
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[1], &outputs[0]],
+        };
+
+        let options = SwitchOptions {
+            auto_scale_mirror: true,
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(
+            &switch_plan,
+            Some(Resolution {
+                width: 1280,
+                height: 720,
+            }),
+            &options,
+        );
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &[
+                "--output",
+                "HDMI-1",
+                "--mode",
+                "3840x2160",
+                "--scale",
+                "0.5x0.5",
+            ],
+        );
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &[
+                "--output",
+                "eDP-1",
+                "--mode",
+                "1920x1080",
+                "--same-as",
+                "HDMI-1",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_scale_filter() {
+        // Arrange
+        let mode = |width, height| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let outputs = [
+            Output {
+                modes: vec![mode(1920, 1080), mode(1280, 720)],
+                ..Output::test("eDP-1", true, true)
+            },
+            Output {
+                modes: vec![mode(3840, 2160), mode(1920, 1080)],
+                ..Output::test("HDMI-1", true, false)
+            },
+            Output {
+                modes: vec![mode(1920, 1080)],
+                ..Output::test("HDMI-2", true, false)
+            },
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1], &outputs[2]],
+        };
+
+        let options = SwitchOptions {
+            scale_mirrored: true,
+            scale_filter: Some(ScaleFilter::Nearest),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(
+            &switch_plan,
+            Some(Resolution {
+                width: 1280,
+                height: 720,
+            }),
+            &options,
+        );
+
+        // Assert
+        assert!(commands.len() == 3);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &["--output", "HDMI-1", "--mode", "3840x2160"],
+        );
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &[
+                "--output",
+                "eDP-1",
+                "--mode",
+                "1920x1080",
+                "--scale-from",
+                "3840x2160",
+                "--filter",
+                "nearest",
+                "--same-as",
+                "HDMI-1",
+            ],
+        );
+        assert_command_eq(
+            &commands[2],
+            "xrandr",
+            &[
+                "--output",
+                "HDMI-2",
+                "--mode",
+                "1920x1080",
+                "--scale-from",
+                "3840x2160",
+                "--filter",
+                "nearest",
+                "--same-as",
+                "HDMI-1",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_portrait_stack() {
+        // Arrange
+        let outputs = [
+            Output::test("HDMI-1", true, false),
+            Output::test("DP-2", true, false),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        let resolution = Some(Resolution {
+            width: 1920,
+            height: 1080,
+        });
+
+        let (relations, rotations) =
+            layout::preset(LayoutPreset::PortraitStack, &switch_plan.outputs_to_enable);
+        let mut sizes = layout::estimate_sizes(&switch_plan.outputs_to_enable, resolution);
+        layout::rotate_sizes(&mut sizes, &rotations);
+        let options = SwitchOptions {
+            layout: layout::resolve(&relations, &sizes)
+                .expect("layout should resolve")
+                .with_rotations(rotations),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, resolution, &options);
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &[
+                "--output",
+                "HDMI-1",
+                "--mode",
+                "1920x1080",
+                "--rotate",
+                "left",
+            ],
+        );
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &[
+                "--output",
+                "DP-2",
+                "--mode",
+                "1920x1080",
+                "--rotate",
+                "left",
+                "--below",
+                "HDMI-1",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_layout() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-1", true, false),
+            Output::test("DP-2", true, false),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1], &outputs[2]],
+        };
+
+        let resolution = Some(Resolution {
+            width: 1920,
+            height: 1080,
+        });
+
+        let relations = [
+            Relation {
+                output: "eDP-1".to_string(),
+                direction: Direction::Below,
+                anchor: "DP-2".to_string(),
+            },
+            Relation {
+                output: "DP-2".to_string(),
+                direction: Direction::RightOf,
+                anchor: "HDMI-1".to_string(),
+            },
+        ];
+        let options = SwitchOptions {
+            layout: layout::resolve(
+                &relations,
+                &layout::estimate_sizes(&switch_plan.outputs_to_enable, resolution),
+            )
+            .expect("layout should resolve"),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, resolution, &options);
+
+        // Assert
+        assert!(commands.len() == 3);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &["--output", "HDMI-1", "--mode", "1920x1080"],
+        );
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &[
+                "--output",
+                "DP-2",
+                "--mode",
+                "1920x1080",
+                "--right-of",
+                "HDMI-1",
+            ],
+        );
+        assert_command_eq(
+            &commands[2],
+            "xrandr",
+            &[
+                "--output",
+                "eDP-1",
+                "--mode",
+                "1920x1080",
+                "--below",
+                "DP-2",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_make_switch_commands_with_fixed_layout() {
+        // Arrange
+        let outputs = [
+            Output::test("eDP-1", true, true),
+            Output::test("HDMI-1", true, false),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        let options = SwitchOptions {
+            layout: Layout::fixed([
+                ("eDP-1".to_string(), Position { x: 0, y: 1080 }),
+                ("HDMI-1".to_string(), Position { x: 0, y: 0 }),
+            ]),
+            ..Default::default()
+        };
+
+        // Act
+        let commands = build_switch_commands(&switch_plan, None, &options);
+
+        // Assert
+        assert!(commands.len() == 2);
+        assert_command_eq(
+            &commands[0],
+            "xrandr",
+            &["--output", "eDP-1", "--auto", "--pos", "0x1080"],
+        );
+        assert_command_eq(
+            &commands[1],
+            "xrandr",
+            &["--output", "HDMI-1", "--auto", "--pos", "0x0"],
+        );
+    }
+
+    #[test]
+    fn test_build_add_mode_commands_adds_mode_to_outputs_without_modes() {
+        // Arrange
+        let outputs = [
+            Output {
+                modes: vec![Mode {
+                    resolution: Resolution {
+                        width: 1920,
+                        height: 1080,
+                    },
+                    refresh_rate_millihz: 60000,
+                    preferred: true,
+                    active: true,
+                    refresh_rates_millihz: Vec::new(),
+                }],
+                ..Output::test("eDP-1", true, true)
+            },
+            Output::test("HDMI-1", false, false),
+        ];
+
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&outputs[0], &outputs[1]],
+        };
+
+        let resolution = Some(Resolution {
+            width: 1920,
+            height: 1080,
+        });
+        let modeline: Modeline =
+            r#"Modeline "1920x1080_60.00" 148.50 1920 2008 2052 2200 1080 1084 1089 1125 +hsync +vsync"#
+                .parse()
+                .expect("modeline should parse");
+
+        // Act
+        let commands = build_add_mode_commands(&switch_plan, resolution, None);
+        let with_modeline = build_add_mode_commands(&switch_plan, resolution, Some(&modeline));
+        let without_resolution = build_add_mode_commands(&switch_plan, None, None);
+
+        // Assert
+        let (new_mode_command, add_mode_commands) = commands.expect("HDMI-1 has no modes");
+        assert_command_eq(
+            &new_mode_command,
+            "xrandr",
+            &[
+                "--newmode",
+                "1920x1080",
+                "173.000",
+                "1920",
+                "2048",
+                "2248",
+                "2576",
+                "1080",
+                "1083",
+                "1088",
+                "1120",
+                "-hsync",
+                "+vsync",
+            ],
+        );
+        assert!(add_mode_commands.len() == 1);
+        assert_command_eq(
+            &add_mode_commands[0],
+            "xrandr",
+            &["--addmode", "HDMI-1", "1920x1080"],
+        );
+        let (new_mode_command, add_mode_commands) = with_modeline.expect("HDMI-1 has no modes");
+        assert_command_eq(
+            &new_mode_command,
+            "xrandr",
+            &[
+                "--newmode",
+                "1920x1080_60.00",
+                "148.500",
+                "1920",
+                "2008",
+                "2052",
+                "2200",
+                "1080",
+                "1084",
+                "1089",
+                "1125",
+                "+hsync",
+                "+vsync",
+            ],
+        );
+        assert_command_eq(
+            &add_mode_commands[0],
+            "xrandr",
+            &["--addmode", "HDMI-1", "1920x1080_60.00"],
+        );
+        assert!(without_resolution.is_none());
+    }
 }
@@ -1,9 +1,12 @@
-use crate::screen::{Location, Mode, Output, Resolution, Screen};
+use crate::screen::{Location, Mode, Output, Position, Resolution, Screen};
 use regex::Regex;
 
 struct Parser {
     output_line_regex: Regex,
     mode_line_regex: Regex,
+    /// Matches mode lines of `xrandr --verbose`, like `  1920x1080 (0x501) 148.500MHz +HSync +VSync`,
+    /// which describe a mode by its ID and pixel clock instead of listing refresh rates.
+    verbose_mode_line_regex: Regex,
     freq_regex: Regex,
 }
 
@@ -15,48 +18,120 @@ impl Parser {
                 ^(?P<name>\S+)
                 \s(?P<status>connected|disconnected)
                 (?:\sprimary)?
-                (?:\s(?P<resolution>\d+x\d+\+\d+\+\d+))?
+                (?:\s(?P<resolution>(?P<width>\d+)x(?P<height>\d+)\+(?P<x>\d+)\+(?P<y>\d+)))?
+                # Some versions of xrandr print primary after the resolution.
+                (?:\sprimary)?
                 \s
+                (?:.*\s(?P<mm_width>\d+)mm\sx\s(?P<mm_height>\d+)mm$)?
             ",
             )
             .expect("bad output_line_regex"),
             mode_line_regex: Regex::new(
-                r"^\s+(?P<width>\d+)x(?P<height>\d+)(?P<freqs>(?:\s+\d+\.\d{2}[ *][ +])+)$",
+                r"^\s+(?P<width>\d+)x(?P<height>\d+)(?P<freqs>(?:\s+\d+\.\d{1,2}[ *][ +])+)$",
             )
             .expect("bad mode_line_regex"),
-            freq_regex: Regex::new(r"(\d+)\.(\d{2})").expect("bad freq_regex"),
+            verbose_mode_line_regex: Regex::new(r"^\s+\d+x\d+\S*\s+\(0x[0-9a-f]+\)\s")
+                .expect("bad verbose_mode_line_regex"),
+            // Refresh rates are followed by `*` if current and `+` if preferred.
+            freq_regex: Regex::new(r"(\d+)\.(\d{1,2})(?:(\*)| )?(\+)?").expect("bad freq_regex"),
         }
     }
 
     fn parse_output_line(&self, line: &str) -> Option<Output> {
-        self.output_line_regex.captures(line).map(|caps| Output {
+        let caps = self.output_line_regex.captures(line)?;
+
+        let Some(location) = Location::try_from_output_name(&caps["name"]) else {
+            log::warn!("Skipping output with unknown location: {line:?}");
+            return None;
+        };
+
+        let enabled = caps.name("resolution").is_some();
+
+        let current_resolution = if enabled {
+            match (caps["width"].parse(), caps["height"].parse()) {
+                (Ok(width), Ok(height)) => Some(Resolution { width, height }),
+                _ => {
+                    log::warn!("Ignoring bad current resolution of output: {line:?}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let current_position = if enabled {
+            match (caps["x"].parse(), caps["y"].parse()) {
+                (Ok(x), Ok(y)) => Some(Position { x, y }),
+                _ => {
+                    log::warn!("Ignoring bad current position of output: {line:?}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Disconnected outputs and projectors report a size of zero.
+        let physical_size_mm = caps
+            .name("mm_width")
+            .zip(caps.name("mm_height"))
+            .and_then(|(width, height)| {
+                Some((width.as_str().parse().ok()?, height.as_str().parse().ok()?))
+            })
+            .filter(|&(width, height)| width != 0 && height != 0);
+
+        Some(Output {
             name: caps["name"].to_string(),
             connected: &caps["status"] == "connected",
-            enabled: caps.name("resolution").is_some(),
+            enabled,
             modes: Vec::new(),
-            location: Location::from_output_name(&caps["name"]),
+            location,
+            // xrandr does not report the power state of outputs.
+            powered: true,
+            current_resolution,
+            current_position,
+            scale_filter: None,
+            physical_size_mm,
+            allow_tearing: None,
+            max_render_time_ms: None,
         })
     }
 
+    fn is_verbose_mode_line(&self, line: &str) -> bool {
+        self.verbose_mode_line_regex.is_match(line)
+    }
+
     fn parse_mode_line(&self, line: &str, modes: &mut Vec<Mode>) {
+        if self.is_verbose_mode_line(line) {
+            log::trace!("Skipping verbose mode line: {line:?}");
+            return;
+        }
+
         let Some(caps) = self.mode_line_regex.captures(line) else {
             return;
         };
 
-        let resolution = Resolution {
-            width: caps["width"].parse().expect("bad width"),
-            height: caps["height"].parse().expect("bad height"),
+        let (Ok(width), Ok(height)) = (caps["width"].parse(), caps["height"].parse()) else {
+            log::warn!("Skipping mode line with bad resolution: {line:?}");
+            return;
         };
+        let resolution = Resolution { width, height };
 
         for caps in self.freq_regex.captures_iter(&caps["freqs"]) {
-            let x: u32 = caps[1].parse().expect("bad integer part");
-            let y: u32 = caps[2].parse().expect("bad fractional part");
-            assert!((0..100).contains(&y));
-            let refresh_rate = x * 1000 + y * 10;
+            let Some(refresh_rate) = parse_refresh_rate_millihz(&caps[1], &caps[2]) else {
+                log::warn!(
+                    "Skipping bad refresh rate {:?} in mode line: {line:?}",
+                    &caps[0]
+                );
+                continue;
+            };
 
             modes.push(Mode {
                 resolution,
                 refresh_rate_millihz: refresh_rate,
+                preferred: caps.get(4).is_some(),
+                active: caps.get(3).is_some(),
+                refresh_rates_millihz: Vec::new(),
             });
         }
     }
@@ -66,11 +141,12 @@ impl Parser {
         let mut current_output: Option<Output> = None;
 
         for line in xrandr_output.lines() {
-            if let Some(output) = self.parse_output_line(line) {
+            if self.output_line_regex.is_match(line) {
                 if let Some(output) = current_output {
                     outputs.push(output);
                 }
-                current_output = Some(output);
+                // Mode lines following a malformed output line are skipped too.
+                current_output = self.parse_output_line(line);
             } else if let Some(output) = current_output.as_mut() {
                 self.parse_mode_line(line, &mut output.modes);
             }
@@ -84,14 +160,93 @@ impl Parser {
     }
 }
 
+/// Combines the parts of a refresh rate like `59.95` or `60.0` into millihertz.
+fn parse_refresh_rate_millihz(integer_part: &str, fractional_part: &str) -> Option<u32> {
+    let x: u32 = integer_part.parse().ok()?;
+    let y: u32 = fractional_part.parse().ok()?;
+    let y_scale = match fractional_part.len() {
+        1 => 100,
+        2 => 10,
+        _ => return None,
+    };
+    x.checked_mul(1000)?.checked_add(y * y_scale)
+}
+
 pub(super) fn parse(xrandr_output: &str) -> Screen {
     Parser::new().parse(xrandr_output)
 }
 
+/// A GPU as listed by `xrandr --listproviders`.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Provider {
+    pub(super) name: String,
+    /// Can render the picture for the outputs of other providers.
+    pub(super) source_output: bool,
+    /// Can show the picture rendered by another provider on its outputs.
+    pub(super) sink_output: bool,
+}
+
+/// Parses the output of `xrandr --listproviders`, with lines like
+/// `Provider 0: id: 0x1b8 cap: 0x1, Source Output crtcs: 4 outputs: 2 associated providers: 1 name:NVIDIA-0`.
+pub(super) fn parse_providers(xrandr_output: &str) -> Vec<Provider> {
+    const SOURCE_OUTPUT: u32 = 0x1;
+    const SINK_OUTPUT: u32 = 0x2;
+
+    let provider_line_regex = Regex::new(
+        r"^Provider \d+: id: 0x[0-9a-f]+ cap: 0x(?P<cap>[0-9a-f]+),.*\sname:(?P<name>.+)$",
+    )
+    .expect("bad provider_line_regex");
+    xrandr_output
+        .lines()
+        .filter_map(|line| {
+            let caps = provider_line_regex.captures(line)?;
+            let Ok(capabilities) = u32::from_str_radix(&caps["cap"], 16) else {
+                log::warn!("Skipping provider with bad capabilities: {line:?}");
+                return None;
+            };
+            Some(Provider {
+                name: caps["name"].to_string(),
+                source_output: capabilities & SOURCE_OUTPUT != 0,
+                sink_output: capabilities & SINK_OUTPUT != 0,
+            })
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_parse_providers() {
+        // Arrange
+        let xrandr_output = "\
+Providers: number : 2
+Provider 0: id: 0x1b8 cap: 0x1, Source Output crtcs: 4 outputs: 2 associated providers: 1 name:NVIDIA-0
+Provider 1: id: 0x46 cap: 0xf, Source Output, Sink Output, Source Offload, Sink Offload crtcs: 3 outputs: 5 associated providers: 1 name:modesetting
+";
+
+        // Act
+        let providers = parse_providers(xrandr_output);
+
+        // Assert
+        assert_eq!(
+            providers,
+            [
+                Provider {
+                    name: "NVIDIA-0".to_string(),
+                    source_output: true,
+                    sink_output: false,
+                },
+                Provider {
+                    name: "modesetting".to_string(),
+                    source_output: true,
+                    sink_output: true,
+                },
+            ]
+        );
+    }
+
     #[test]
     fn parse_output_line_must_return_nothing() {
         let parser = Parser::new();
@@ -118,6 +273,9 @@ mod test {
             connected: bool,
             enabled: bool,
             location: Location,
+            current_resolution: Option<Resolution>,
+            current_position: Option<Position>,
+            physical_size_mm: Option<(u32, u32)>,
         }
 
         let test_cases = [
@@ -127,6 +285,12 @@ mod test {
                 connected: true,
                 enabled: true,
                 location: Location::Internal,
+                current_resolution: Some(Resolution {
+                    width: 1920,
+                    height: 1080,
+                }),
+                current_position: Some(Position { x: 0, y: 0 }),
+                physical_size_mm: Some((344, 194)),
             },
             TestCase {
                 line: CONNECTED_DISABLED_EXTERNAL_OUTPUT_LINE,
@@ -134,6 +298,9 @@ mod test {
                 connected: true,
                 enabled: false,
                 location: Location::External,
+                current_resolution: None,
+                current_position: None,
+                physical_size_mm: None,
             },
             TestCase {
                 line: DISCONNECTED_ENABLED_EXTERNAL_OUTPUT_LINE,
@@ -141,6 +308,38 @@ mod test {
                 connected: false,
                 enabled: true,
                 location: Location::External,
+                current_resolution: Some(Resolution {
+                    width: 1920,
+                    height: 1080,
+                }),
+                current_position: Some(Position { x: 0, y: 0 }),
+                physical_size_mm: None,
+            },
+            TestCase {
+                line: CONNECTED_ENABLED_PROJECTOR_OUTPUT_LINE,
+                name: "VGA-1",
+                connected: true,
+                enabled: true,
+                location: Location::External,
+                current_resolution: Some(Resolution {
+                    width: 1024,
+                    height: 768,
+                }),
+                current_position: Some(Position { x: 1920, y: 0 }),
+                physical_size_mm: None,
+            },
+            TestCase {
+                line: CONNECTED_ENABLED_PRIMARY_AFTER_RESOLUTION_OUTPUT_LINE,
+                name: "DP-2",
+                connected: true,
+                enabled: true,
+                location: Location::External,
+                current_resolution: Some(Resolution {
+                    width: 2560,
+                    height: 1440,
+                }),
+                current_position: Some(Position { x: 1920, y: 0 }),
+                physical_size_mm: Some((597, 336)),
             },
             TestCase {
                 line: DISCONNECTED_DISABLED_EXTERNAL_OUTPUT_LINE,
@@ -148,6 +347,9 @@ mod test {
                 connected: false,
                 enabled: false,
                 location: Location::External,
+                current_resolution: None,
+                current_position: None,
+                physical_size_mm: None,
             },
         ];
 
@@ -165,6 +367,9 @@ mod test {
             assert_eq!(output.connected, test_case.connected);
             assert_eq!(output.enabled, test_case.enabled);
             assert_eq!(output.location, test_case.location);
+            assert_eq!(output.current_resolution, test_case.current_resolution);
+            assert_eq!(output.current_position, test_case.current_position);
+            assert_eq!(output.physical_size_mm, test_case.physical_size_mm);
         }
     }
 
@@ -188,6 +393,18 @@ mod test {
         assert!(modes.is_empty());
     }
 
+    #[test]
+    fn verbose_mode_lines_must_be_identified() {
+        let parser = Parser::new();
+        assert!(parser.is_verbose_mode_line(VERBOSE_INFO_LINES[0]));
+        assert!(
+            parser.is_verbose_mode_line("  1920x1080i (0x4f) 74.250MHz +HSync +VSync Interlace")
+        );
+        assert!(!parser.is_verbose_mode_line(VERBOSE_INFO_LINES[1]));
+        assert!(!parser.is_verbose_mode_line(ACTIVE_PREFERRED_MODE_LINE));
+        assert!(!parser.is_verbose_mode_line(PLAIN_MODE_LINE));
+    }
+
     #[test]
     fn parse_mode_line_must_parse_active_preferred_mode_line() {
         // Arrange
@@ -207,6 +424,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 60020,
+                    preferred: true,
+                    active: true,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -214,6 +434,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 60010,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -221,6 +444,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 59970,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -228,6 +454,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 59960,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -235,6 +464,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 59930,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -242,6 +474,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 48020,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
             ]
         );
@@ -266,6 +501,9 @@ mod test {
                         height: 1050
                     },
                     refresh_rate_millihz: 59950,
+                    preferred: false,
+                    active: true,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -273,6 +511,9 @@ mod test {
                         height: 1050
                     },
                     refresh_rate_millihz: 59880,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
             ]
         );
@@ -297,6 +538,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 60020,
+                    preferred: true,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -304,6 +548,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 60010,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -311,6 +558,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 59970,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -318,6 +568,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 59960,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -325,6 +578,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 59930,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -332,6 +588,9 @@ mod test {
                         height: 1080
                     },
                     refresh_rate_millihz: 48020,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
             ]
         );
@@ -355,6 +614,9 @@ mod test {
                         height: 1050
                     },
                     refresh_rate_millihz: 59950,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -362,11 +624,165 @@ mod test {
                         height: 1050
                     },
                     refresh_rate_millihz: 59880,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_mode_line_must_parse_one_decimal_digit_mode_line() {
+        // Arrange
+        let mut modes = Vec::new();
+        let parser = Parser::new();
+
+        // Act
+        parser.parse_mode_line(ONE_DECIMAL_DIGIT_MODE_LINE, &mut modes);
+
+        // Assert
+        assert_eq!(
+            modes,
+            [
+                Mode {
+                    resolution: Resolution {
+                        width: 1024,
+                        height: 768
+                    },
+                    refresh_rate_millihz: 75000,
+                    preferred: false,
+                    active: true,
+                    refresh_rates_millihz: Vec::new(),
+                },
+                Mode {
+                    resolution: Resolution {
+                        width: 1024,
+                        height: 768
+                    },
+                    refresh_rate_millihz: 60000,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
+                },
+                Mode {
+                    resolution: Resolution {
+                        width: 1024,
+                        height: 768
+                    },
+                    refresh_rate_millihz: 59940,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
             ]
         );
     }
 
+    #[test]
+    fn test_parse_refresh_rate_millihz() {
+        assert_eq!(parse_refresh_rate_millihz("60", "0"), Some(60000));
+        assert_eq!(parse_refresh_rate_millihz("75", "5"), Some(75500));
+        assert_eq!(parse_refresh_rate_millihz("59", "95"), Some(59950));
+        assert_eq!(parse_refresh_rate_millihz("59", "05"), Some(59050));
+        assert_eq!(parse_refresh_rate_millihz("99999999999", "0"), None);
+    }
+
+    #[test]
+    fn parse_mode_line_must_skip_mode_line_with_too_large_width() {
+        // Arrange
+        let mut modes = Vec::new();
+        let parser = Parser::new();
+
+        // Act
+        parser.parse_mode_line(TOO_LARGE_WIDTH_MODE_LINE, &mut modes);
+
+        // Assert
+        assert!(modes.is_empty());
+    }
+
+    #[test]
+    fn parse_mode_line_must_skip_too_large_refresh_rates() {
+        // Arrange
+        let mut modes = Vec::new();
+        let parser = Parser::new();
+
+        // Act
+        parser.parse_mode_line(TOO_LARGE_REFRESH_RATE_MODE_LINE, &mut modes);
+
+        // Assert
+        assert_eq!(
+            modes,
+            [Mode {
+                resolution: Resolution {
+                    width: 1680,
+                    height: 1050
+                },
+                refresh_rate_millihz: 59880,
+                preferred: false,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_output_line_must_skip_output_with_unknown_location() {
+        let parser = Parser::new();
+        assert!(parser.parse_output_line(CORRUPTED_OUTPUT_LINE).is_none());
+    }
+
+    #[test]
+    fn test_parse_malformed_output() {
+        // Arrange
+
+        // Act
+        let screen = parse(MALFORMED_TEST_OUTPUT);
+
+        // Assert
+        assert_eq!(screen.outputs.len(), 2);
+        assert_eq!(screen.outputs[0].name, "eDP-1");
+        assert_eq!(
+            screen.outputs[0].modes,
+            [Mode {
+                resolution: Resolution {
+                    width: 1680,
+                    height: 1050
+                },
+                refresh_rate_millihz: 59950,
+                preferred: false,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
+            }]
+        );
+        assert_eq!(screen.outputs[1].name, "HDMI-2");
+        assert!(screen.outputs[1].modes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_disconnected_output_with_saved_crtc() {
+        // Arrange
+        let xrandr_output = [
+            SCREEN_LINE,
+            "HDMI-1 disconnected 1920x1080+0+0 (normal left inverted right x axis y axis) 0mm x 0mm",
+            "  1920x1080 (0x501) 148.500MHz +HSync +VSync",
+            "        h: width  1920 start 2008 end 2052 total 2200 skew    0 clock  67.50KHz",
+            "        v: height 1080 start 1084 end 1089 total 1125           clock  60.00Hz",
+        ]
+        .join("\n");
+
+        // Act
+        let screen = parse(&xrandr_output);
+
+        // Assert
+        assert_eq!(screen.outputs.len(), 1);
+        let output = &screen.outputs[0];
+        assert!(!output.connected);
+        assert!(output.enabled);
+        assert!(output.modes.is_empty());
+        assert!(!output.is_usable());
+    }
+
     #[test]
     fn test_parse_output() {
         // Arrange
@@ -406,6 +822,9 @@ mod test {
         "HDMI-2 connected (normal left inverted right x axis y axis)";
     const DISCONNECTED_ENABLED_EXTERNAL_OUTPUT_LINE: &str =
         "HDMI-2 disconnected 1920x1080+0+0 (normal left inverted right x axis y axis) 0mm x 0mm";
+    const CONNECTED_ENABLED_PROJECTOR_OUTPUT_LINE: &str =
+        "VGA-1 connected 1024x768+1920+0 (normal left inverted right x axis y axis) 0mm x 0mm";
+    const CONNECTED_ENABLED_PRIMARY_AFTER_RESOLUTION_OUTPUT_LINE: &str = "DP-2 connected 2560x1440+1920+0 primary (normal left inverted right x axis y axis) 597mm x 336mm";
     const DISCONNECTED_DISABLED_EXTERNAL_OUTPUT_LINE: &str =
         "DP-1 disconnected (normal left inverted right x axis y axis)";
 
@@ -415,12 +834,27 @@ mod test {
     const PREFERRED_MODE_LINE: &str =
         "   1920x1080     60.02 +  60.01    59.97    59.96    59.93    48.02  ";
     const PLAIN_MODE_LINE: &str = "   1680x1050     59.95    59.88  ";
+    const ONE_DECIMAL_DIGIT_MODE_LINE: &str = "   1024x768      75.0*    60.0     59.94  ";
+    const TOO_LARGE_WIDTH_MODE_LINE: &str = "   99999999999x1050     59.95*   59.88  ";
+    const TOO_LARGE_REFRESH_RATE_MODE_LINE: &str = "   1680x1050     99999999999.95*   59.88  ";
+    const CORRUPTED_OUTPUT_LINE: &str =
+        "?DMI-1 connected (normal left inverted right x axis y axis)";
     const VERBOSE_INFO_LINES: [&str; 3] = [
         "  1920x1080 (0x501) 148.500MHz +HSync +VSync ",
         "        h: width  1920 start 2008 end 2052 total 2200 skew    0 clock  67.50KHz ",
         "        v: height 1080 start 1084 end 1089 total 1125           clock  60.00Hz ",
     ];
 
+    const MALFORMED_TEST_OUTPUT: &str = r#"
+Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 16384 x 16384
+eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 194mm
+   99999999999x1080     60.02*+  60.01  
+   1680x1050     59.95  
+?DMI-1 connected (normal left inverted right x axis y axis)
+   1920x1080     60.00  
+HDMI-2 connected (normal left inverted right x axis y axis)
+"#;
+
     const TEST_OUTPUT: &str = r#"
 Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 16384 x 16384
 eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 194mm
@@ -0,0 +1,310 @@
+use crate::diff::OutputState;
+use crate::layout::Layout;
+use crate::screen::{ModeFilter, ModeRestrictions, Resolution, Screen};
+use crate::switch::SwitchPlan;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// State of an output, identified by its name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct NamedOutputState {
+    pub(super) name: String,
+    #[serde(flatten)]
+    pub(super) state: OutputState,
+}
+
+/// State of the outputs, as printed by `--print-state` and restored by `--apply-state`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct State {
+    pub(super) outputs: Vec<NamedOutputState>,
+}
+
+impl State {
+    pub(super) fn current(screen: &Screen) -> Self {
+        Self {
+            outputs: screen
+                .outputs
+                .iter()
+                .map(|output| NamedOutputState {
+                    name: output.name.clone(),
+                    state: OutputState::current(output),
+                })
+                .collect(),
+        }
+    }
+
+    pub(super) fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("output states should serialize to JSON")
+    }
+
+    /// Reads the state from the file, or from the standard input if `path` is `-`.
+    pub(super) fn read(path: &Path) -> Result<Self, String> {
+        let json = if path == Path::new("-") {
+            std::io::read_to_string(std::io::stdin())
+        } else {
+            std::fs::read_to_string(path)
+        }
+        .map_err(|err| err.to_string())?;
+        json.parse()
+    }
+
+    fn enabled_outputs(&self) -> impl Iterator<Item = &NamedOutputState> {
+        self.outputs.iter().filter(|output| output.state.enabled)
+    }
+
+    /// Builds a plan enabling the outputs enabled in the state and disabling the enabled outputs
+    /// disabled in it. Outputs the state does not mention are left alone. Fails if the state
+    /// names an output that does not exist, or a mode the output does not have.
+    pub(super) fn build_switch_plan<'a>(
+        &self,
+        screen: &'a Screen,
+    ) -> Result<SwitchPlan<'a>, String> {
+        let mut switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: Vec::new(),
+        };
+        for NamedOutputState { name, state } in &self.outputs {
+            let output = screen
+                .find_output_by_name(name)
+                .ok_or_else(|| format!("output {name} does not exist"))?;
+            if !state.enabled {
+                if output.enabled {
+                    switch_plan.outputs_to_disable.push(output);
+                }
+                continue;
+            }
+
+            if output.modes.is_empty() {
+                return Err(format!("output {name} has no known modes"));
+            }
+            if let Some(resolution) = state.resolution
+                && !output
+                    .modes
+                    .iter()
+                    .any(|mode| mode.resolution == resolution)
+            {
+                return Err(format!("output {name} has no {resolution} mode"));
+            }
+            switch_plan.outputs_to_enable.push(output);
+        }
+        Ok(switch_plan)
+    }
+
+    /// Whether every output in the state is already enabled or disabled as in it, at its mode and
+    /// position if the state has them.
+    pub(super) fn is_applied(&self, screen: &Screen) -> bool {
+        self.outputs.iter().all(|NamedOutputState { name, state }| {
+            screen.find_output_by_name(name).is_some_and(|output| {
+                let current = OutputState::current(output);
+                current.enabled == state.enabled
+                    && (!state.enabled
+                        || state
+                            .resolution
+                            .is_none_or(|_| current.resolution == state.resolution)
+                            && state
+                                .position
+                                .is_none_or(|_| current.position == state.position))
+            })
+        })
+    }
+
+    /// Mode of all enabled outputs, if they have the same one.
+    pub(super) fn common_resolution(&self) -> Option<Resolution> {
+        let mut resolutions = self.enabled_outputs().map(|output| output.state.resolution);
+        let first = resolutions.next()??;
+        resolutions
+            .all(|resolution| resolution == Some(first))
+            .then_some(first)
+    }
+
+    /// Restricts every enabled output to its mode in the state, if it has one, so that the
+    /// controllers set it even when the outputs have different modes.
+    pub(super) fn mode_restrictions(&self) -> ModeRestrictions {
+        ModeRestrictions {
+            blacklist: Vec::new(),
+            whitelist: self
+                .enabled_outputs()
+                .filter_map(|output| {
+                    Some(ModeFilter {
+                        output: output.name.clone(),
+                        resolution: output.state.resolution?,
+                        refresh_rate_hz: None,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Places every enabled output with a position in the state at it.
+    pub(super) fn layout(&self) -> Layout {
+        Layout::fixed(
+            self.enabled_outputs()
+                .filter_map(|output| Some((output.name.clone(), output.state.position?))),
+        )
+    }
+}
+
+impl std::str::FromStr for State {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|err| format!("invalid state: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::{Mode, Output, Position};
+
+    const FULL_HD: Resolution = Resolution {
+        width: 1920,
+        height: 1080,
+    };
+
+    fn test_screen() -> Screen {
+        let output = |name: &str, enabled: bool| Output {
+            modes: vec![Mode {
+                resolution: FULL_HD,
+                refresh_rate_millihz: 60000,
+                preferred: true,
+                active: enabled,
+                refresh_rates_millihz: Vec::new(),
+            }],
+            current_resolution: enabled.then_some(FULL_HD),
+            current_position: enabled.then(Position::default),
+            ..Output::test(name, true, enabled)
+        };
+
+        Screen {
+            outputs: vec![output("eDP-1", true), output("HDMI-1", false)],
+        }
+    }
+
+    #[test]
+    fn state_round_trips_through_json() {
+        // Arrange
+        let state = State::current(&test_screen());
+
+        // Act
+        let json = state.to_json();
+        let parsed = json.parse::<State>();
+
+        // Assert
+        assert_eq!(
+            json,
+            concat!(
+                "{\"outputs\":[",
+                "{\"name\":\"eDP-1\",\"enabled\":true,\"mode\":\"1920x1080\",\"position\":{\"x\":0,\"y\":0}},",
+                "{\"name\":\"HDMI-1\",\"enabled\":false,\"mode\":null,\"position\":null}",
+                "]}"
+            )
+        );
+        assert_eq!(parsed, Ok(state));
+    }
+
+    #[test]
+    fn state_parses_without_mode_and_position() {
+        // Act
+        let state = r#"{"outputs":[{"name":"HDMI-1","enabled":true}]}"#.parse::<State>();
+
+        // Assert
+        assert_eq!(
+            state,
+            Ok(State {
+                outputs: vec![NamedOutputState {
+                    name: "HDMI-1".to_string(),
+                    state: OutputState {
+                        enabled: true,
+                        resolution: None,
+                        position: None,
+                    },
+                }],
+            })
+        );
+        assert!(r#"{"outputs":[{"name":"HDMI-1"}]}"#.parse::<State>().is_err());
+        assert!(
+            r#"{"outputs":[{"name":"HDMI-1","enabled":true,"mode":"1920"}]}"#
+                .parse::<State>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn plan_enables_and_disables_outputs_as_in_the_state() {
+        // Arrange
+        let screen = test_screen();
+        let state: State = r#"{"outputs":[
+            {"name":"eDP-1","enabled":false},
+            {"name":"HDMI-1","enabled":true,"mode":"1920x1080","position":{"x":1920,"y":0}}
+        ]}"#
+        .parse()
+        .unwrap();
+
+        // Act
+        let switch_plan = state.build_switch_plan(&screen);
+
+        // Assert
+        let switch_plan = switch_plan.unwrap();
+        assert_eq!(switch_plan.outputs_to_disable, [&screen.outputs[0]]);
+        assert_eq!(switch_plan.outputs_to_enable, [&screen.outputs[1]]);
+        assert_eq!(state.common_resolution(), Some(FULL_HD));
+        assert_eq!(
+            state.layout().position("HDMI-1"),
+            Position { x: 1920, y: 0 }
+        );
+    }
+
+    #[test]
+    fn plan_fails_for_unknown_output_or_mode() {
+        // Arrange
+        let screen = test_screen();
+        let unknown_output: State =
+            r#"{"outputs":[{"name":"HMDI-1","enabled":true}]}"#.parse().unwrap();
+        let unknown_mode: State =
+            r#"{"outputs":[{"name":"HDMI-1","enabled":true,"mode":"3840x2160"}]}"#
+                .parse()
+                .unwrap();
+
+        // Act
+        let unknown_output_plan = unknown_output.build_switch_plan(&screen);
+        let unknown_mode_plan = unknown_mode.build_switch_plan(&screen);
+
+        // Assert
+        assert_eq!(
+            unknown_output_plan.map(|_| ()),
+            Err("output HMDI-1 does not exist".to_string())
+        );
+        assert_eq!(
+            unknown_mode_plan.map(|_| ()),
+            Err("output HDMI-1 has no 3840x2160 mode".to_string())
+        );
+    }
+
+    #[test]
+    fn outputs_with_different_modes_are_restricted_to_their_own() {
+        // Arrange
+        let state: State = r#"{"outputs":[
+            {"name":"eDP-1","enabled":true,"mode":"1920x1080"},
+            {"name":"HDMI-1","enabled":true,"mode":"1280x720"},
+            {"name":"DP-1","enabled":true}
+        ]}"#
+        .parse()
+        .unwrap();
+
+        // Act
+        let common_resolution = state.common_resolution();
+        let mode_restrictions = state.mode_restrictions();
+
+        // Assert
+        assert_eq!(common_resolution, None);
+        assert_eq!(
+            mode_restrictions.whitelist,
+            [
+                "eDP-1=1920x1080@*".parse().unwrap(),
+                "HDMI-1=1280x720@*".parse::<ModeFilter>().unwrap(),
+            ]
+        );
+        assert!(mode_restrictions.blacklist.is_empty());
+    }
+}
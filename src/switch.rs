@@ -1,312 +1,2286 @@
-use crate::screen::{Location, Output, Resolution, Screen};
+use crate::layout::{Layout, Rotation};
+use crate::screen::{
+    Color, ColorAdjustment, Location, Mode, ModeRestrictions, Output, Resolution, ScaleFilter,
+    Screen, round_millihz_to_hz,
+};
 use std::collections::HashSet;
 use std::iter::Iterator;
 
+/// Strategy for choosing the outputs to enable and disable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum SwitchMode {
+    /// Cycle from the internal outputs to all connected outputs mirrored, then to the external outputs only.
+    #[default]
+    Toggle,
+    /// Enable every connected output and disable the disconnected ones.
+    AllConnected,
+}
+
+/// How mirrored outputs share their picture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum MirrorMode {
+    /// Drive every output from its own CRTC, placed at the same position.
+    #[default]
+    Software,
+    /// Drive mirrored outputs from a single CRTC where the hardware allows it,
+    /// falling back to separate CRTCs otherwise.
+    Hardware,
+}
+
+/// What to maximize when choosing the common resolution of mirrored outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum MirrorOptimize {
+    /// The largest resolution, using the refresh rate only as a floor.
+    #[default]
+    Area,
+    /// The highest refresh rate all outputs can run at, then the largest resolution at it.
+    Refresh,
+}
+
 #[derive(Debug)]
 pub(crate) struct SwitchPlan<'a> {
     pub(crate) outputs_to_disable: Vec<&'a Output>,
     pub(crate) outputs_to_enable: Vec<&'a Output>,
 }
 
-pub(super) fn build_switch_plan<'a>(screen: &'a Screen) -> SwitchPlan<'a> {
-    if screen
-        .outputs
+/// A [`SwitchPlan`] holding copies of the outputs, so it can outlive the [`Screen`] it was
+/// built from, e.g. to remember the last switch across polls of the outputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OwnedSwitchPlan {
+    pub(crate) outputs_to_disable: Vec<Output>,
+    pub(crate) outputs_to_enable: Vec<Output>,
+}
+
+impl From<&SwitchPlan<'_>> for OwnedSwitchPlan {
+    fn from(switch_plan: &SwitchPlan<'_>) -> Self {
+        let to_owned = |outputs: &[&Output]| outputs.iter().map(|&output| output.clone()).collect();
+        Self {
+            outputs_to_disable: to_owned(&switch_plan.outputs_to_disable),
+            outputs_to_enable: to_owned(&switch_plan.outputs_to_enable),
+        }
+    }
+}
+
+/// Providers, i.e. GPUs, to connect with `xrandr --setprovideroutputsource`, so that the outputs
+/// of the sink, e.g. a discrete GPU on a hybrid laptop, show the picture rendered by the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ProviderOutputSource {
+    pub(crate) sink: String,
+    pub(crate) source: String,
+}
+
+/// How to configure the outputs when carrying out a [`SwitchPlan`], besides their resolution.
+#[derive(Debug, Default)]
+pub(crate) struct SwitchOptions {
+    pub(crate) color_adjustment: ColorAdjustment,
+    /// Color to fill outputs with before disabling them.
+    pub(crate) disabled_background: Option<Color>,
+    pub(crate) layout: Layout,
+    /// Reconfigure the outputs even if they already match the plan.
+    pub(crate) force: bool,
+    /// Run mirrored outputs at their largest resolution, scaling the picture of the largest one,
+    /// or of `scale_from`, to fit the others.
+    pub(crate) scale_mirrored: bool,
+    /// Output whose picture the other mirrored outputs show with `scale_mirrored`.
+    #[cfg_attr(not(feature = "xrandr"), allow(dead_code))]
+    pub(crate) scale_from: Option<String>,
+    /// Run mirrored outputs at their largest resolution, scaling the larger ones down
+    /// to show the area of the smallest one.
+    pub(crate) auto_scale_mirror: bool,
+    /// Filter for sampling the picture of scaled outputs.
+    pub(crate) scale_filter: Option<ScaleFilter>,
+    /// Power off outputs after disabling them, and power them on again when enabling them.
+    pub(crate) dpms_on_disable: bool,
+    /// Whether outputs may tear to lower latency.
+    pub(crate) allow_tearing: Option<bool>,
+    /// How long before the next frame to start rendering, 0 for off.
+    pub(crate) max_render_time_ms: Option<u32>,
+    /// Whether mirrored outputs share a CRTC, only honored by the RandR controller.
+    pub(crate) mirror_mode: MirrorMode,
+    /// Name of the output to make the primary one.
+    pub(crate) primary: Option<String>,
+    /// Modes never to be chosen, for controllers picking the mode of each output themselves.
+    pub(crate) mode_restrictions: ModeRestrictions,
+    /// Providers to connect before switching, checked to exist so that a typo fails the switch.
+    pub(crate) provider_output_source: Option<ProviderOutputSource>,
+    /// Keep the size of the X screen, for controllers setting it after configuring the CRTCs.
+    pub(crate) no_resize: bool,
+    /// Keep the position of outputs that stay enabled, e.g. as arranged by another tool.
+    pub(crate) no_reposition: bool,
+    /// Timings of the mode to create for outputs without modes, instead of CVT ones.
+    #[cfg_attr(not(any(feature = "xrandr", feature = "randr")), allow(dead_code))]
+    pub(crate) modeline: Option<crate::screen::Modeline>,
+}
+
+impl SwitchPlan<'_> {
+    pub(crate) fn is_noop(&self) -> bool {
+        self.outputs_to_disable.is_empty() && self.outputs_to_enable.is_empty()
+    }
+
+    /// Whether the outputs are already configured as carrying out the plan would configure them.
+    ///
+    /// Errs on the side of `false` when the current state cannot be fully verified,
+    /// e.g. when the resolution is left to the controller, color adjustment or scaling is requested.
+    pub(crate) fn is_applied(
+        &self,
+        resolution: Option<Resolution>,
+        options: &SwitchOptions,
+    ) -> bool {
+        let Some(resolution) = resolution else {
+            return false;
+        };
+        if options.color_adjustment.is_requested()
+            || options.scale_mirrored
+            || options.auto_scale_mirror
+            || options.primary.is_some()
+            || options.provider_output_source.is_some()
+        {
+            return false;
+        }
+
+        self.outputs_to_disable
+            .iter()
+            .all(|output| !output.enabled && (!options.dpms_on_disable || !output.powered))
+            && self
+                .outputs_to_enable
+                .iter()
+                .all(|output| is_enabled_as_planned(output, resolution, options))
+    }
+}
+
+/// Whether the output is already enabled at the resolution and position carrying out a plan
+/// would give it, so that it need not be reconfigured. The current rotation is not known,
+/// so outputs to be rotated are never considered to be enabled as planned. Outputs with
+/// restricted modes must also currently run in an allowed mode, e.g. at an allowed refresh rate.
+pub(crate) fn is_enabled_as_planned(
+    output: &Output,
+    resolution: Resolution,
+    options: &SwitchOptions,
+) -> bool {
+    output.enabled
+        && output.powered
+        && output.current_resolution == Some(resolution)
+        && output.current_position == Some(options.layout.position(&output.name))
+        && options.layout.rotation(&output.name) == Rotation::Normal
+        && options
+            .scale_filter
+            .is_none_or(|scale_filter| output.scale_filter == Some(scale_filter))
+        && options
+            .allow_tearing
+            .is_none_or(|allow_tearing| output.allow_tearing == Some(allow_tearing))
+        && options
+            .max_render_time_ms
+            .is_none_or(|max_render_time_ms| output.max_render_time_ms == Some(max_render_time_ms))
+        && (!options.mode_restrictions.restricts(&output.name)
+            || output
+                .modes
+                .iter()
+                .any(|mode| mode.active && options.mode_restrictions.allows(&output.name, mode)))
+}
+
+/// User-requested changes to the switch plan computed by [`build_switch_plan`].
+#[derive(Debug, Default)]
+pub(crate) struct SwitchPlanOverrides {
+    /// Do not disable any outputs.
+    pub(crate) no_disable: bool,
+    /// Do not enable any outputs.
+    pub(crate) no_enable: bool,
+    /// Names of outputs to disable in addition to the planned ones.
+    pub(crate) disable: Vec<String>,
+    /// Names of outputs to enable even if they are reported as disconnected.
+    pub(crate) force_enable: Vec<String>,
+}
+
+pub(super) fn apply_overrides<'a>(
+    switch_plan: &mut SwitchPlan<'a>,
+    screen: &'a Screen,
+    overrides: &SwitchPlanOverrides,
+) {
+    if overrides.no_disable {
+        switch_plan.outputs_to_disable.clear();
+    }
+
+    if overrides.no_enable {
+        switch_plan.outputs_to_enable.clear();
+    }
+
+    for name in &overrides.force_enable {
+        let Some(output) = screen.find_output_by_name(name) else {
+            log::warn!("Output {name:?} requested to be force-enabled does not exist");
+            continue;
+        };
+
+        switch_plan
+            .outputs_to_disable
+            .retain(|output_to_disable| !std::ptr::eq(*output_to_disable, output));
+
+        if !switch_plan
+            .outputs_to_enable
+            .iter()
+            .any(|output_to_enable| std::ptr::eq(*output_to_enable, output))
+        {
+            switch_plan.outputs_to_enable.push(output);
+        }
+    }
+
+    for name in &overrides.disable {
+        let Some(output) = screen.find_output_by_name(name) else {
+            log::warn!("Output {name:?} requested to be disabled does not exist");
+            continue;
+        };
+
+        switch_plan
+            .outputs_to_enable
+            .retain(|output_to_enable| !std::ptr::eq(*output_to_enable, output));
+
+        if output.enabled
+            && !switch_plan
+                .outputs_to_disable
+                .iter()
+                .any(|output_to_disable| std::ptr::eq(*output_to_disable, output))
+        {
+            switch_plan.outputs_to_disable.push(output);
+        }
+    }
+}
+
+/// Biases the plan towards the newest output, i.e. the one just plugged in, for --prefer-newest:
+/// it becomes the first output to enable, i.e. the mirror anchor and primary candidate, and
+/// other external outputs that are not enabled yet are left as they are.
+pub(super) fn prefer_newest_output(switch_plan: &mut SwitchPlan, name: &str) {
+    let Some(index) = switch_plan
+        .outputs_to_enable
+        .iter()
+        .position(|output| output.name == name)
+    else {
+        log::debug!("Preferred output {name:?} is not going to be enabled");
+        return;
+    };
+
+    let preferred = switch_plan.outputs_to_enable.remove(index);
+    if preferred.location == Location::External {
+        switch_plan
+            .outputs_to_enable
+            .retain(|output| output.location != Location::External || output.enabled);
+    }
+    switch_plan.outputs_to_enable.insert(0, preferred);
+}
+
+/// Chooses the first output of the given location among the outputs the plan enables,
+/// to be made the primary one.
+pub(super) fn choose_primary<'a>(
+    switch_plan: &SwitchPlan<'a>,
+    location: Location,
+) -> Option<&'a Output> {
+    switch_plan
+        .outputs_to_enable
+        .iter()
+        .copied()
+        .find(|output| output.location == location)
+}
+
+/// Keeps one output enabled if carrying out the plan would leave none, e.g. when a dock
+/// briefly reports all of its outputs as disconnected. An internal output is kept if possible.
+pub(super) fn keep_last_enabled_output(switch_plan: &mut SwitchPlan, screen: &Screen) {
+    if !switch_plan.outputs_to_enable.is_empty() {
+        return;
+    }
+
+    let remains_enabled = screen.enabled_outputs().any(|output| {
+        !switch_plan
+            .outputs_to_disable
+            .iter()
+            .any(|output_to_disable| std::ptr::eq(*output_to_disable, output))
+    });
+    if remains_enabled {
+        return;
+    }
+
+    let Some(index) = switch_plan
+        .outputs_to_disable
         .iter()
-        .any(|output| output.location == Location::Internal && output.connected && output.enabled)
+        .position(|output| output.location == Location::Internal)
+        .or((!switch_plan.outputs_to_disable.is_empty()).then_some(0))
+    else {
+        return;
+    };
+
+    let output = switch_plan.outputs_to_disable.remove(index);
+    log::warn!(
+        "Not disabling {}, which would leave no output enabled; use --force to disable it anyway",
+        output.name
+    );
+}
+
+/// Builds the plan switching to the next state, leaving virtual outputs as they are
+/// unless `include_virtual` is set.
+pub(super) fn build_switch_plan(screen: &Screen, include_virtual: bool) -> SwitchPlan<'_> {
+    let switch_plan = build_target_switch_plan(screen, include_virtual);
+
+    // Already enabled outputs may still need a modeset to switch them to a common
+    // resolution with newly enabled ones, or need to be powered on again, so they
+    // are only dropped from the plan when nothing else changes.
+    if switch_plan.outputs_to_disable.is_empty()
+        && switch_plan
+            .outputs_to_enable
+            .iter()
+            .all(|output| output.enabled && output.powered)
     {
-        if screen.outputs.iter().any(|output| {
-            output.location == Location::External && output.connected && output.enabled
-        }) {
-            SwitchPlan {
-                outputs_to_disable: screen
-                    .outputs
-                    .iter()
-                    .filter(|output| {
-                        output.enabled
-                            && (!output.connected || output.location == Location::Internal)
-                    })
-                    .collect(),
-                outputs_to_enable: screen
-                    .outputs
+        SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: Vec::new(),
+        }
+    } else {
+        switch_plan
+    }
+}
+
+/// Builds the plan restoring the outputs the last plan enabled, e.g. after they were changed
+/// by hand: the ones still connected are enabled and all other outputs disabled. Falls back
+/// to the target plan without a last plan, or if none of its outputs is connected anymore.
+pub(super) fn build_reconcile_switch_plan<'a>(
+    screen: &'a Screen,
+    last_switch_plan: Option<&OwnedSwitchPlan>,
+    include_virtual: bool,
+) -> SwitchPlan<'a> {
+    let outputs_to_enable: Vec<_> = last_switch_plan
+        .into_iter()
+        .flat_map(|last_switch_plan| &last_switch_plan.outputs_to_enable)
+        .filter_map(|last_output| screen.find_output_by_name(&last_output.name))
+        .filter(|output| output.connected)
+        .collect();
+    if outputs_to_enable.is_empty() {
+        return build_target_switch_plan(screen, include_virtual);
+    }
+
+    let switch_plan = SwitchPlan {
+        outputs_to_disable: screen
+            .enabled_outputs()
+            .filter(|output| {
+                !outputs_to_enable
                     .iter()
-                    .filter(|output| output.location == Location::External && output.connected)
-                    .collect(),
-            }
+                    .any(|output_to_enable| std::ptr::eq(*output_to_enable, *output))
+            })
+            .collect(),
+        outputs_to_enable,
+    };
+    exclude_virtual_outputs(switch_plan, include_virtual)
+}
+
+/// Builds the plan without dropping outputs that already appear to be in the target state.
+/// All internal outputs are switched together, so devices with several internal panels
+/// keep them in the same state.
+pub(super) fn build_target_switch_plan(screen: &Screen, include_virtual: bool) -> SwitchPlan<'_> {
+    let switch_plan = if screen
+        .internal_outputs()
+        .any(|output| output.connected && output.enabled)
+    {
+        if screen
+            .external_outputs()
+            .any(|output| output.connected && output.enabled)
+        {
+            plan_for_internal_and_external(screen)
         } else {
-            SwitchPlan {
-                outputs_to_disable: screen
-                    .outputs
-                    .iter()
-                    .filter(|output| output.enabled && !output.connected)
-                    .collect(),
-                outputs_to_enable: screen
-                    .outputs
-                    .iter()
-                    .filter(|output| output.connected)
-                    .collect(),
-            }
+            plan_for_internal_only(screen)
         }
+    } else if screen.internal_outputs().any(|output| output.connected) {
+        plan_for_external_active(screen)
     } else {
-        SwitchPlan {
-            outputs_to_disable: screen
-                .outputs
-                .iter()
-                .filter(|output| {
-                    output.enabled && (!output.connected || output.location == Location::External)
-                })
-                .collect(),
-            outputs_to_enable: screen
-                .outputs
-                .iter()
-                .filter(|output| output.connected && output.location == Location::Internal)
-                .collect(),
+        plan_for_no_internal(screen)
+    };
+    exclude_virtual_outputs(switch_plan, include_virtual)
+}
+
+/// Leaves virtual outputs, e.g. of a nested compositor, as they are unless `include_virtual` is set.
+fn exclude_virtual_outputs(
+    mut switch_plan: SwitchPlan<'_>,
+    include_virtual: bool,
+) -> SwitchPlan<'_> {
+    if !include_virtual {
+        switch_plan
+            .outputs_to_disable
+            .retain(|output| output.location != Location::Virtual);
+        switch_plan
+            .outputs_to_enable
+            .retain(|output| output.location != Location::Virtual);
+    }
+    switch_plan
+}
+
+/// Both internal and external outputs are enabled, switches to the external ones only.
+fn plan_for_internal_and_external(screen: &Screen) -> SwitchPlan<'_> {
+    SwitchPlan {
+        outputs_to_disable: screen
+            .enabled_outputs()
+            .filter(|output| !output.connected || output.location == Location::Internal)
+            .collect(),
+        outputs_to_enable: screen
+            .external_outputs()
+            .filter(|output| output.connected)
+            .collect(),
+    }
+}
+
+/// Only internal outputs are enabled, mirrors them to every connected output.
+fn plan_for_internal_only(screen: &Screen) -> SwitchPlan<'_> {
+    SwitchPlan {
+        outputs_to_disable: screen
+            .enabled_outputs()
+            .filter(|output| !output.connected)
+            .collect(),
+        outputs_to_enable: screen.connected_outputs().collect(),
+    }
+}
+
+/// An internal output is connected but disabled, switches back to the internal outputs only.
+fn plan_for_external_active(screen: &Screen) -> SwitchPlan<'_> {
+    SwitchPlan {
+        outputs_to_disable: screen
+            .enabled_outputs()
+            .filter(|output| !output.connected || output.location == Location::External)
+            .collect(),
+        outputs_to_enable: screen
+            .internal_outputs()
+            .filter(|output| output.connected)
+            .collect(),
+    }
+}
+
+/// Without an internal display, e.g. on a desktop, there is nothing to switch to,
+/// so keeps the external outputs on instead of leaving the screen dark.
+fn plan_for_no_internal(screen: &Screen) -> SwitchPlan<'_> {
+    SwitchPlan {
+        outputs_to_disable: screen
+            .enabled_outputs()
+            .filter(|output| !output.connected)
+            .collect(),
+        outputs_to_enable: screen
+            .external_outputs()
+            .filter(|output| output.connected)
+            .collect(),
+    }
+}
+
+/// Builds a plan enabling every connected output and disabling only the disconnected ones,
+/// like `xrandr --auto` does.
+pub(super) fn build_switch_plan_all_connected(
+    screen: &Screen,
+    include_virtual: bool,
+) -> SwitchPlan<'_> {
+    let switch_plan = SwitchPlan {
+        outputs_to_disable: screen
+            .enabled_outputs()
+            .filter(|output| !output.connected)
+            .collect(),
+        outputs_to_enable: screen.connected_outputs().collect(),
+    };
+    exclude_virtual_outputs(switch_plan, include_virtual)
+}
+
+/// Builds a plan making the named output the sole enabled one, regardless of its location
+/// or whether it is reported as connected. Returns `None` if there is no such output.
+pub(super) fn build_only_switch_plan<'a>(screen: &'a Screen, name: &str) -> Option<SwitchPlan<'a>> {
+    let only_output = screen.find_output_by_name(name)?;
+
+    Some(SwitchPlan {
+        outputs_to_disable: screen
+            .enabled_outputs()
+            .filter(|output| !std::ptr::eq(*output, only_output))
+            .collect(),
+        outputs_to_enable: vec![only_output],
+    })
+}
+
+/// Reason why [`choose_best_resolution`] could not choose a resolution.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ChooseResolutionError {
+    /// There are no outputs to choose a resolution for.
+    NoOutputs,
+    /// The named output reports no modes at all.
+    NoModesOnOutput(String),
+    /// The outputs have no resolution in common, regardless of constraints.
+    NoCommonResolution {
+        /// Resolutions supported by each output, largest first.
+        per_output: Vec<(String, Vec<Resolution>)>,
+    },
+    /// There are common resolutions, but none of them meets the minimum refresh rate, resolution or DPI.
+    NoResolutionMeetsConstraints,
+}
+
+impl std::fmt::Display for ChooseResolutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChooseResolutionError::NoOutputs => write!(f, "no outputs to choose a resolution for"),
+            ChooseResolutionError::NoModesOnOutput(name) => {
+                write!(f, "output {name} does not report any modes")
+            }
+            ChooseResolutionError::NoCommonResolution { per_output } => {
+                write!(f, "no common resolution")?;
+                for (i, (name, resolutions)) in per_output.iter().enumerate() {
+                    let resolutions = resolutions
+                        .iter()
+                        .map(Resolution::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let separator = if i == 0 { ":" } else { "," };
+                    write!(f, "{separator} {name} supports [{resolutions}]")?;
+                }
+                Ok(())
+            }
+            ChooseResolutionError::NoResolutionMeetsConstraints => write!(
+                f,
+                "no common resolution meets the minimum refresh rate, resolution and DPI"
+            ),
         }
     }
 }
 
+/// What [`choose_best_resolution`] chooses the common resolution by.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResolutionConstraints {
+    /// Lowest refresh rate, in millihertz, every output must reach at the resolution.
+    pub(crate) min_refresh_rate: Option<u32>,
+    /// Smallest area the resolution must have.
+    pub(crate) min_resolution: Option<Resolution>,
+    /// Choose the resolution nearest to this one by area, preferring ones not exceeding it,
+    /// instead of the largest one.
+    pub(crate) closest_to: Option<Resolution>,
+    /// Lowest pixel density every output of known physical size must have at the resolution.
+    pub(crate) min_dpi: Option<f32>,
+    /// Prefer resolutions of this aspect ratio, falling back to any if none is common.
+    pub(crate) aspect_ratio: Option<Resolution>,
+    /// What to maximize among the resolutions meeting the other constraints.
+    pub(crate) optimize: MirrorOptimize,
+    /// Keep the resolution all outputs are already running at if it is a candidate,
+    /// to avoid an unnecessary modeset.
+    pub(crate) prefer_current: bool,
+}
+
+/// Chooses the largest resolution supported by all outputs that meets the constraints,
+/// or with `closest_to`, the one nearest to it by area, preferring ones not exceeding it.
+/// Outputs of unknown physical size always meet `min_dpi`. With `aspect_ratio`, resolutions
+/// of that aspect ratio are preferred, falling back to any if none is common to all outputs.
+/// With [`MirrorOptimize::Refresh`], only the resolutions giving the highest refresh rate common
+/// to all outputs are considered. With `prefer_current`, the resolution all outputs are already
+/// running at is kept if it is still a candidate then, to avoid an unnecessary modeset.
 pub(super) fn choose_best_resolution(
     outputs: &[&Output],
-    min_refresh_rate: Option<u32>,
-) -> Option<Resolution> {
-    outputs
-        .iter()
-        .map(|output| {
+    constraints: &ResolutionConstraints,
+) -> Result<Resolution, ChooseResolutionError> {
+    let ResolutionConstraints {
+        min_refresh_rate,
+        min_resolution,
+        closest_to,
+        min_dpi,
+        aspect_ratio,
+        optimize,
+        prefer_current,
+    } = *constraints;
+    if outputs.is_empty() {
+        return Err(ChooseResolutionError::NoOutputs);
+    }
+    if let Some(output) = outputs.iter().find(|output| output.modes.is_empty()) {
+        return Err(ChooseResolutionError::NoModesOnOutput(output.name.clone()));
+    }
+
+    let common_resolutions = |filter: &dyn Fn(&Output, &Mode) -> bool| {
+        outputs
+            .iter()
+            .map(|output| {
+                output
+                    .modes
+                    .iter()
+                    .filter(|mode| filter(output, mode))
+                    .map(|mode| mode.resolution)
+                    .collect::<HashSet<_>>()
+            })
+            .reduce(|mut acc, e| {
+                acc.retain(|resolution| e.contains(resolution));
+                acc
+            })
+            .unwrap_or_default()
+    };
+
+    let meets_constraints = |output: &Output, mode: &Mode| {
+        min_refresh_rate.is_none_or(|min_refresh_rate| {
+            mode.refresh_rate_hz_rounded() >= round_millihz_to_hz(min_refresh_rate)
+        }) && min_resolution
+            .is_none_or(|min_resolution| mode.resolution.area() >= min_resolution.area())
+            && min_dpi.is_none_or(|min_dpi| {
+                output
+                    .dpi_at(mode.resolution)
+                    .is_none_or(|dpi| dpi >= min_dpi)
+            })
+    };
+
+    let mut candidates = common_resolutions(&meets_constraints);
+    if let Some(aspect_ratio) = aspect_ratio {
+        let matching: HashSet<_> = candidates
+            .iter()
+            .copied()
+            .filter(|resolution| resolution.has_aspect_ratio_of(aspect_ratio))
+            .collect();
+        if matching.is_empty() {
+            if !candidates.is_empty() {
+                log::warn!(
+                    "No common resolution has the aspect ratio of {aspect_ratio}, ignoring it"
+                );
+            }
+        } else {
+            candidates = matching;
+        }
+    }
+    if optimize == MirrorOptimize::Refresh {
+        // The refresh rate every output can run at, in Hz, for rates like 59.94 and 60 to match.
+        let common_refresh_rate = |resolution: Resolution| {
+            outputs
+                .iter()
+                .map(|output| {
+                    output
+                        .modes
+                        .iter()
+                        .filter(|mode| {
+                            mode.resolution == resolution && meets_constraints(output, mode)
+                        })
+                        .map(Mode::refresh_rate_hz_rounded)
+                        .max()
+                        .unwrap_or(0)
+                })
+                .min()
+                .unwrap_or(0)
+        };
+        if let Some(max_refresh_rate) = candidates.iter().copied().map(common_refresh_rate).max() {
+            candidates.retain(|&resolution| common_refresh_rate(resolution) == max_refresh_rate);
+        }
+    }
+    if prefer_current {
+        let mut current_resolutions = outputs.iter().map(|output| {
             output
                 .modes
                 .iter()
-                .filter(|mode| {
-                    min_refresh_rate.is_none_or(|min_refresh_rate| {
-                        mode.refresh_rate_millihz >= min_refresh_rate
-                    })
-                })
+                .find(|mode| mode.active)
                 .map(|mode| mode.resolution)
-                .collect::<HashSet<_>>()
-        })
-        .reduce(|mut acc, e| {
-            acc.retain(|resolution| e.contains(resolution));
-            acc
+        });
+        if let Some(Some(current_resolution)) = current_resolutions.next()
+            && current_resolutions.all(|resolution| resolution == Some(current_resolution))
+            && candidates.contains(&current_resolution)
+        {
+            return Ok(current_resolution);
+        }
+    }
+    let best = match closest_to {
+        Some(target) => candidates.into_iter().min_by_key(|resolution| {
+            let exceeds = resolution.area() > target.area();
+            (
+                exceeds,
+                resolution.area().abs_diff(target.area()),
+                std::cmp::Reverse(resolution.width),
+            )
+        }),
+        None => candidates.into_iter().max_by_key(Resolution::area),
+    };
+    if let Some(resolution) = best {
+        return Ok(resolution);
+    }
+
+    if common_resolutions(&|_, _| true).is_empty() {
+        Err(ChooseResolutionError::NoCommonResolution {
+            per_output: outputs
+                .iter()
+                .map(|output| (output.name.clone(), supported_resolutions(output)))
+                .collect(),
         })
-        .and_then(|resolutions| resolutions.into_iter().max_by_key(Resolution::area))
+    } else {
+        Err(ChooseResolutionError::NoResolutionMeetsConstraints)
+    }
+}
+
+/// Uniform scale making an output of the given native resolution show at least the given
+/// logical area, e.g. 0.5 for showing 1920x1080 on a 3840x2160 output.
+#[cfg(feature = "xrandr")]
+pub(crate) fn mirror_scale(native: Resolution, logical: Resolution) -> f64 {
+    let horizontal = logical.width as f64 / native.width as f64;
+    let vertical = logical.height as f64 / native.height as f64;
+    horizontal.max(vertical)
+}
+
+fn supported_resolutions(output: &Output) -> Vec<Resolution> {
+    let mut resolutions: Vec<_> = output.modes.iter().map(|mode| mode.resolution).collect();
+    resolutions.sort_by_key(|resolution| std::cmp::Reverse((resolution.area(), resolution.width)));
+    resolutions.dedup();
+    resolutions
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::screen::Mode;
+    use crate::screen::Position;
+
+    #[test]
+    fn when_no_outputs_nothing_must_be_switched() {
+        // Arrange
+        let screen = Screen {
+            outputs: Vec::new(),
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert!(switch_plan.outputs_to_enable.is_empty());
+    }
+
+    #[test]
+    fn when_only_internal_is_connected_and_enabled_nothing_must_be_switched() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output::test("HDMI-1", false, false),
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert!(switch_plan.is_noop());
+    }
+
+    #[test]
+    fn reconcile_plan_restores_outputs_of_last_plan() {
+        // Arrange
+        let last_screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-2", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+        let last_switch_plan = OwnedSwitchPlan::from(&SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&last_screen.outputs[2], &last_screen.outputs[0]],
+        });
+        // HDMI-1 was disabled and eDP-2 enabled by hand since.
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-2", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_reconcile_switch_plan(&screen, Some(&last_switch_plan), false);
+        let without_last_plan = build_reconcile_switch_plan(&screen, None, false);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[1]]);
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[2], &screen.outputs[0]],
+        );
+        let target_switch_plan = build_target_switch_plan(&screen, false);
+        assert_eq_ref(
+            &without_last_plan.outputs_to_disable,
+            &target_switch_plan.outputs_to_disable,
+        );
+        assert_eq_ref(
+            &without_last_plan.outputs_to_enable,
+            &target_switch_plan.outputs_to_enable,
+        );
+    }
+
+    /// All orderings of the given items.
+    fn permutations<T: Copy>(items: &[T]) -> Vec<Vec<T>> {
+        if items.is_empty() {
+            return vec![Vec::new()];
+        }
+        (0..items.len())
+            .flat_map(|i| {
+                let mut rest = items.to_vec();
+                let first = rest.remove(i);
+                permutations(&rest).into_iter().map(move |mut permutation| {
+                    permutation.insert(0, first);
+                    permutation
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn switch_plan_does_not_depend_on_order_of_outputs() {
+        // Arrange
+        let output = |&(name, connected, enabled): &(&str, bool, bool)| Output {
+            modes: vec![TEST_MODE],
+            ..Output::test(name, connected, enabled)
+        };
+        let names = |outputs: &[&Output]| {
+            let mut names: Vec<_> = outputs.iter().map(|output| output.name.clone()).collect();
+            names.sort();
+            names
+        };
+        let states = [(false, false), (false, true), (true, false), (true, true)];
+
+        for edp in states {
+            for hdmi in states {
+                for dp in states {
+                    let outputs = [
+                        ("eDP-1", edp.0, edp.1),
+                        ("HDMI-1", hdmi.0, hdmi.1),
+                        ("DP-1", dp.0, dp.1),
+                    ];
+                    let screen = Screen {
+                        outputs: outputs.iter().map(output).collect(),
+                    };
+                    let switch_plan = build_switch_plan(&screen, false);
+
+                    for permutation in permutations(&outputs) {
+                        let permuted_screen = Screen {
+                            outputs: permutation.iter().map(output).collect(),
+                        };
+
+                        // Act
+                        let permuted_switch_plan = build_switch_plan(&permuted_screen, false);
+
+                        // Assert
+                        assert_eq!(
+                            names(&permuted_switch_plan.outputs_to_disable),
+                            names(&switch_plan.outputs_to_disable),
+                            "{permutation:?}"
+                        );
+                        assert_eq!(
+                            names(&permuted_switch_plan.outputs_to_enable),
+                            names(&switch_plan.outputs_to_enable),
+                            "{permutation:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn when_plan_would_disable_the_only_enabled_output_it_must_be_kept() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", false, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", false, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DVI-D-1", false, false)
+                },
+            ],
+        };
+        let mut switch_plan = build_switch_plan(&screen, false);
+        let planned_to_disable = switch_plan.outputs_to_disable.clone();
+
+        // Act
+        keep_last_enabled_output(&mut switch_plan, &screen);
+
+        // Assert
+        assert_eq_ref(&planned_to_disable, &[&screen.outputs[0]]);
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert!(switch_plan.outputs_to_enable.is_empty());
+    }
+
+    #[test]
+    fn when_plan_leaves_an_output_enabled_nothing_must_be_kept() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DVI-D-1", false, true)
+                },
+            ],
+        };
+        let mut switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&screen.outputs[2]],
+            outputs_to_enable: Vec::new(),
+        };
+
+        // Act
+        keep_last_enabled_output(&mut switch_plan, &screen);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[2]]);
+    }
+
+    #[test]
+    fn newest_output_becomes_the_anchor_and_primary_candidate() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DVI-D-1", true, false)
+                },
+            ],
+        };
+        let mut switch_plan = build_switch_plan(&screen, false);
+
+        // Act
+        prefer_newest_output(&mut switch_plan, "DVI-D-1");
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[2]]);
+        assert!(
+            choose_primary(&switch_plan, Location::External)
+                .is_some_and(|output| output.name == "DVI-D-1")
+        );
+    }
+
+    #[test]
+    fn newest_output_keeps_enabled_outputs() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DVI-D-1", true, false)
+                },
+            ],
+        };
+        let mut switch_plan = build_target_switch_plan(&screen, false);
+
+        // Act
+        prefer_newest_output(&mut switch_plan, "DVI-D-1");
+
+        // Assert
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[2], &screen.outputs[0]],
+        );
+    }
+
+    #[test]
+    fn primary_is_the_first_enabled_output_of_the_location() {
+        // Arrange
+        let mut screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DVI-D-1", false, true)
+                },
+            ],
+        };
+        screen.mark_internal(&["DP-1".to_string()]);
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&screen.outputs[2]],
+            outputs_to_enable: vec![&screen.outputs[1], &screen.outputs[0]],
+        };
+
+        // Act
+        let internal = choose_primary(&switch_plan, Location::Internal);
+        let external = choose_primary(&switch_plan, Location::External);
+
+        // Assert
+        assert!(internal.is_some_and(|output| output.name == "DP-1"));
+        assert!(external.is_some_and(|output| output.name == "HDMI-1"));
+    }
+
+    #[test]
+    fn when_internal_connector_is_marked_it_must_be_switched_as_internal() {
+        // Arrange
+        let mut screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DVI-D-1", false, true)
+                },
+            ],
+        };
+        screen.mark_internal(&["DP-1".to_string()]);
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert_eq!(screen.outputs[0].location, Location::Internal);
+        assert_eq_ref(
+            &switch_plan.outputs_to_disable,
+            &[&screen.outputs[1], &screen.outputs[2]],
+        );
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[0]]);
+    }
+
+    #[test]
+    fn when_no_internal_is_connected_must_enable_all_connected_external() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DVI-D-1", false, true)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[2]]);
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[1]],
+        );
+    }
+
+    #[test]
+    fn when_dual_internal_and_external_are_enabled_must_disable_both_internal() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-2", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert_eq_ref(
+            &switch_plan.outputs_to_disable,
+            &[&screen.outputs[0], &screen.outputs[1]],
+        );
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[2]]);
+    }
+
+    #[test]
+    fn when_only_external_is_enabled_must_enable_both_internal() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-2", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[2]]);
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[1]],
+        );
+    }
+
+    #[test]
+    fn when_one_of_dual_internal_is_enabled_must_enable_both_with_external() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-2", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[1], &screen.outputs[2]],
+        );
+    }
+
+    #[test]
+    fn when_one_of_dual_internal_is_enabled_with_external_must_disable_it() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-2", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[1]]);
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[2]]);
+    }
+
+    /// Internal and external outputs, and an external one left enabled after being unplugged.
+    #[test]
+    fn plan_for_internal_and_external_switches_to_external() {
+        // Arrange
+        let mut screen = overrides_test_screen();
+        screen.outputs[2].connected = false;
+        screen.outputs[2].enabled = true;
+
+        // Act
+        let switch_plan = plan_for_internal_and_external(&screen);
+
+        // Assert
+        assert_eq_ref(
+            &switch_plan.outputs_to_disable,
+            &[&screen.outputs[0], &screen.outputs[2]],
+        );
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[1]]);
+    }
+
+    #[test]
+    fn plan_for_internal_only_mirrors_to_all_connected() {
+        // Arrange
+        let mut screen = overrides_test_screen();
+        screen.outputs[1].enabled = false;
+        screen.outputs[2].connected = false;
+        screen.outputs[2].enabled = true;
+
+        // Act
+        let switch_plan = plan_for_internal_only(&screen);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[2]]);
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[1]],
+        );
+    }
+
+    #[test]
+    fn plan_for_external_active_switches_to_internal() {
+        // Arrange
+        let mut screen = overrides_test_screen();
+        screen.outputs[0].enabled = false;
+        screen.outputs[2].connected = false;
+        screen.outputs[2].enabled = true;
+
+        // Act
+        let switch_plan = plan_for_external_active(&screen);
+
+        // Assert
+        assert_eq_ref(
+            &switch_plan.outputs_to_disable,
+            &[&screen.outputs[1], &screen.outputs[2]],
+        );
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[0]]);
+    }
+
+    #[test]
+    fn plan_for_no_internal_keeps_connected_external() {
+        // Arrange
+        let mut screen = overrides_test_screen();
+        screen.outputs[0].enabled = false;
+        screen.outputs[1].enabled = false;
+        screen.outputs[2].connected = false;
+        screen.outputs[2].enabled = true;
+
+        // Act
+        let switch_plan = plan_for_no_internal(&screen);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[2]]);
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[1]]);
+    }
+
+    #[test]
+    fn virtual_outputs_must_be_left_alone_by_default() {
+        // Arrange
+        let output = |name: &str, enabled| Output {
+            modes: vec![TEST_MODE],
+            ..Output::test(name, true, enabled)
+        };
+        let screen = Screen {
+            outputs: vec![
+                output("eDP-1", true),
+                output("HDMI-1", false),
+                output("WL-1", false),
+                output("HEADLESS-1", false),
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[1]],
+        );
+    }
+
+    #[test]
+    fn virtual_outputs_must_be_switched_when_included() {
+        // Arrange
+        let output = |name: &str, connected| Output {
+            modes: vec![TEST_MODE],
+            ..Output::test(name, connected, true)
+        };
+        let screen = Screen {
+            outputs: vec![
+                output("eDP-1", true),
+                output("VIRTUAL-1", false),
+                output("WL-1", true),
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, true);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[1]]);
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[2]],
+        );
+    }
+
+    #[test]
+    fn when_only_internal_is_connected_and_powered_off_must_enable_internal() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![Output {
+                modes: vec![TEST_MODE],
+                powered: false,
+                ..Output::test("eDP-1", true, true)
+            }],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[0]]);
+    }
+
+    #[test]
+    fn when_only_internal_is_connected_and_disconnected_is_enabled_must_disable_disconnected() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output::test("HDMI-1", false, true),
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert!(!switch_plan.is_noop());
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[1]]);
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[0]]);
+    }
+
+    #[test]
+    fn when_nothing_is_enabled_must_enable_internal() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[0]]);
+    }
+
+    #[test]
+    fn when_internal_is_enabled_must_disable_disconnected_and_enable_internal_and_external() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-2", false, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", false, false)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[2]]);
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[1]],
+        );
+    }
+
+    #[test]
+    fn when_internal_and_external_are_enabled_must_disable_internal_and_disconnected_and_enable_external()
+     {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-2", false, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", false, false)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert_eq_ref(
+            &switch_plan.outputs_to_disable,
+            &[&screen.outputs[0], &screen.outputs[2]],
+        );
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[1]]);
+    }
+
+    #[test]
+    fn when_external_is_enabled_must_disable_external_and_disconnected_and_enable_internal() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-2", false, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-2", false, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", false, false)
+                },
+            ],
+        };
+
+        // Act
+        let switch_plan = build_switch_plan(&screen, false);
+
+        // Assert
+        assert_eq_ref(
+            &switch_plan.outputs_to_disable,
+            &[&screen.outputs[1], &screen.outputs[2], &screen.outputs[3]],
+        );
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[0]]);
+    }
+
+    #[test]
+    fn when_no_overrides_apply_overrides_keeps_plan() {
+        // Arrange
+        let screen = overrides_test_screen();
+        let mut switch_plan = build_switch_plan(&screen, false);
+
+        // Act
+        apply_overrides(&mut switch_plan, &screen, &SwitchPlanOverrides::default());
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[0]]);
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[1], &screen.outputs[2]],
+        );
+    }
+
+    #[test]
+    fn when_no_disable_apply_overrides_disables_nothing() {
+        // Arrange
+        let screen = overrides_test_screen();
+        let mut switch_plan = build_switch_plan(&screen, false);
+        let overrides = SwitchPlanOverrides {
+            no_disable: true,
+            ..Default::default()
+        };
+
+        // Act
+        apply_overrides(&mut switch_plan, &screen, &overrides);
+
+        // Assert
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[1], &screen.outputs[2]],
+        );
+    }
+
+    #[test]
+    fn when_no_enable_apply_overrides_enables_nothing() {
+        // Arrange
+        let screen = overrides_test_screen();
+        let mut switch_plan = build_switch_plan(&screen, false);
+        let overrides = SwitchPlanOverrides {
+            no_enable: true,
+            ..Default::default()
+        };
+
+        // Act
+        apply_overrides(&mut switch_plan, &screen, &overrides);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[0]]);
+        assert!(switch_plan.outputs_to_enable.is_empty());
+    }
+
+    #[test]
+    fn when_no_disable_and_no_enable_apply_overrides_switches_nothing() {
+        // Arrange
+        let screen = overrides_test_screen();
+        let mut switch_plan = build_switch_plan(&screen, false);
+        let overrides = SwitchPlanOverrides {
+            no_disable: true,
+            no_enable: true,
+            ..Default::default()
+        };
+
+        // Act
+        apply_overrides(&mut switch_plan, &screen, &overrides);
+
+        // Assert
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert!(switch_plan.outputs_to_enable.is_empty());
+    }
+
+    #[test]
+    fn when_no_enable_and_disable_apply_overrides_disables_only_requested_outputs() {
+        // Arrange
+        let screen = overrides_test_screen();
+        let mut switch_plan = build_switch_plan(&screen, false);
+        let overrides = SwitchPlanOverrides {
+            no_disable: true,
+            no_enable: true,
+            disable: vec!["HDMI-1".to_string(), "DP-1".to_string(), "DP-2".to_string()],
+            ..Default::default()
+        };
+
+        // Act
+        apply_overrides(&mut switch_plan, &screen, &overrides);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[1]]);
+        assert!(switch_plan.outputs_to_enable.is_empty());
+    }
+
+    #[test]
+    fn when_disable_apply_overrides_does_not_enable_disabled_outputs() {
+        // Arrange
+        let screen = overrides_test_screen();
+        let mut switch_plan = build_switch_plan(&screen, false);
+        let overrides = SwitchPlanOverrides {
+            disable: vec!["eDP-1".to_string(), "HDMI-1".to_string()],
+            ..Default::default()
+        };
+
+        // Act
+        apply_overrides(&mut switch_plan, &screen, &overrides);
+
+        // Assert
+        assert_eq_ref(
+            &switch_plan.outputs_to_disable,
+            &[&screen.outputs[0], &screen.outputs[1]],
+        );
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[2]]);
+    }
+
+    #[test]
+    fn when_force_enable_apply_overrides_enables_disconnected_output() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output::test("HDMI-1", false, true),
+            ],
+        };
+        let mut switch_plan = build_switch_plan(&screen, false);
+        let overrides = SwitchPlanOverrides {
+            force_enable: vec!["HDMI-1".to_string(), "DP-1".to_string()],
+            ..Default::default()
+        };
+
+        // Act
+        apply_overrides(&mut switch_plan, &screen, &overrides);
+
+        // Assert
+        assert!(switch_plan.outputs_to_disable.is_empty());
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[1]],
+        );
+    }
+
+    #[test]
+    fn when_no_enable_and_force_enable_apply_overrides_enables_only_requested_outputs() {
+        // Arrange
+        let screen = overrides_test_screen();
+        let mut switch_plan = build_switch_plan(&screen, false);
+        let overrides = SwitchPlanOverrides {
+            no_enable: true,
+            force_enable: vec!["DP-1".to_string()],
+            ..Default::default()
+        };
+
+        // Act
+        apply_overrides(&mut switch_plan, &screen, &overrides);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[0]]);
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[2]]);
+    }
+
+    fn overrides_test_screen() -> Screen {
+        Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("HDMI-1", true, true)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("DP-1", true, false)
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn auto_enables_all_connected_outputs() {
+        // Arrange
+        let mut screen = overrides_test_screen();
+        screen.outputs.push(Output::test("DP-2", false, true));
+
+        // Act
+        let switch_plan = build_switch_plan_all_connected(&screen, false);
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[3]]);
+        assert_eq_ref(
+            &switch_plan.outputs_to_enable,
+            &[&screen.outputs[0], &screen.outputs[1], &screen.outputs[2]],
+        );
+    }
+
+    #[test]
+    fn only_disables_other_outputs_and_enables_named_one() {
+        // Arrange
+        let screen = overrides_test_screen();
+
+        // Act
+        let switch_plan = build_only_switch_plan(&screen, "HDMI-1").unwrap();
+
+        // Assert
+        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[0]]);
+        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[1]]);
+    }
+
+    #[test]
+    fn only_with_unknown_output_builds_no_plan() {
+        // Arrange
+        let screen = overrides_test_screen();
+
+        // Act
+        let switch_plan = build_only_switch_plan(&screen, "DP-9");
+
+        // Assert
+        assert!(switch_plan.is_none());
+    }
+
+    #[test]
+    fn plan_with_different_scale_filter_is_not_applied() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    current_resolution: Some(TEST_MODE.resolution),
+                    current_position: Some(Position::default()),
+                    scale_filter: Some(ScaleFilter::Bilinear),
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&screen.outputs[0]],
+            outputs_to_enable: vec![&screen.outputs[1]],
+        };
+        let options = |scale_filter| SwitchOptions {
+            scale_filter: Some(scale_filter),
+            ..Default::default()
+        };
+
+        // Act
+        let is_applied_with_bilinear =
+            switch_plan.is_applied(Some(TEST_MODE.resolution), &options(ScaleFilter::Bilinear));
+        let is_applied_with_nearest =
+            switch_plan.is_applied(Some(TEST_MODE.resolution), &options(ScaleFilter::Nearest));
+
+        // Assert
+        assert!(is_applied_with_bilinear);
+        assert!(!is_applied_with_nearest);
+    }
+
+    #[test]
+    fn plan_with_disallowed_current_refresh_rate_is_not_applied() {
+        // Arrange
+        let resolution = Resolution {
+            width: 3840,
+            height: 2160,
+        };
+        let mode = |refresh_rate_millihz, active| Mode {
+            resolution,
+            refresh_rate_millihz,
+            preferred: false,
+            active,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let tv = Output {
+            modes: vec![mode(60000, true), mode(24000, false)],
+            current_resolution: Some(resolution),
+            current_position: Some(Position::default()),
+            ..Output::test("HDMI-1", true, true)
+        };
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&tv],
+        };
+        let options = |blacklist: &[&str], whitelist: &[&str]| SwitchOptions {
+            mode_restrictions: ModeRestrictions {
+                blacklist: blacklist.iter().map(|mode| mode.parse().unwrap()).collect(),
+                whitelist: whitelist.iter().map(|mode| mode.parse().unwrap()).collect(),
+            },
+            ..Default::default()
+        };
+
+        // Act
+        let is_applied_locked_to_24hz =
+            switch_plan.is_applied(Some(resolution), &options(&[], &["HDMI-1=3840x2160@24"]));
+        let is_applied_blacklisting_60hz =
+            switch_plan.is_applied(Some(resolution), &options(&["HDMI-1=3840x2160@60"], &[]));
+        let is_applied_locked_to_60hz =
+            switch_plan.is_applied(Some(resolution), &options(&[], &["HDMI-1=3840x2160@60"]));
+
+        // Assert
+        assert!(!is_applied_locked_to_24hz);
+        assert!(!is_applied_blacklisting_60hz);
+        assert!(is_applied_locked_to_60hz);
+    }
+
+    #[test]
+    fn plan_matching_current_state_is_applied() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    current_resolution: Some(TEST_MODE.resolution),
+                    current_position: Some(Position::default()),
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&screen.outputs[0]],
+            outputs_to_enable: vec![&screen.outputs[1]],
+        };
+
+        // Act
+        let is_applied =
+            switch_plan.is_applied(Some(TEST_MODE.resolution), &SwitchOptions::default());
+
+        // Assert
+        assert!(is_applied);
+    }
+
+    #[test]
+    fn plan_with_other_resolution_is_not_applied() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    current_resolution: Some(TEST_MODE.resolution),
+                    current_position: Some(Position::default()),
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&screen.outputs[0]],
+            outputs_to_enable: vec![&screen.outputs[1]],
+        };
+        let resolution = Resolution {
+            width: 1280,
+            height: 720,
+        };
+
+        // Act
+        let is_applied = switch_plan.is_applied(Some(resolution), &SwitchOptions::default());
+
+        // Assert
+        assert!(!is_applied);
+    }
+
+    #[test]
+    fn plan_enabling_disabled_output_is_not_applied() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    current_resolution: Some(TEST_MODE.resolution),
+                    current_position: Some(Position::default()),
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: Vec::new(),
+            outputs_to_enable: vec![&screen.outputs[0], &screen.outputs[1]],
+        };
+
+        // Act
+        let is_applied =
+            switch_plan.is_applied(Some(TEST_MODE.resolution), &SwitchOptions::default());
+
+        // Assert
+        assert!(!is_applied);
+    }
+
+    #[test]
+    fn plan_with_unknown_resolution_or_color_adjustment_is_not_applied() {
+        // Arrange
+        let screen = Screen {
+            outputs: vec![
+                Output {
+                    modes: vec![TEST_MODE],
+                    ..Output::test("eDP-1", true, false)
+                },
+                Output {
+                    modes: vec![TEST_MODE],
+                    current_resolution: Some(TEST_MODE.resolution),
+                    current_position: Some(Position::default()),
+                    ..Output::test("HDMI-1", true, true)
+                },
+            ],
+        };
+        let switch_plan = SwitchPlan {
+            outputs_to_disable: vec![&screen.outputs[0]],
+            outputs_to_enable: vec![&screen.outputs[1]],
+        };
+        let options = SwitchOptions {
+            color_adjustment: ColorAdjustment {
+                gamma: None,
+                brightness: Some(0.5),
+            },
+            ..Default::default()
+        };
+
+        // Act & Assert
+        assert!(!switch_plan.is_applied(None, &SwitchOptions::default()));
+        assert!(!switch_plan.is_applied(Some(TEST_MODE.resolution), &options));
+    }
+
+    #[test]
+    fn best_resolution_matches_internal_aspect_ratio() {
+        // Arrange
+        let mode = |width, height| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name: &str, modes| Output {
+            modes,
+            ..Output::test(name, true, true)
+        };
+        let internal = output(
+            "eDP-1",
+            vec![mode(1920, 1200), mode(1920, 1080), mode(1680, 1050)],
+        );
+        let external = output("HDMI-1", vec![mode(1920, 1080), mode(1680, 1050)]);
+        let internal_resolution = Resolution {
+            width: 1920,
+            height: 1200,
+        };
+
+        // Act
+        let matching = choose_best_resolution(
+            &[&internal, &external],
+            &ResolutionConstraints {
+                aspect_ratio: Some(internal_resolution),
+                ..Default::default()
+            },
+        );
+        let largest =
+            choose_best_resolution(&[&internal, &external], &ResolutionConstraints::default());
+
+        // Assert
+        assert_eq!(
+            matching,
+            Ok(Resolution {
+                width: 1680,
+                height: 1050,
+            })
+        );
+        assert_eq!(
+            largest,
+            Ok(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+    }
+
+    #[test]
+    fn best_resolution_keeps_current_mode_when_preferred() {
+        // Arrange
+        let mode = |width, height, active| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name: &str, modes| Output {
+            modes,
+            ..Output::test(name, true, true)
+        };
+        let internal = output(
+            "eDP-1",
+            vec![mode(1920, 1080, false), mode(1280, 720, true)],
+        );
+        let external = output(
+            "HDMI-1",
+            vec![mode(1920, 1080, false), mode(1280, 720, true)],
+        );
+        let other_external = output("DP-1", vec![mode(1920, 1080, true), mode(1280, 720, false)]);
+        let choose = |outputs: &[&Output], prefer_current| {
+            choose_best_resolution(
+                outputs,
+                &ResolutionConstraints {
+                    prefer_current,
+                    ..Default::default()
+                },
+            )
+        };
+
+        // Act
+        let current = choose(&[&internal, &external], true);
+        let largest = choose(&[&internal, &external], false);
+        let disagreeing = choose(&[&internal, &other_external], true);
+
+        // Assert
+        let hd = Resolution {
+            width: 1280,
+            height: 720,
+        };
+        let full_hd = Resolution {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(current, Ok(hd));
+        assert_eq!(largest, Ok(full_hd));
+        assert_eq!(disagreeing, Ok(full_hd));
+    }
+
+    #[test]
+    fn refresh_optimized_mirror_prefers_higher_common_refresh_rate() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name: &str, modes| Output {
+            modes,
+            ..Output::test(name, true, true)
+        };
+        let internal = output(
+            "eDP-1",
+            vec![mode(3840, 2160, 60000), mode(1920, 1080, 60000)],
+        );
+        let external = output(
+            "HDMI-1",
+            vec![mode(3840, 2160, 30000), mode(1920, 1080, 59940)],
+        );
+
+        // Act
+        let refresh = choose_best_resolution(
+            &[&internal, &external],
+            &ResolutionConstraints {
+                optimize: MirrorOptimize::Refresh,
+                ..Default::default()
+            },
+        );
+        let area =
+            choose_best_resolution(&[&internal, &external], &ResolutionConstraints::default());
+
+        // Assert
+        assert_eq!(
+            refresh,
+            Ok(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+        assert_eq!(
+            area,
+            Ok(Resolution {
+                width: 3840,
+                height: 2160,
+            })
+        );
+    }
 
     #[test]
-    fn when_no_outputs_nothing_must_be_switched() {
+    fn refresh_optimized_mirror_leaves_current_mode_of_lower_refresh_rate() {
         // Arrange
-        let screen = Screen {
-            outputs: Vec::new(),
+        let mode = |width, height, refresh_rate_millihz, active| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name: &str, modes| Output {
+            modes,
+            ..Output::test(name, true, true)
         };
+        let internal = output(
+            "eDP-1",
+            vec![
+                mode(3840, 2160, 60000, true),
+                mode(1920, 1080, 60000, false),
+            ],
+        );
+        let external = output(
+            "HDMI-1",
+            vec![
+                mode(3840, 2160, 30000, true),
+                mode(1920, 1080, 60000, false),
+            ],
+        );
 
         // Act
-        let switch_plan = build_switch_plan(&screen);
+        let best_resolution = choose_best_resolution(
+            &[&internal, &external],
+            &ResolutionConstraints {
+                optimize: MirrorOptimize::Refresh,
+                prefer_current: true,
+                ..Default::default()
+            },
+        );
 
         // Assert
-        assert!(switch_plan.outputs_to_disable.is_empty());
-        assert!(switch_plan.outputs_to_enable.is_empty());
+        assert_eq!(
+            best_resolution,
+            Ok(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
     }
 
     #[test]
-    fn when_nothing_is_enabled_must_enable_internal() {
+    fn best_resolution_falls_back_without_matching_aspect_ratio() {
         // Arrange
-        let screen = Screen {
-            outputs: vec![
-                Output {
-                    name: "eDP-1".to_string(),
-                    connected: true,
-                    enabled: false,
-                    modes: vec![TEST_MODE],
-                    location: Location::Internal,
-                },
-                Output {
-                    name: "HDMI-1".to_string(),
-                    connected: true,
-                    enabled: false,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
+        let output = Output {
+            modes: vec![Mode {
+                resolution: Resolution {
+                    width: 1920,
+                    height: 1080,
                 },
-            ],
+                refresh_rate_millihz: 60000,
+                preferred: false,
+                active: false,
+                refresh_rates_millihz: Vec::new(),
+            }],
+            ..Output::test("HDMI-1", true, true)
         };
 
         // Act
-        let switch_plan = build_switch_plan(&screen);
+        let best_resolution = choose_best_resolution(
+            &[&output],
+            &ResolutionConstraints {
+                aspect_ratio: Some(Resolution {
+                    width: 1920,
+                    height: 1200,
+                }),
+                ..Default::default()
+            },
+        );
 
         // Assert
-        assert!(switch_plan.outputs_to_disable.is_empty());
-        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[0]]);
+        assert_eq!(
+            best_resolution,
+            Ok(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
     }
 
     #[test]
-    fn when_internal_is_enabled_must_disable_disconnected_and_enable_internal_and_external() {
+    fn owned_switch_plan_outlives_the_screen() {
         // Arrange
-        let screen = Screen {
-            outputs: vec![
-                Output {
-                    name: "eDP-1".to_string(),
-                    connected: true,
-                    enabled: true,
-                    modes: vec![TEST_MODE],
-                    location: Location::Internal,
-                },
-                Output {
-                    name: "HDMI-1".to_string(),
-                    connected: true,
-                    enabled: false,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-                Output {
-                    name: "HDMI-2".to_string(),
-                    connected: false,
-                    enabled: true,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-                Output {
-                    name: "DP-1".to_string(),
-                    connected: false,
-                    enabled: false,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-            ],
+        let owned_switch_plan = {
+            let screen = overrides_test_screen();
+            let switch_plan = build_switch_plan(&screen, false);
+            OwnedSwitchPlan::from(&switch_plan)
         };
+        let screen = overrides_test_screen();
+        let mut other_screen = overrides_test_screen();
+        other_screen.outputs[0].enabled = false;
 
         // Act
-        let switch_plan = build_switch_plan(&screen);
+        let same = OwnedSwitchPlan::from(&build_switch_plan(&screen, false));
+        let other = OwnedSwitchPlan::from(&build_switch_plan(&other_screen, false));
 
         // Assert
-        assert_eq_ref(&switch_plan.outputs_to_disable, &[&screen.outputs[2]]);
-        assert_eq_ref(
-            &switch_plan.outputs_to_enable,
-            &[&screen.outputs[0], &screen.outputs[1]],
+        assert_eq!(
+            owned_switch_plan.outputs_to_enable,
+            [screen.outputs[1].clone(), screen.outputs[2].clone()]
         );
+        assert_eq!(owned_switch_plan, same);
+        assert_ne!(owned_switch_plan, other);
     }
 
     #[test]
-    fn when_internal_and_external_are_enabled_must_disable_internal_and_disconnected_and_enable_external()
-     {
+    #[cfg(feature = "xrandr")]
+    fn mirror_scale_shows_the_logical_area() {
         // Arrange
-        let screen = Screen {
-            outputs: vec![
-                Output {
-                    name: "eDP-1".to_string(),
-                    connected: true,
-                    enabled: true,
-                    modes: vec![TEST_MODE],
-                    location: Location::Internal,
-                },
-                Output {
-                    name: "HDMI-1".to_string(),
-                    connected: true,
-                    enabled: true,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-                Output {
-                    name: "HDMI-2".to_string(),
-                    connected: false,
-                    enabled: true,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-                Output {
-                    name: "DP-1".to_string(),
-                    connected: false,
-                    enabled: false,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-            ],
-        };
+        let resolution = |width, height| Resolution { width, height };
 
         // Act
-        let switch_plan = build_switch_plan(&screen);
+        let uhd_to_full_hd = mirror_scale(resolution(3840, 2160), resolution(1920, 1080));
+        let same = mirror_scale(resolution(1920, 1080), resolution(1920, 1080));
+        let wider_to_full_hd = mirror_scale(resolution(2560, 1080), resolution(1920, 1080));
 
         // Assert
-        assert_eq_ref(
-            &switch_plan.outputs_to_disable,
-            &[&screen.outputs[0], &screen.outputs[2]],
-        );
-        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[1]]);
+        assert_eq!(uhd_to_full_hd, 0.5);
+        assert_eq!(same, 1.0);
+        assert_eq!(wider_to_full_hd, 1.0);
     }
 
     #[test]
-    fn when_external_is_enabled_must_disable_external_and_disconnected_and_enable_internal() {
+    fn best_resolution_for_no_outputs() {
         // Arrange
-        let screen = Screen {
-            outputs: vec![
-                Output {
-                    name: "eDP-1".to_string(),
-                    connected: true,
-                    enabled: false,
-                    modes: vec![TEST_MODE],
-                    location: Location::Internal,
-                },
-                Output {
-                    name: "eDP-2".to_string(),
-                    connected: false,
-                    enabled: true,
-                    modes: vec![TEST_MODE],
-                    location: Location::Internal,
-                },
-                Output {
-                    name: "HDMI-1".to_string(),
-                    connected: true,
-                    enabled: true,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-                Output {
-                    name: "HDMI-2".to_string(),
-                    connected: false,
-                    enabled: true,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-                Output {
-                    name: "DP-1".to_string(),
-                    connected: false,
-                    enabled: false,
-                    modes: vec![TEST_MODE],
-                    location: Location::External,
-                },
-            ],
-        };
+        let outputs = [];
 
         // Act
-        let switch_plan = build_switch_plan(&screen);
+        let best_resolution = choose_best_resolution(&outputs, &ResolutionConstraints::default());
 
         // Assert
-        assert_eq_ref(
-            &switch_plan.outputs_to_disable,
-            &[&screen.outputs[1], &screen.outputs[2], &screen.outputs[3]],
-        );
-        assert_eq_ref(&switch_plan.outputs_to_enable, &[&screen.outputs[0]]);
+        assert_eq!(best_resolution, Err(ChooseResolutionError::NoOutputs));
     }
 
     #[test]
-    fn best_resolution_for_no_outputs() {
+    fn best_resolution_for_output_without_modes() {
         // Arrange
-        let outputs = [];
+        let outputs = [&Output::test("HDMI-1", true, false)];
 
         // Act
-        let best_resolution = choose_best_resolution(&outputs, None);
+        let best_resolution = choose_best_resolution(&outputs, &ResolutionConstraints::default());
 
         // Assert
-        assert!(best_resolution.is_none());
+        assert_eq!(
+            best_resolution,
+            Err(ChooseResolutionError::NoModesOnOutput("HDMI-1".to_string()))
+        );
     }
 
     #[test]
     fn best_resolution_for_single_output() {
         // Arrange
         let outputs = [&Output {
-            name: "eDP-1".to_string(),
-            connected: true,
-            enabled: false,
             modes: vec![
                 Mode {
                     resolution: Resolution {
@@ -314,6 +2288,9 @@ mod tests {
                         height: 1080,
                     },
                     refresh_rate_millihz: 60000,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
                 Mode {
                     resolution: Resolution {
@@ -321,18 +2298,21 @@ mod tests {
                         height: 480,
                     },
                     refresh_rate_millihz: 60000,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 },
             ],
-            location: Location::Internal,
+            ..Output::test("eDP-1", true, false)
         }];
 
         // Act
-        let best_resolution = choose_best_resolution(&outputs, None);
+        let best_resolution = choose_best_resolution(&outputs, &ResolutionConstraints::default());
 
         // Assert
         assert_eq!(
             best_resolution,
-            Some(Resolution {
+            Ok(Resolution {
                 width: 1920,
                 height: 1080,
             })
@@ -344,9 +2324,6 @@ mod tests {
         // Arrange
         let outputs = [
             &Output {
-                name: "eDP-1".to_string(),
-                connected: true,
-                enabled: false,
                 modes: vec![
                     Mode {
                         resolution: Resolution {
@@ -354,6 +2331,9 @@ mod tests {
                             height: 1080,
                         },
                         refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                     Mode {
                         resolution: Resolution {
@@ -361,6 +2341,9 @@ mod tests {
                             height: 600,
                         },
                         refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                     Mode {
                         resolution: Resolution {
@@ -368,14 +2351,14 @@ mod tests {
                             height: 480,
                         },
                         refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                 ],
-                location: Location::Internal,
+                ..Output::test("eDP-1", true, false)
             },
             &Output {
-                name: "HDMI-1".to_string(),
-                connected: true,
-                enabled: false,
                 modes: vec![
                     Mode {
                         resolution: Resolution {
@@ -383,6 +2366,9 @@ mod tests {
                             height: 600,
                         },
                         refresh_rate_millihz: 30000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                     Mode {
                         resolution: Resolution {
@@ -390,19 +2376,23 @@ mod tests {
                             height: 480,
                         },
                         refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                 ],
                 location: Location::Internal,
+                ..Output::test("HDMI-1", true, false)
             },
         ];
 
         // Act
-        let best_resolution = choose_best_resolution(&outputs, None);
+        let best_resolution = choose_best_resolution(&outputs, &ResolutionConstraints::default());
 
         // Assert
         assert_eq!(
             best_resolution,
-            Some(Resolution {
+            Ok(Resolution {
                 width: 800,
                 height: 600,
             })
@@ -414,9 +2404,6 @@ mod tests {
         // Arrange
         let outputs = [
             &Output {
-                name: "eDP-1".to_string(),
-                connected: true,
-                enabled: false,
                 modes: vec![
                     Mode {
                         resolution: Resolution {
@@ -424,6 +2411,9 @@ mod tests {
                             height: 1080,
                         },
                         refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                     Mode {
                         resolution: Resolution {
@@ -431,6 +2421,9 @@ mod tests {
                             height: 600,
                         },
                         refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                     Mode {
                         resolution: Resolution {
@@ -438,14 +2431,14 @@ mod tests {
                             height: 480,
                         },
                         refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                 ],
-                location: Location::Internal,
+                ..Output::test("eDP-1", true, false)
             },
             &Output {
-                name: "HDMI-1".to_string(),
-                connected: true,
-                enabled: false,
                 modes: vec![
                     Mode {
                         resolution: Resolution {
@@ -453,6 +2446,9 @@ mod tests {
                             height: 600,
                         },
                         refresh_rate_millihz: 30000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                     Mode {
                         resolution: Resolution {
@@ -460,62 +2456,375 @@ mod tests {
                             height: 480,
                         },
                         refresh_rate_millihz: 60000,
+                        preferred: false,
+                        active: false,
+                        refresh_rates_millihz: Vec::new(),
                     },
                 ],
                 location: Location::Internal,
+                ..Output::test("HDMI-1", true, false)
             },
         ];
 
         // Act
-        let best_resolution = choose_best_resolution(&outputs, Some(50000));
+        let best_resolution = choose_best_resolution(
+            &outputs,
+            &ResolutionConstraints {
+                min_refresh_rate: Some(50000),
+                ..Default::default()
+            },
+        );
 
         // Assert
         assert_eq!(
             best_resolution,
-            Some(Resolution {
+            Ok(Resolution {
                 width: 640,
                 height: 480,
             })
         );
     }
 
+    #[test]
+    fn min_refresh_rate_is_compared_in_whole_hertz() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = Output {
+            modes: vec![mode(3840, 2160, 30000), mode(1920, 1080, 59940)],
+            ..Output::test("HDMI-1", true, false)
+        };
+
+        // Act
+        let best_resolution = choose_best_resolution(
+            &[&output],
+            &ResolutionConstraints {
+                min_refresh_rate: Some(60000),
+                ..Default::default()
+            },
+        );
+
+        // Assert
+        assert_eq!(
+            best_resolution,
+            Ok(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+    }
+
+    #[test]
+    fn best_resolution_with_min_resolution_and_min_refresh_rate() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name| Output {
+            modes: vec![
+                mode(1920, 1080, 30000),
+                mode(1280, 720, 60000),
+                mode(800, 600, 60000),
+            ],
+            ..Output::test(name, true, false)
+        };
+        let edp = output("eDP-1");
+        let hdmi = output("HDMI-1");
+        let outputs = [&edp, &hdmi];
+        let min_resolution = Resolution {
+            width: 1024,
+            height: 768,
+        };
+
+        // Act
+        let best_resolution = choose_best_resolution(
+            &outputs,
+            &ResolutionConstraints {
+                min_refresh_rate: Some(50000),
+                min_resolution: Some(min_resolution),
+                ..Default::default()
+            },
+        );
+
+        // Assert
+        assert_eq!(
+            best_resolution,
+            Ok(Resolution {
+                width: 1280,
+                height: 720,
+            })
+        );
+    }
+
+    #[test]
+    fn min_resolution_eliminates_all_resolutions() {
+        // Arrange
+        let mode = |width, height, refresh_rate_millihz| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name| Output {
+            modes: vec![
+                mode(1920, 1080, 30000),
+                mode(1280, 720, 60000),
+                mode(800, 600, 60000),
+            ],
+            ..Output::test(name, true, false)
+        };
+        let edp = output("eDP-1");
+        let hdmi = output("HDMI-1");
+        let outputs = [&edp, &hdmi];
+        let min_resolution = Resolution {
+            width: 2560,
+            height: 1440,
+        };
+
+        // Act
+        let best_resolution = choose_best_resolution(
+            &outputs,
+            &ResolutionConstraints {
+                min_resolution: Some(min_resolution),
+                ..Default::default()
+            },
+        );
+
+        // Assert
+        assert_eq!(
+            best_resolution,
+            Err(ChooseResolutionError::NoResolutionMeetsConstraints)
+        );
+    }
+
+    #[test]
+    fn closest_to_target_does_not_exceed_it() {
+        // Arrange
+        let mode = |width, height| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let output = |name| Output {
+            modes: vec![mode(3840, 2160), mode(1920, 1080)],
+            ..Output::test(name, true, true)
+        };
+        let edp = output("eDP-1");
+        let hdmi = output("HDMI-1");
+        let outputs = [&edp, &hdmi];
+        let target = Resolution {
+            width: 2560,
+            height: 1440,
+        };
+
+        // Act
+        let best_resolution = choose_best_resolution(
+            &outputs,
+            &ResolutionConstraints {
+                closest_to: Some(target),
+                ..Default::default()
+            },
+        );
+
+        // Assert
+        assert_eq!(
+            best_resolution,
+            Ok(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+    }
+
+    #[test]
+    fn closest_to_target_exceeds_it_when_nothing_is_smaller() {
+        // Arrange
+        let mode = |width, height| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let edp = Output {
+            modes: vec![mode(3840, 2160), mode(1920, 1080)],
+            ..Output::test("eDP-1", true, true)
+        };
+        let outputs = [&edp];
+        let target = Resolution {
+            width: 1280,
+            height: 720,
+        };
+
+        // Act
+        let best_resolution = choose_best_resolution(
+            &outputs,
+            &ResolutionConstraints {
+                closest_to: Some(target),
+                ..Default::default()
+            },
+        );
+
+        // Assert
+        assert_eq!(
+            best_resolution,
+            Ok(Resolution {
+                width: 1920,
+                height: 1080,
+            })
+        );
+    }
+
+    #[test]
+    fn min_dpi_eliminates_resolutions_too_coarse_on_outputs_of_known_size() {
+        // Arrange
+        let mode = |width, height| Mode {
+            resolution: Resolution { width, height },
+            refresh_rate_millihz: 60000,
+            preferred: false,
+            active: false,
+            refresh_rates_millihz: Vec::new(),
+        };
+        let edp = Output {
+            modes: vec![mode(3840, 2160), mode(1920, 1080)],
+            physical_size_mm: Some((344, 194)),
+            ..Output::test("eDP-1", true, true)
+        };
+        let hdmi = Output {
+            modes: vec![mode(3840, 2160), mode(1920, 1080)],
+            ..Output::test("HDMI-1", true, true)
+        };
+        let outputs = [&edp, &hdmi];
+        let target = Resolution {
+            width: 1920,
+            height: 1080,
+        };
+
+        // Act
+        let best_resolution = choose_best_resolution(
+            &outputs,
+            &ResolutionConstraints {
+                closest_to: Some(target),
+                min_dpi: Some(200.0),
+                ..Default::default()
+            },
+        );
+        let no_resolution = choose_best_resolution(
+            &outputs,
+            &ResolutionConstraints {
+                min_dpi: Some(300.0),
+                ..Default::default()
+            },
+        );
+
+        // Assert
+        assert_eq!(
+            best_resolution,
+            Ok(Resolution {
+                width: 3840,
+                height: 2160,
+            })
+        );
+        assert_eq!(
+            no_resolution,
+            Err(ChooseResolutionError::NoResolutionMeetsConstraints)
+        );
+    }
+
     #[test]
     fn no_common_resolution() {
         // Arrange
         let outputs = [
             &Output {
-                name: "eDP-1".to_string(),
-                connected: true,
-                enabled: false,
                 modes: vec![Mode {
                     resolution: Resolution {
                         width: 1920,
                         height: 1080,
                     },
                     refresh_rate_millihz: 60000,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 }],
-                location: Location::Internal,
+                ..Output::test("eDP-1", true, false)
             },
             &Output {
-                name: "HDMI-1".to_string(),
-                connected: true,
-                enabled: false,
                 modes: vec![Mode {
                     resolution: Resolution {
                         width: 800,
                         height: 600,
                     },
                     refresh_rate_millihz: 60000,
+                    preferred: false,
+                    active: false,
+                    refresh_rates_millihz: Vec::new(),
                 }],
                 location: Location::Internal,
+                ..Output::test("HDMI-1", true, false)
             },
         ];
 
         // Act
-        let best_resolution = choose_best_resolution(&outputs, None);
+        let best_resolution = choose_best_resolution(&outputs, &ResolutionConstraints::default());
+
+        // Assert
+        assert_eq!(
+            best_resolution,
+            Err(ChooseResolutionError::NoCommonResolution {
+                per_output: vec![
+                    (
+                        "eDP-1".to_string(),
+                        vec![Resolution {
+                            width: 1920,
+                            height: 1080,
+                        }]
+                    ),
+                    (
+                        "HDMI-1".to_string(),
+                        vec![Resolution {
+                            width: 800,
+                            height: 600,
+                        }]
+                    ),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn no_common_resolution_error_lists_resolutions_per_output() {
+        // Arrange
+        let resolution = |width, height| Resolution { width, height };
+        let err = ChooseResolutionError::NoCommonResolution {
+            per_output: vec![
+                (
+                    "eDP-1".to_string(),
+                    vec![resolution(1920, 1080), resolution(1280, 720)],
+                ),
+                ("HDMI-1".to_string(), vec![resolution(3840, 2160)]),
+            ],
+        };
+
+        // Act
+        let message = err.to_string();
 
         // Assert
-        assert!(best_resolution.is_none());
+        assert_eq!(
+            message,
+            "no common resolution: eDP-1 supports [1920x1080, 1280x720], HDMI-1 supports [3840x2160]"
+        );
     }
 
     fn assert_eq_ref<T>(a: &[&T], b: &[&T])
@@ -539,5 +2848,8 @@ mod tests {
             height: 1080,
         },
         refresh_rate_millihz: 60000,
+        preferred: false,
+        active: false,
+        refresh_rates_millihz: Vec::new(),
     };
 }
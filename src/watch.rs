@@ -0,0 +1,622 @@
+use crate::exit_code::{self, Outcome};
+use crate::screen::Screen;
+use crate::screen_controller::{ScreenController, ScreenControllerType, SwitchError};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long an in-progress switch may take after a termination request before the process exits anyway.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Granularity of checking for signals while waiting for the next poll.
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest wait between attempts to reach the controller again, e.g. while the X server restarts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// How many times in a row the controller may fail to report the outputs before giving up.
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Polls the outputs and calls `switch` every time a display gets connected or disconnected,
+/// passing the outputs that got connected or disconnected. Outputs enabled or disabled
+/// by hand are left as they are.
+/// Disconnects are only acted on once they persist for `disconnect_delay`.
+/// `SIGHUP` makes it restore the outputs it switched to last right away, e.g. after they got
+/// reconfigured by hand, which `switch` is told by its last argument.
+/// When the controller fails, e.g. because the X server restarted, it is retried with backoff.
+/// Returns after receiving `SIGTERM` or `SIGINT`, when the controller keeps failing, or right
+/// away if the signal handlers cannot be registered.
+/// Polling and switching run on a thread of their own, so that a switch not finishing within
+/// [`SHUTDOWN_TIMEOUT`] of a termination request does not keep the caller from returning.
+pub(super) fn watch(
+    controller_type: ScreenControllerType,
+    poll_interval: Duration,
+    disconnect_delay: Duration,
+    switch: impl FnMut(&mut ScreenController, &Hotplug, bool) + Send + 'static,
+) -> Outcome {
+    let signal_flags = match SignalFlags::register() {
+        Ok(signal_flags) => signal_flags,
+        Err(err) => {
+            log::error!("Unable to register signal handlers: {err}");
+            return Outcome::ControllerFailed;
+        }
+    };
+    let (outcome_sender, outcome_receiver) = mpsc::channel();
+    thread::spawn({
+        let signal_flags = signal_flags.clone();
+        move || {
+            let outcome = watch_outputs(
+                &signal_flags,
+                controller_type,
+                poll_interval,
+                disconnect_delay,
+                switch,
+            );
+            // The receiver is gone only if the caller gave up waiting already.
+            let _ = outcome_sender.send(outcome);
+        }
+    });
+
+    loop {
+        match outcome_receiver.recv_timeout(SIGNAL_CHECK_INTERVAL) {
+            Ok(outcome) => return outcome,
+            // Watching panicked, which was reported already.
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Outcome::ControllerFailed,
+            Err(mpsc::RecvTimeoutError::Timeout)
+                if signal_flags.terminate.load(Ordering::SeqCst) =>
+            {
+                return outcome_receiver
+                    .recv_timeout(SHUTDOWN_TIMEOUT)
+                    .unwrap_or_else(|_| {
+                        log::error!("Switch did not finish in {SHUTDOWN_TIMEOUT:?}, exiting");
+                        Outcome::ControllerFailed
+                    });
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
+
+/// The polling loop of [`watch`], returning once `signal_flags` requests termination.
+fn watch_outputs(
+    signal_flags: &SignalFlags,
+    controller_type: ScreenControllerType,
+    poll_interval: Duration,
+    disconnect_delay: Duration,
+    mut switch: impl FnMut(&mut ScreenController, &Hotplug, bool),
+) -> Outcome {
+    let mut last_screen: Option<Screen> = None;
+    let mut disconnect_debouncer = DisconnectDebouncer::new(disconnect_delay);
+    let mut reconnect_backoff = ReconnectBackoff::new(poll_interval);
+    let mut screen_controller = None;
+
+    loop {
+        if signal_flags.terminate.load(Ordering::SeqCst) {
+            log::info!("Terminating");
+            return Outcome::Applied;
+        }
+        // All options come from the command line and environment, so there is no configuration
+        // to reload, only the outputs to re-evaluate.
+        let reevaluate = signal_flags.reload.swap(false, Ordering::SeqCst);
+        if reevaluate {
+            log::info!("Received SIGHUP, re-evaluating the outputs");
+        }
+
+        let screen = match poll(&mut screen_controller, controller_type) {
+            Ok(polled) => {
+                reconnect_backoff.reset();
+                polled
+            }
+            Err(err) if err.is_transient() => match reconnect_backoff.next_delay() {
+                Some(delay) => {
+                    log::warn!("Unable to get the outputs: {err}, retrying in {delay:?}");
+                    signal_flags.sleep(delay);
+                    continue;
+                }
+                None => {
+                    log::error!(
+                        "Unable to get the outputs {MAX_RECONNECT_ATTEMPTS} times in a row, giving up"
+                    );
+                    return exit_code::report_switch_error(err, Outcome::NoController);
+                }
+            },
+            // Retrying does not help when e.g. the controller is not installed.
+            Err(err) => return exit_code::report_switch_error(err, Outcome::NoController),
+        };
+
+        let changed = last_screen
+            .as_ref()
+            .is_some_and(|last_screen| !last_screen.connectivity_eq(&screen));
+        if changed || reevaluate {
+            if reevaluate {
+                disconnect_debouncer.reset();
+            } else if let Some(last_screen) = &last_screen
+                && !disconnect_debouncer.should_switch(last_screen, &screen, Instant::now())
+            {
+                // Keep comparing against the last acted on state until the disconnect settles.
+                signal_flags.sleep(poll_interval);
+                continue;
+            }
+
+            let hotplug = last_screen
+                .as_ref()
+                .map(|last_screen| Hotplug::between(last_screen, &screen))
+                .unwrap_or_default();
+            if changed {
+                log::info!("Outputs changed, switching");
+            }
+            log::debug!(
+                "last_screen = {last_screen:?}, screen = {screen:?}, hotplug = {hotplug:?}"
+            );
+            // A hotplug at the same time is switched for as usual.
+            switch(
+                screen_controller
+                    .as_mut()
+                    .expect("controller is kept after polling it"),
+                &hotplug,
+                reevaluate && !changed,
+            );
+        } else {
+            disconnect_debouncer.reset();
+        }
+        last_screen = Some(screen);
+
+        signal_flags.sleep(poll_interval);
+    }
+}
+
+/// Gets the outputs from the controller, connecting to it first unless it is still connected.
+/// A failing controller is dropped, so that the next poll reconnects, e.g. to a restarted X server.
+fn poll(
+    screen_controller: &mut Option<ScreenController>,
+    controller_type: ScreenControllerType,
+) -> Result<Screen, SwitchError> {
+    let controller = match screen_controller.take() {
+        Some(mut controller) => {
+            controller.refresh()?;
+            controller
+        }
+        None => ScreenController::new(controller_type)?,
+    };
+    let screen = controller.get_outputs()?;
+    *screen_controller = Some(controller);
+    Ok(screen)
+}
+
+/// Delays between attempts to reach a failing controller, doubling up to [`MAX_RECONNECT_DELAY`].
+struct ReconnectBackoff {
+    initial_delay: Duration,
+    failures: u32,
+}
+
+impl ReconnectBackoff {
+    fn new(initial_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            failures: 0,
+        }
+    }
+
+    /// Records a failure, returning how long to wait before retrying, or `None` to give up.
+    fn next_delay(&mut self) -> Option<Duration> {
+        self.failures += 1;
+        if self.failures >= MAX_RECONNECT_ATTEMPTS {
+            return None;
+        }
+        let delay = self
+            .initial_delay
+            .saturating_mul(1 << (self.failures - 1).min(16));
+        Some(delay.min(MAX_RECONNECT_DELAY))
+    }
+
+    fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// Names of the outputs that got connected or disconnected between two polls.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(super) struct Hotplug<'a> {
+    pub(super) connected: Vec<&'a str>,
+    pub(super) disconnected: Vec<&'a str>,
+}
+
+impl<'a> Hotplug<'a> {
+    fn between(last_screen: &'a Screen, screen: &'a Screen) -> Self {
+        // Outputs missing from a screen altogether, e.g. of an undocked dock, count as disconnected.
+        let newly_connected = |from: &'a Screen, to: &'a Screen| {
+            to.connected_outputs()
+                .filter(|output| {
+                    !from
+                        .find_output_by_name(&output.name)
+                        .is_some_and(|from_output| from_output.connected)
+                })
+                .map(|output| output.name.as_str())
+                .collect()
+        };
+        Self {
+            connected: newly_connected(last_screen, screen),
+            disconnected: newly_connected(screen, last_screen),
+        }
+    }
+
+    /// Name of an output that got connected, the one to prefer when switching.
+    pub(super) fn trigger(&self) -> Option<&'a str> {
+        self.connected.first().copied()
+    }
+}
+
+/// Polls the outputs until the named one is connected, e.g. until the monitor has sent its EDID
+/// at boot, or until `timeout` elapses. Returns whether the output got connected.
+pub(super) fn wait_for_output<E>(
+    mut get_outputs: impl FnMut() -> Result<Screen, E>,
+    name: &str,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<bool, E> {
+    let start = Instant::now();
+    loop {
+        let screen = get_outputs()?;
+        if screen
+            .find_output_by_name(name)
+            .is_some_and(|output| output.connected)
+        {
+            log::debug!("Output {name} is connected after {:?}", start.elapsed());
+            return Ok(true);
+        }
+        if start.elapsed() >= timeout {
+            return Ok(false);
+        }
+        thread::sleep(poll_interval);
+    }
+}
+
+#[derive(Clone)]
+struct SignalFlags {
+    terminate: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+}
+
+impl SignalFlags {
+    fn register() -> io::Result<Self> {
+        let terminate = Arc::new(AtomicBool::new(false));
+        let reload = Arc::new(AtomicBool::new(false));
+
+        let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP])?;
+        thread::spawn({
+            let terminate = Arc::clone(&terminate);
+            let reload = Arc::clone(&reload);
+            move || {
+                for signal in signals.forever() {
+                    if signal == SIGHUP {
+                        reload.store(true, Ordering::SeqCst);
+                    } else {
+                        log::debug!("Received signal {signal}, terminating");
+                        terminate.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+        });
+
+        Ok(Self { terminate, reload })
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut remaining = duration;
+        while !remaining.is_zero() && !self.terminate.load(Ordering::SeqCst) {
+            let step = remaining.min(SIGNAL_CHECK_INTERVAL);
+            thread::sleep(step);
+            remaining -= step;
+        }
+    }
+}
+
+/// Tracks outputs that got disconnected since the last switch, so that a switch only happens
+/// once they stay disconnected for the configured delay.
+struct DisconnectDebouncer {
+    delay: Duration,
+    pending_disconnects: HashMap<String, Instant>,
+}
+
+impl DisconnectDebouncer {
+    fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            pending_disconnects: HashMap::new(),
+        }
+    }
+
+    /// Decides whether the change from `last_screen` to `screen` should be acted on at `now`.
+    fn should_switch(&mut self, last_screen: &Screen, screen: &Screen, now: Instant) -> bool {
+        let mut disconnected = Vec::new();
+        let mut other_change = false;
+
+        let names: BTreeSet<_> = last_screen
+            .outputs
+            .iter()
+            .chain(&screen.outputs)
+            .map(|output| output.name.as_str())
+            .collect();
+        for name in names {
+            let is_connected = |screen: &Screen| {
+                screen
+                    .find_output_by_name(name)
+                    .is_some_and(|output| output.connected)
+            };
+            let was_connected = is_connected(last_screen);
+            let connected = is_connected(screen);
+
+            if was_connected == connected {
+                continue;
+            }
+            if was_connected {
+                disconnected.push(name);
+            } else {
+                other_change = true;
+            }
+        }
+
+        self.pending_disconnects
+            .retain(|name, _| disconnected.contains(&name.as_str()));
+        for name in disconnected {
+            self.pending_disconnects
+                .entry(name.to_string())
+                .or_insert_with(|| {
+                    log::info!("Output {name} disconnected, waiting {:?}", self.delay);
+                    now
+                });
+        }
+
+        let disconnect_settled = self
+            .pending_disconnects
+            .values()
+            .any(|since| now.duration_since(*since) >= self.delay);
+        if other_change || disconnect_settled {
+            self.reset();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pending_disconnects.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::Output;
+    use clap::ValueEnum;
+
+    const DELAY: Duration = Duration::from_secs(5);
+
+    /// A laptop panel with an HDMI output, both enabled, the latter plugged in or not.
+    fn laptop_with_hdmi_screen(hdmi_connected: bool) -> Screen {
+        Screen {
+            outputs: vec![
+                Output::test("eDP-1", true, true),
+                Output::test("HDMI-1", hdmi_connected, true),
+            ],
+        }
+    }
+
+    #[test]
+    fn connect_is_acted_on_immediately() {
+        // Arrange
+        let mut debouncer = DisconnectDebouncer::new(DELAY);
+        let now = Instant::now();
+
+        // Act
+        let should_switch = debouncer.should_switch(
+            &laptop_with_hdmi_screen(false),
+            &laptop_with_hdmi_screen(true),
+            now,
+        );
+
+        // Assert
+        assert!(should_switch);
+    }
+
+    #[test]
+    fn disconnect_is_acted_on_after_delay() {
+        // Arrange
+        let mut debouncer = DisconnectDebouncer::new(DELAY);
+        let connected = laptop_with_hdmi_screen(true);
+        let disconnected = laptop_with_hdmi_screen(false);
+        let now = Instant::now();
+
+        // Act
+        let immediately = debouncer.should_switch(&connected, &disconnected, now);
+        let before_delay =
+            debouncer.should_switch(&connected, &disconnected, now + Duration::from_secs(4));
+        let after_delay = debouncer.should_switch(&connected, &disconnected, now + DELAY);
+
+        // Assert
+        assert!(!immediately);
+        assert!(!before_delay);
+        assert!(after_delay);
+    }
+
+    #[test]
+    fn reconnect_within_delay_cancels_disconnect() {
+        // Arrange
+        let mut debouncer = DisconnectDebouncer::new(DELAY);
+        let connected = laptop_with_hdmi_screen(true);
+        let disconnected = laptop_with_hdmi_screen(false);
+        let now = Instant::now();
+
+        // Act
+        let on_disconnect = debouncer.should_switch(&connected, &disconnected, now);
+        // The watch loop sees no change against the last state once the output is back.
+        debouncer.reset();
+        let on_second_disconnect =
+            debouncer.should_switch(&connected, &disconnected, now + Duration::from_secs(4));
+        let after_first_delay =
+            debouncer.should_switch(&connected, &disconnected, now + Duration::from_secs(6));
+
+        // Assert
+        assert!(!on_disconnect);
+        assert!(!on_second_disconnect);
+        assert!(!after_first_delay);
+    }
+
+    #[test]
+    fn zero_delay_acts_on_disconnect_immediately() {
+        // Arrange
+        let mut debouncer = DisconnectDebouncer::new(Duration::ZERO);
+
+        // Act
+        let should_switch = debouncer.should_switch(
+            &laptop_with_hdmi_screen(true),
+            &laptop_with_hdmi_screen(false),
+            Instant::now(),
+        );
+
+        // Assert
+        assert!(should_switch);
+    }
+
+    #[test]
+    fn hotplug_tells_connects_from_disconnects() {
+        // Arrange
+        let disconnected = laptop_with_hdmi_screen(false);
+        let connected = laptop_with_hdmi_screen(true);
+
+        // Act
+        let on_connect = Hotplug::between(&disconnected, &connected);
+        let on_disconnect = Hotplug::between(&connected, &disconnected);
+        let unchanged = Hotplug::between(&connected, &connected);
+
+        // Assert
+        assert_eq!(
+            on_connect,
+            Hotplug {
+                connected: vec!["HDMI-1"],
+                disconnected: Vec::new(),
+            }
+        );
+        assert_eq!(on_connect.trigger(), Some("HDMI-1"));
+        assert_eq!(
+            on_disconnect,
+            Hotplug {
+                connected: Vec::new(),
+                disconnected: vec!["HDMI-1"],
+            }
+        );
+        assert_eq!(on_disconnect.trigger(), None);
+        assert_eq!(unchanged, Hotplug::default());
+    }
+
+    #[test]
+    fn wait_for_output_polls_until_it_is_connected() {
+        // Arrange
+        let mut calls = 0;
+        let get_outputs = || {
+            calls += 1;
+            Ok::<_, SwitchError>(laptop_with_hdmi_screen(calls >= 3))
+        };
+
+        // Act
+        let connected = wait_for_output(get_outputs, "HDMI-1", DELAY, Duration::ZERO);
+
+        // Assert
+        assert_eq!(connected, Ok(true));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn wait_for_output_gives_up_after_timeout() {
+        // Arrange
+        let mut calls = 0;
+        let get_outputs = || {
+            calls += 1;
+            Ok::<_, SwitchError>(laptop_with_hdmi_screen(false))
+        };
+
+        // Act
+        let connected = wait_for_output(
+            get_outputs,
+            "HDMI-1",
+            Duration::from_millis(30),
+            Duration::from_millis(10),
+        );
+
+        // Assert
+        assert_eq!(connected, Ok(false));
+        assert!(calls >= 2);
+    }
+
+    #[test]
+    fn reconnect_backoff_doubles_up_to_the_maximum_and_gives_up() {
+        // Arrange
+        let mut reconnect_backoff = ReconnectBackoff::new(Duration::from_secs(1));
+
+        // Act
+        let delays: Vec<_> = std::iter::from_fn(|| reconnect_backoff.next_delay()).collect();
+
+        // Assert
+        let seconds = |delays: &[u64]| {
+            delays
+                .iter()
+                .map(|&s| Duration::from_secs(s))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(delays, seconds(&[1, 2, 4, 8, 16, 30, 30, 30, 30]));
+        assert_eq!(delays.len() as u32, MAX_RECONNECT_ATTEMPTS - 1);
+    }
+
+    #[test]
+    fn reconnect_backoff_starts_over_after_success() {
+        // Arrange
+        let mut reconnect_backoff = ReconnectBackoff::new(Duration::from_millis(200));
+        reconnect_backoff.next_delay();
+        reconnect_backoff.next_delay();
+
+        // Act
+        reconnect_backoff.reset();
+        let delay = reconnect_backoff.next_delay();
+
+        // Assert
+        assert_eq!(delay, Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn poll_keeps_the_connected_controller() {
+        // Arrange
+        let screen = laptop_with_hdmi_screen(true);
+        let mut screen_controller = Some(ScreenController::fake(screen.clone(), false));
+        let controller_type = ScreenControllerType::value_variants()[0];
+
+        // Act
+        let first = poll(&mut screen_controller, controller_type);
+        let second = poll(&mut screen_controller, controller_type);
+
+        // Assert
+        assert_eq!(first, Ok(screen.clone()));
+        assert_eq!(second, Ok(screen));
+        assert!(screen_controller.is_some());
+    }
+
+    #[test]
+    fn sleep_returns_early_when_terminating() {
+        // Arrange
+        let signal_flags = SignalFlags {
+            terminate: Arc::new(AtomicBool::new(true)),
+            reload: Arc::new(AtomicBool::new(false)),
+        };
+        let start = std::time::Instant::now();
+
+        // Act
+        signal_flags.sleep(Duration::from_secs(60));
+
+        // Assert
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+#[cfg(feature = "xrandr")]
+const XRANDR_LAPTOP_ONLY: &str = "\
+Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 16384 x 16384
+eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 194mm
+   1920x1080     60.02*+  59.97
+   1280x720      60.00
+HDMI-1 disconnected (normal left inverted right x axis y axis)
+";
+
+/// Like [`XRANDR_LAPTOP_ONLY`], with modes padded like xrandr does and a disabled HDMI-1 connected.
+#[cfg(feature = "xrandr")]
+const XRANDR_LAPTOP_AND_HDMI: &str = "\
+Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 16384 x 16384
+eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 194mm
+   1920x1080     60.02*+  59.97  
+   1280x720      60.00  
+HDMI-1 connected (normal left inverted right x axis y axis)
+   1920x1080     60.00 +
+";
+
+/// Creates an empty directory for the test, to be put into `PATH`.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("switch-display-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("unable to create test directory");
+    dir
+}
+
+/// Puts an `xrandr` script printing the given query output into the directory.
+#[cfg(feature = "xrandr")]
+fn fake_xrandr(dir: &std::path::Path, query_output: &str) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("xrandr");
+    std::fs::write(
+        &path,
+        format!("#!/bin/sh\ncat <<'EOF'\n{query_output}EOF\n"),
+    )
+    .expect("unable to write fake xrandr");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .expect("unable to make fake xrandr executable");
+}
+
+fn switch_display(path: &std::path::Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_switch-display"));
+    command
+        .env_clear()
+        .env("PATH", format!("{}:/usr/bin:/bin", path.display()));
+    command
+}
+
+#[test]
+#[cfg(feature = "xrandr")]
+fn exits_with_2_when_outputs_are_already_switched() {
+    // Arrange
+    let dir = test_dir("no-change");
+    fake_xrandr(&dir, XRANDR_LAPTOP_ONLY);
+
+    // Act
+    let status = switch_display(&dir)
+        .args(["--controller", "xrandr", "--quiet"])
+        .status()
+        .expect("unable to run switch-display");
+
+    // Assert
+    assert_eq!(status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).expect("unable to remove test directory");
+}
+
+#[test]
+#[cfg(feature = "xrandr")]
+fn exits_with_5_when_controller_is_unavailable() {
+    // Arrange
+    let dir = test_dir("no-controller");
+
+    // Act
+    let output = switch_display(&dir)
+        .env("PATH", &dir)
+        .args(["--controller", "xrandr"])
+        .output()
+        .expect("unable to run switch-display");
+
+    // Assert
+    assert_eq!(output.status.code(), Some(5));
+    std::fs::remove_dir_all(&dir).expect("unable to remove test directory");
+}
+
+#[test]
+#[cfg(feature = "xrandr")]
+fn exits_with_4_without_panicking_when_controller_command_fails() {
+    // Arrange
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("controller-failed");
+    let path = dir.join("xrandr");
+    std::fs::write(
+        &path,
+        format!(
+            "#!/bin/sh\nif [ $# -ne 0 ]; then echo 'X Error of failed request' >&2; exit 1; fi\ncat <<'EOF'\n{XRANDR_LAPTOP_AND_HDMI}EOF\n"
+        ),
+    )
+    .expect("unable to write fake xrandr");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .expect("unable to make fake xrandr executable");
+
+    // Act
+    let output = switch_display(&dir)
+        .args(["--controller", "xrandr", "--quiet"])
+        .output()
+        .expect("unable to run switch-display");
+
+    // Assert
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(4), "stderr: {stderr}");
+    assert!(!stderr.contains("panicked"), "stderr: {stderr}");
+    std::fs::remove_dir_all(&dir).expect("unable to remove test directory");
+}
+
+#[test]
+fn exits_with_3_on_invalid_arguments() {
+    // Arrange
+    let dir = test_dir("invalid-arguments");
+
+    // Act
+    let output = switch_display(&dir)
+        .args(["--controller", "no-such-controller"])
+        .output()
+        .expect("unable to run switch-display");
+
+    // Assert
+    assert_eq!(output.status.code(), Some(3));
+    std::fs::remove_dir_all(&dir).expect("unable to remove test directory");
+}
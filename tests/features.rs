@@ -0,0 +1,56 @@
+//! Checks the crate with each controller feature alone and with none of them, which building
+//! with the default features does not cover.
+
+use std::process::{Command, Output};
+
+/// Runs `cargo check` on all targets with only the given features, in a target directory
+/// of its own so that it does not wait for the build running the tests.
+fn cargo_check(features: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .args(["check", "--quiet", "--offline", "--all-targets"])
+        .args([
+            "--manifest-path",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"),
+        ])
+        .arg("--no-default-features")
+        .env(
+            "CARGO_TARGET_DIR",
+            concat!(env!("CARGO_TARGET_TMPDIR"), "/features"),
+        );
+    if !features.is_empty() {
+        command.args(["--features", &features.join(",")]);
+    }
+    command.output().expect("unable to run cargo")
+}
+
+#[test]
+fn build_without_controller_fails_with_only_the_compile_error() {
+    // Act
+    let output = cargo_check(&[]);
+
+    // Assert
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(
+            r#"error: no screen controller is compiled in: enable at least one of the "xrandr", "sway" or "randr" features"#
+        ),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("error["), "{stderr}");
+    assert!(!stderr.contains("warning"), "{stderr}");
+}
+
+#[test]
+fn build_with_each_controller_alone_succeeds() {
+    for feature in ["xrandr", "sway", "randr"] {
+        // Act
+        let output = cargo_check(&[feature]);
+
+        // Assert
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{feature}: {stderr}");
+        assert!(!stderr.contains("warning"), "{feature}: {stderr}");
+    }
+}